@@ -14,21 +14,55 @@
  * limitations under the License.
  */
 
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::BTreeMap, future::Future, time::Duration};
+use tokio::sync::{broadcast, mpsc, oneshot};
 
-use serde::{Deserialize, Serialize, Serializer};
-use tokio::sync::{mpsc, oneshot};
-use warp::Filter;
+use opentelemetry::trace::TraceContextExt;
+use tracing::{error, field, info, info_span, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use warp::{http::StatusCode, Filter, Reply};
 
 use crate::{
+    config::Config,
     context::{AppQuery, AppQueryKind, AppResponse, Error},
+    events::{event_stream, ChangeEvent},
+    limits, metrics,
     objects::{List, TaskState},
-    util::{EntityUid, ListUid, UserOrTeamUid, UserUid},
+    openapi::ApiDoc,
+    telemetry,
+    tls::{self, ClientIdentity},
+    util::{EntityUid, ListUid, TeamUid, UserOrTeamUid, UserUid},
+    web,
 };
 
-type AppChannel = mpsc::Sender<AppQuery>;
+pub mod v2;
 
-#[derive(Debug, Clone, Deserialize)]
+/// The application actor's channel, as seen by one request.
+/// Requests on a connection whose client certificate maps to a user may only act as that user.
+#[derive(Debug, Clone)]
+pub struct AppChannel {
+    chan: mpsc::Sender<AppQuery>,
+    client: Option<UserUid>,
+    /// The trace context from the request's `traceparent` header, if any
+    trace: opentelemetry::Context,
+}
+
+impl AppChannel {
+    fn check_client(&self, kind: &AppQueryKind) -> Result<(), Error> {
+        match (&self.client, kind.principal()) {
+            (Some(client), Some(principal)) if client != principal => Err(Error::ClientMismatch(
+                client.clone().into(),
+                principal.clone().into(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct GetList {
     pub uid: UserUid,
     pub list: ListUid,
@@ -40,7 +74,7 @@ impl From<GetList> for AppQueryKind {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct CreateList {
     pub uid: UserUid,
     pub name: String,
@@ -52,7 +86,7 @@ impl From<CreateList> for AppQueryKind {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct UpdateList {
     pub uid: UserUid,
     pub list: ListUid,
@@ -65,12 +99,44 @@ impl From<UpdateList> for AppQueryKind {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct TransferOwnership {
+    pub uid: UserUid,
+    pub list: ListUid,
+    /// The list's new owner
+    pub owner: UserUid,
+}
+
+impl From<TransferOwnership> for AppQueryKind {
+    fn from(v: TransferOwnership) -> AppQueryKind {
+        AppQueryKind::TransferOwnership(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct DuplicateList {
+    pub uid: UserUid,
+    pub list: ListUid,
+    /// The name of the copy; it has the original's name if unset
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl From<DuplicateList> for AppQueryKind {
+    fn from(v: DuplicateList) -> AppQueryKind {
+        AppQueryKind::DuplicateList(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct AddShare {
     pub uid: UserUid,
     pub list: ListUid,
     pub share_with: UserOrTeamUid,
     pub role: ShareRole,
+    /// When the share ends, in seconds since the Unix epoch; it lasts until removed if unset
+    #[serde(default)]
+    pub expires: Option<i64>,
 }
 
 impl From<AddShare> for AppQueryKind {
@@ -79,13 +145,37 @@ impl From<AddShare> for AppQueryKind {
     }
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
-pub enum ShareRole {
-    Reader,
-    Editor,
+/// The role a list is shared in: `Reader`, `Editor`, or one of the roles defined in the
+/// `[roles]` section of the config file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize, ToSchema)]
+#[serde(transparent)]
+pub struct ShareRole(String);
+
+impl ShareRole {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    pub fn reader() -> Self {
+        Self::new("Reader")
+    }
+
+    pub fn editor() -> Self {
+        Self::new("Editor")
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for ShareRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct DeleteShare {
     pub uid: UserUid,
     pub list: ListUid,
@@ -99,7 +189,7 @@ impl From<DeleteShare> for AppQueryKind {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct DeleteList {
     pub uid: UserUid,
     pub list: ListUid,
@@ -111,7 +201,8 @@ impl From<DeleteList> for AppQueryKind {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct GetLists {
     pub uid: UserUid,
 }
@@ -122,13 +213,136 @@ impl From<GetLists> for AppQueryKind {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetListAccess {
+    pub uid: UserUid,
+    pub list: ListUid,
+    pub action: EntityUid,
+}
+
+impl From<GetListAccess> for AppQueryKind {
+    fn from(v: GetListAccess) -> AppQueryKind {
+        AppQueryKind::GetListAccess(v)
+    }
+}
+
+/// The users and teams that would be allowed to perform an action on a list
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ListAccess {
+    pub users: Vec<UserUid>,
+    pub teams: Vec<TeamUid>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetCapabilities {
+    pub uid: UserUid,
+}
+
+impl From<GetCapabilities> for AppQueryKind {
+    fn from(v: GetCapabilities) -> AppQueryKind {
+        AppQueryKind::GetCapabilities(v)
+    }
+}
+
+/// What a user may do, from which a client can work out their permissions on any resource
+/// without asking the server
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Capabilities {
+    /// The teams the user is in, directly or through other teams, for checking constraints
+    /// such as `User::"kesha" in resource.readers`
+    pub teams: Vec<TeamUid>,
+    /// The user's capabilities for each action, keyed by its name (e.g., `GetList`)
+    pub actions: BTreeMap<String, ActionCapability>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ActionCapability {
+    /// The types of resource the action applies to
+    pub resource_types: Vec<String>,
+    /// Whether the action is allowed, when that does not depend on the resource
+    pub allowed: Option<bool>,
+    /// The policies that may apply, in Cedar's JSON policy format. The user, the time and
+    /// the entities are substituted in, so what is left of each policy's conditions only
+    /// constrains the resource (e.g., its `owner`, `readers` or `editors`) and, for actions
+    /// on comments and attachments, `context.author`.
+    #[schema(value_type = Vec<Object>)]
+    pub residuals: Vec<serde_json::Value>,
+}
+
+/// Ask whether a user may perform each of a batch of actions, e.g., to enable the controls
+/// of a user interface
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct Authorize {
+    pub uid: UserUid,
+    pub requests: Vec<AuthorizationRequest>,
+}
+
+impl From<Authorize> for AppQueryKind {
+    fn from(v: Authorize) -> AppQueryKind {
+        AppQueryKind::Authorize(v)
+    }
+}
+
+/// An action on the application, a list, or a task, authorized as the server would
+/// authorize it when performed
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct AuthorizationRequest {
+    pub action: EntityUid,
+    /// The list acted on; the action is on the application (e.g., `CreateList`) if unset
+    #[serde(default)]
+    pub list: Option<ListUid>,
+    /// The task of `list` acted on, which is the resource when tasks are entities
+    #[serde(default)]
+    pub task: Option<i64>,
+    /// The author of the comment or attachment acted on, for the actions whose context
+    /// holds one (e.g., `EditComment`)
+    #[serde(default)]
+    pub author: Option<UserUid>,
+}
+
+/// Whether each request of a batch is allowed, in the order of the requests
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Decisions {
+    pub allowed: Vec<bool>,
+}
+
+/// The state of the application server, reported by `/readyz`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Readiness {
+    /// The number of policies in effect, including template-linked policies
+    pub policies: usize,
+    pub templates: usize,
+    /// Why the policies file could not be reloaded, if the last attempt failed.
+    /// The previously loaded policies stay in effect until the file is fixed.
+    pub policy_reload_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Subscribe {
+    pub uid: UserUid,
+    pub list: ListUid,
+}
+
+impl From<Subscribe> for AppQueryKind {
+    fn from(v: Subscribe) -> AppQueryKind {
+        AppQueryKind::Subscribe(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct UpdateTask {
     pub uid: UserUid,
     pub list: ListUid,
     pub task: i64,
     pub name: Option<String>,
     pub state: Option<TaskState>,
+    /// The user to assign the task to, or `null` to unassign it
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<UserUid>)]
+    pub assignee: Option<Option<UserUid>>,
 }
 
 impl From<UpdateTask> for AppQueryKind {
@@ -137,11 +351,14 @@ impl From<UpdateTask> for AppQueryKind {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct CreateTask {
     pub uid: UserUid,
     pub list: ListUid,
     pub name: String,
+    /// The user the task is assigned to, if any
+    #[serde(default)]
+    pub assignee: Option<UserUid>,
 }
 
 impl From<CreateTask> for AppQueryKind {
@@ -150,7 +367,7 @@ impl From<CreateTask> for AppQueryKind {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 pub struct DeleteTask {
     pub uid: UserUid,
     pub list: ListUid,
@@ -163,19 +380,108 @@ impl From<DeleteTask> for AppQueryKind {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct CommentOnTask {
+    pub uid: UserUid,
+    pub list: ListUid,
+    pub task: i64,
+    pub body: String,
+}
+
+impl From<CommentOnTask> for AppQueryKind {
+    fn from(v: CommentOnTask) -> AppQueryKind {
+        AppQueryKind::CommentOnTask(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct EditComment {
+    pub uid: UserUid,
+    pub list: ListUid,
+    pub task: i64,
+    pub comment: i64,
+    pub body: String,
+}
+
+impl From<EditComment> for AppQueryKind {
+    fn from(v: EditComment) -> AppQueryKind {
+        AppQueryKind::EditComment(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct DeleteComment {
+    pub uid: UserUid,
+    pub list: ListUid,
+    pub task: i64,
+    pub comment: i64,
+}
+
+impl From<DeleteComment> for AppQueryKind {
+    fn from(v: DeleteComment) -> AppQueryKind {
+        AppQueryKind::DeleteComment(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct AttachToTask {
+    pub uid: UserUid,
+    pub list: ListUid,
+    pub task: i64,
+    /// The file name
+    pub name: String,
+    pub content_type: String,
+    /// The file's contents, base64 encoded
+    pub data: String,
+}
+
+impl From<AttachToTask> for AppQueryKind {
+    fn from(v: AttachToTask) -> AppQueryKind {
+        AppQueryKind::AttachToTask(v)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct DeleteAttachment {
+    pub uid: UserUid,
+    pub list: ListUid,
+    pub task: i64,
+    pub attachment: i64,
+}
+
+impl From<DeleteAttachment> for AppQueryKind {
+    fn from(v: DeleteAttachment) -> AppQueryKind {
+        AppQueryKind::DeleteAttachment(v)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Empty {
-    message: &'static str,
+    message: String,
 }
 
 impl Default for Empty {
     fn default() -> Self {
-        Self { message: "ok" }
+        Self {
+            message: "ok".to_string(),
+        }
     }
 }
 
-pub async fn serve_api(chan: AppChannel, port: u16) {
-    let filter = warp::path("api").and(
+/// How long `/healthz` and `/readyz` wait for the application server to answer
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serve the API until `shutdown` completes, then stop accepting connections and
+/// wait for in-flight requests to finish
+pub async fn serve_api(
+    chan: mpsc::Sender<AppQuery>,
+    config: &Config,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) {
+    let api_v2 = warp::path("api")
+        .and(warp::path("v2"))
+        .and(v2::routes(chan.clone(), config.limits.max_attachment_bytes));
+    let api_v1 = warp::path("api").and(
         // List CRUD
         (warp::path("list").and(
             (warp::path("get")
@@ -197,7 +503,27 @@ pub async fn serve_api(chan: AppChannel, port: u16) {
                 .and(warp::delete())
                 .and(with_app(chan.clone()))
                 .and(warp::body::json())
-                .and_then(simple_query::<DeleteList, Empty>)),
+                .and_then(simple_query::<DeleteList, Empty>))
+            .or(warp::path("transfer")
+                .and(warp::post())
+                .and(with_app(chan.clone()))
+                .and(warp::body::json())
+                .and_then(simple_query::<TransferOwnership, Empty>))
+            .or(warp::path("duplicate")
+                .and(warp::post())
+                .and(with_app(chan.clone()))
+                .and(warp::body::json())
+                .and_then(simple_query::<DuplicateList, EntityUid>))
+            .or(warp::path("access")
+                .and(warp::get())
+                .and(with_app(chan.clone()))
+                .and(warp::query::query::<GetListAccess>())
+                .and_then(simple_query::<GetListAccess, ListAccess>))
+            .or(warp::path("events")
+                .and(warp::get())
+                .and(with_app(chan.clone()))
+                .and(warp::query::query::<Subscribe>())
+                .and_then(subscribe)),
         ))
         .or(
             // Task CRUD
@@ -219,11 +545,57 @@ pub async fn serve_api(chan: AppChannel, port: u16) {
                     .and_then(simple_query::<DeleteTask, Empty>)),
             ),
         )
+        .or(
+            // Comments on tasks
+            warp::path("comment").and(
+                (warp::path("create")
+                    .and(warp::post())
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<CommentOnTask, i64>))
+                .or(warp::path("update")
+                    .and(warp::post())
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<EditComment, Empty>))
+                .or(warp::path("delete")
+                    .and(warp::delete())
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<DeleteComment, Empty>)),
+            ),
+        )
+        .or(
+            // Attachments to tasks
+            warp::path("attachment").and(
+                (warp::path("create")
+                    .and(warp::post())
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<AttachToTask, i64>))
+                .or(warp::path("delete")
+                    .and(warp::delete())
+                    .and(with_app(chan.clone()))
+                    .and(warp::body::json())
+                    .and_then(simple_query::<DeleteAttachment, Empty>)),
+            ),
+        )
         .or(warp::path("lists")
             .and(warp::path("get"))
+            .and(warp::get())
             .and(with_app(chan.clone()))
             .and(warp::query::query::<GetLists>())
             .and_then(simple_query::<GetLists, Vec<List>>))
+        .or(warp::path("capabilities")
+            .and(warp::get())
+            .and(with_app(chan.clone()))
+            .and(warp::query::query::<GetCapabilities>())
+            .and_then(simple_query::<GetCapabilities, Capabilities>))
+        .or(warp::path("authorize")
+            .and(warp::post())
+            .and(with_app(chan.clone()))
+            .and(warp::body::json())
+            .and_then(simple_query::<Authorize, Decisions>))
         .or(warp::path("share").and(
             (warp::post()
                 .and(with_app(chan.clone()))
@@ -233,26 +605,88 @@ pub async fn serve_api(chan: AppChannel, port: u16) {
                 .and(with_app(chan.clone()))
                 .and(warp::body::json())
                 .and_then(simple_query::<DeleteShare, Empty>)),
-        )),
+        ))
+        .or(warp::path("openapi.json")
+            .and(warp::get())
+            .map(|| warp::reply::json(&ApiDoc::openapi()))),
     );
 
-    let s = warp::serve(filter);
-    let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
-    s.run(socket).await
+    // Probes and metrics are not rate limited
+    let probes = warp::get().and(
+        (warp::path!("healthz")
+            .and(with_app(chan.clone()))
+            .and_then(healthz))
+        .or(warp::path!("readyz")
+            .and(with_app(chan.clone()))
+            .and_then(readyz))
+        .or(warp::path!("metrics").map(metrics::metrics)),
+    );
+
+    let routes = probes
+        .or(limits::per_ip(config.limits.per_ip)
+            .and(api_v2.or(api_v1).or(web::routes()))
+            .recover(limits::handle_rejection))
+        .with(warp::log::custom(metrics::record_request));
+
+    let app = AppChannel {
+        chan,
+        client: None,
+        trace: opentelemetry::Context::new(),
+    };
+    let shutdown = async move {
+        shutdown.await;
+        info!("Shutting down, waiting for in-flight requests");
+        // End event streams, which would otherwise keep their connections open
+        if let Err(e) = simple_query_inner::<Empty>(app, AppQueryKind::CloseSubscriptions).await {
+            error!("Error closing subscriptions: {e}");
+        }
+    };
+    match &config.tls {
+        Some(tls) => {
+            if let Err(e) = tls::serve(warp::service(routes), config.bind, tls, shutdown).await {
+                error!("Error serving HTTPS: {e}");
+            }
+        }
+        None => match warp::serve(routes).try_bind_with_graceful_shutdown(config.bind, shutdown) {
+            Ok((addr, server)) => {
+                info!("Serving HTTP on {addr}");
+                server.await
+            }
+            Err(e) => error!("Error serving HTTP: {e}"),
+        },
+    }
 }
 
 pub fn with_app(
-    chan: AppChannel,
+    chan: mpsc::Sender<AppQuery>,
 ) -> impl Filter<Extract = (AppChannel,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || chan.clone())
+    warp::ext::optional::<ClientIdentity>()
+        .and(warp::header::headers_cloned())
+        .map(
+            move |identity: Option<ClientIdentity>, headers: warp::http::HeaderMap| AppChannel {
+                chan: chan.clone(),
+                client: identity.and_then(|i| i.user),
+                trace: telemetry::extract_context(&headers),
+            },
+        )
 }
 
-#[derive(Serialize)]
-struct ErrorMsg {
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ErrorMsg {
     #[serde(serialize_with = "serialize_error")]
+    #[schema(value_type = String)]
     error: Error,
 }
 
+/// Deserialize a field that may be absent (`None`), `null` (`Some(None)`) or set
+pub fn nullable<'de, D, T>(d: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(d).map(Some)
+}
+
 fn serialize_error<S>(e: &Error, s: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -260,6 +694,41 @@ where
     s.serialize_str(&format!("{}", e))
 }
 
+/// The HTTP status reported for an error by the v2 API.
+/// The v1 API always responds with 200 and reports the error in the body.
+fn status_code(e: &Error) -> StatusCode {
+    match e {
+        Error::AuthDenied(_) => StatusCode::FORBIDDEN,
+        Error::NoSuchEntity(_)
+        | Error::InvalidTaskId(_, _)
+        | Error::InvalidCommentId(_, _, _)
+        | Error::InvalidAttachmentId(_, _, _) => StatusCode::NOT_FOUND,
+        Error::InvalidAttachment(_) => StatusCode::BAD_REQUEST,
+        Error::AttachmentTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        Error::Request(_)
+        | Error::ShareExpired(_)
+        | Error::UnknownRole(_)
+        | Error::BatchTooLarge(_) => StatusCode::BAD_REQUEST,
+        Error::ClientMismatch(_, _) => StatusCode::FORBIDDEN,
+        Error::RateLimited(_, _) => StatusCode::TOO_MANY_REQUESTS,
+        Error::ListQuotaExceeded(_, _)
+        | Error::TaskQuotaExceeded(_, _)
+        | Error::AttachmentQuotaExceeded(_, _, _) => StatusCode::FORBIDDEN,
+        Error::EntityDecode(_)
+        | Error::Entities(_)
+        | Error::TokioSend(_)
+        | Error::TokioRecv(_)
+        | Error::Type
+        | Error::IO(_)
+        | Error::Policy(_)
+        | Error::PolicySet(_)
+        | Error::Lint(_)
+        | Error::Residual(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        #[cfg(feature = "use-templates")]
+        Error::Share(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 fn respond(msg: Result<impl Serialize, Error>) -> impl warp::Reply {
     match msg {
         Ok(msg) => Ok(serde_json::to_string(&msg).unwrap()),
@@ -267,10 +736,7 @@ fn respond(msg: Result<impl Serialize, Error>) -> impl warp::Reply {
     }
 }
 
-pub async fn simple_query<I, R>(
-    app: mpsc::Sender<AppQuery>,
-    q: I,
-) -> Result<impl warp::Reply, warp::Rejection>
+pub async fn simple_query<I, R>(app: AppChannel, q: I) -> Result<impl warp::Reply, warp::Rejection>
 where
     I: Into<AppQueryKind>,
     AppResponse: TryInto<R, Error = Error>,
@@ -280,19 +746,80 @@ where
     Ok(respond(result))
 }
 
-pub async fn simple_query_inner<R>(
-    app: mpsc::Sender<AppQuery>,
-    q: impl Into<AppQueryKind>,
-) -> Result<R, Error>
+pub async fn simple_query_inner<R>(app: AppChannel, q: impl Into<AppQueryKind>) -> Result<R, Error>
 where
     AppResponse: TryInto<R, Error = Error>,
-    R: Serialize,
 {
     let (send, recv) = oneshot::channel();
     let kind = q.into();
-    let q = AppQuery::new(kind, send);
-    app.send(q).await?;
-    let resp = recv.await??;
-    let resp = resp.try_into()?;
-    Ok(resp)
+    app.check_client(&kind)?;
+    let span = info_span!("query", principal = field::Empty, action = field::Empty);
+    if let Some((principal, action)) = kind.principal_action() {
+        span.record("principal", field::display(principal.as_ref()));
+        span.record("action", field::display(action));
+    }
+    // Continue the caller's trace, if the request had a `traceparent` header
+    if app.trace.has_active_span() {
+        let _ = span.set_parent(app.trace.clone());
+    }
+    async move {
+        let q = AppQuery::new(kind, send);
+        app.chan.send(q).await?;
+        let resp = recv.await??;
+        let resp = resp.try_into()?;
+        Ok(resp)
+    }
+    .instrument(span)
+    .await
+}
+
+// Liveness: the application server is answering queries
+async fn healthz(app: AppChannel) -> Result<warp::reply::Response, warp::Rejection> {
+    let readiness = simple_query_inner::<Readiness>(app, AppQueryKind::Readiness);
+    let (status, body) = match tokio::time::timeout(PROBE_TIMEOUT, readiness).await {
+        Ok(Ok(_)) => (StatusCode::OK, "ok"),
+        _ => (StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+    };
+    let body = serde_json::json!({ "status": body });
+    Ok(warp::reply::with_status(warp::reply::json(&body), status).into_response())
+}
+
+// Readiness: the schema and the current policies file are loaded and validated
+async fn readyz(app: AppChannel) -> Result<warp::reply::Response, warp::Rejection> {
+    let readiness = simple_query_inner::<Readiness>(app, AppQueryKind::Readiness);
+    let reply = match tokio::time::timeout(PROBE_TIMEOUT, readiness).await {
+        Ok(Ok(r)) => {
+            let status = match r.policy_reload_error {
+                None => StatusCode::OK,
+                Some(_) => StatusCode::SERVICE_UNAVAILABLE,
+            };
+            warp::reply::with_status(warp::reply::json(&r), status)
+        }
+        Ok(Err(error)) => warp::reply::with_status(
+            warp::reply::json(&ErrorMsg { error }),
+            StatusCode::SERVICE_UNAVAILABLE,
+        ),
+        Err(_) => {
+            let body =
+                serde_json::json!({ "error": "Timed out waiting for the application server" });
+            warp::reply::with_status(warp::reply::json(&body), StatusCode::SERVICE_UNAVAILABLE)
+        }
+    };
+    Ok(reply.into_response())
+}
+
+/// Subscribe to change events on a list, as a stream of server-sent events.
+/// Errors (e.g., the user may not read the list) are reported as for `simple_query`.
+pub async fn subscribe(
+    app: AppChannel,
+    q: Subscribe,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let recv = simple_query_inner::<broadcast::Receiver<ChangeEvent>>(app.clone(), q.clone()).await;
+    match recv {
+        Ok(recv) => {
+            let stream = event_stream(app, q.uid, q.list, recv);
+            Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream)).into_response())
+        }
+        Err(e) => Ok(respond(Err::<Empty, _>(e)).into_response()),
+    }
 }
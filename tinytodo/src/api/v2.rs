@@ -0,0 +1,733 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Version 2 of the TinyTodo HTTP API, served under `/api/v2`.
+//!
+//! Routes are resource oriented (`/lists/{id}/tasks/{task}`), use the HTTP method to select
+//! the operation, and report errors with a matching status code. The calling user is given
+//! by the `x-tinytodo-user` header instead of a `uid` field in every request, or, over
+//! mutual TLS, by the client certificate.
+
+use std::convert::Infallible;
+
+use cedar_policy::EntityId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+use utoipa::{IntoParams, ToSchema};
+use warp::{
+    http::{header::LOCATION, StatusCode},
+    reply::Response,
+    Filter, Rejection, Reply,
+};
+
+use super::{
+    nullable, simple_query_inner, status_code, with_app, AddShare, AppChannel, AttachToTask,
+    AuthorizationRequest, Authorize, Capabilities, CommentOnTask, CreateList, CreateTask,
+    Decisions, DeleteAttachment, DeleteComment, DeleteList, DeleteShare, DeleteTask, DuplicateList,
+    EditComment, Empty, ErrorMsg, GetCapabilities, GetList, GetListAccess, GetLists, ListAccess,
+    ShareRole, Subscribe, TransferOwnership, UpdateList, UpdateTask,
+};
+use crate::{
+    context::{AppQuery, Error},
+    events::{event_stream, ChangeEvent},
+    objects::{List, TaskState},
+    tls::ClientIdentity,
+    util::{EntityUid, ListUid, UserOrTeamUid, UserUid, TYPE_LIST},
+};
+
+/// Header naming the user making a v2 request, e.g. `User::"kesha"`
+pub const USER_HEADER: &str = "x-tinytodo-user";
+
+/// Room in an attachment's request body for everything but its data
+const ATTACHMENT_FIELDS_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct NewList {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct ListPatch {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct NewOwner {
+    pub owner: UserUid,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct ListCopy {
+    /// The name of the copy; it has the original's name if unset
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct NewTask {
+    pub name: String,
+    /// The user the task is assigned to, if any
+    #[serde(default)]
+    pub assignee: Option<UserUid>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct TaskPatch {
+    pub name: Option<String>,
+    pub state: Option<TaskState>,
+    /// The user to assign the task to, or `null` to unassign it
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<UserUid>)]
+    pub assignee: Option<Option<UserUid>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct NewComment {
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct CommentPatch {
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct NewAttachment {
+    /// The file name
+    pub name: String,
+    pub content_type: String,
+    /// The file's contents, base64 encoded
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct NewShare {
+    pub share_with: UserOrTeamUid,
+    pub role: ShareRole,
+    /// When the share ends, in seconds since the Unix epoch; it lasts until removed if unset
+    #[serde(default)]
+    pub expires: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct AuthorizationBatch {
+    pub requests: Vec<AuthorizationRequest>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ShareQuery {
+    pub target: UserOrTeamUid,
+    pub role: ShareRole,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AccessQuery {
+    pub action: EntityUid,
+}
+
+#[derive(Debug)]
+struct InvalidPrincipal(String);
+
+impl warp::reject::Reject for InvalidPrincipal {}
+
+#[derive(Debug)]
+struct MissingPrincipal;
+
+impl warp::reject::Reject for MissingPrincipal {}
+
+pub fn routes(
+    chan: mpsc::Sender<AppQuery>,
+    max_attachment_bytes: Option<usize>,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone {
+    let lists = warp::path!("lists")
+        .and(warp::get())
+        .and(principal())
+        .and(with_app(chan.clone()))
+        .and_then(get_lists)
+        .or(warp::path!("lists")
+            .and(warp::post())
+            .and(principal())
+            .and(with_app(chan.clone()))
+            .and(warp::body::json())
+            .and_then(create_list))
+        .unify()
+        .or(warp::path!("capabilities")
+            .and(warp::get())
+            .and(principal())
+            .and(with_app(chan.clone()))
+            .and_then(get_capabilities))
+        .unify()
+        .or(warp::path!("authorize")
+            .and(warp::post())
+            .and(principal())
+            .and(with_app(chan.clone()))
+            .and(warp::body::json())
+            .and_then(authorize))
+        .unify();
+
+    let list = warp::path!("lists" / String)
+        .and(warp::get())
+        .and(principal())
+        .and(with_app(chan.clone()))
+        .and_then(get_list)
+        .or(warp::path!("lists" / String)
+            .and(warp::patch())
+            .and(principal())
+            .and(with_app(chan.clone()))
+            .and(warp::body::json())
+            .and_then(update_list))
+        .unify()
+        .or(warp::path!("lists" / String)
+            .and(warp::delete())
+            .and(principal())
+            .and(with_app(chan.clone()))
+            .and_then(delete_list))
+        .unify()
+        .or(warp::path!("lists" / String / "access")
+            .and(warp::get())
+            .and(principal())
+            .and(with_app(chan.clone()))
+            .and(warp::query::<AccessQuery>())
+            .and_then(get_list_access))
+        .unify()
+        .or(warp::path!("lists" / String / "events")
+            .and(warp::get())
+            .and(principal())
+            .and(with_app(chan.clone()))
+            .and_then(subscribe))
+        .unify()
+        .or(warp::path!("lists" / String / "owner")
+            .and(warp::put())
+            .and(principal())
+            .and(with_app(chan.clone()))
+            .and(warp::body::json())
+            .and_then(transfer_ownership))
+        .unify()
+        .or(warp::path!("lists" / String / "duplicate")
+            .and(warp::post())
+            .and(principal())
+            .and(with_app(chan.clone()))
+            .and(warp::body::json())
+            .and_then(duplicate_list))
+        .unify();
+
+    let tasks = warp::path!("lists" / String / "tasks")
+        .and(warp::post())
+        .and(principal())
+        .and(with_app(chan.clone()))
+        .and(warp::body::json())
+        .and_then(create_task)
+        .or(warp::path!("lists" / String / "tasks" / i64)
+            .and(warp::patch())
+            .and(principal())
+            .and(with_app(chan.clone()))
+            .and(warp::body::json())
+            .and_then(update_task))
+        .unify()
+        .or(warp::path!("lists" / String / "tasks" / i64)
+            .and(warp::delete())
+            .and(principal())
+            .and(with_app(chan.clone()))
+            .and_then(delete_task))
+        .unify();
+
+    let comments = warp::path!("lists" / String / "tasks" / i64 / "comments")
+        .and(warp::post())
+        .and(principal())
+        .and(with_app(chan.clone()))
+        .and(warp::body::json())
+        .and_then(comment_on_task)
+        .or(
+            warp::path!("lists" / String / "tasks" / i64 / "comments" / i64)
+                .and(warp::patch())
+                .and(principal())
+                .and(with_app(chan.clone()))
+                .and(warp::body::json())
+                .and_then(edit_comment),
+        )
+        .unify()
+        .or(
+            warp::path!("lists" / String / "tasks" / i64 / "comments" / i64)
+                .and(warp::delete())
+                .and(principal())
+                .and(with_app(chan.clone()))
+                .and_then(delete_comment),
+        )
+        .unify();
+
+    let attachments = warp::path!("lists" / String / "tasks" / i64 / "attachments")
+        .and(warp::post())
+        .and(principal())
+        .and(with_app(chan.clone()))
+        .and(warp::body::content_length_limit(attachment_body_limit(
+            max_attachment_bytes,
+        )))
+        .and(warp::body::json())
+        .and_then(attach_to_task)
+        .or(
+            warp::path!("lists" / String / "tasks" / i64 / "attachments" / i64)
+                .and(warp::delete())
+                .and(principal())
+                .and(with_app(chan.clone()))
+                .and_then(delete_attachment),
+        )
+        .unify();
+
+    let shares = warp::path!("lists" / String / "shares")
+        .and(warp::post())
+        .and(principal())
+        .and(with_app(chan.clone()))
+        .and(warp::body::json())
+        .and_then(add_share)
+        .or(warp::path!("lists" / String / "shares")
+            .and(warp::delete())
+            .and(principal())
+            .and(with_app(chan))
+            .and(warp::query::<ShareQuery>())
+            .and_then(delete_share))
+        .unify();
+
+    lists
+        .or(list)
+        .unify()
+        .or(tasks)
+        .unify()
+        .or(comments)
+        .unify()
+        .or(attachments)
+        .unify()
+        .or(shares)
+        .unify()
+        .recover(handle_rejection)
+        .unify()
+}
+
+// The user named by the header, or else by the client certificate.
+// A header naming a different user than the certificate is refused by `simple_query_inner`.
+fn principal() -> impl Filter<Extract = (UserUid,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(USER_HEADER)
+        .and(warp::ext::optional::<ClientIdentity>())
+        .and_then(
+            |user: Option<String>, identity: Option<ClientIdentity>| async move {
+                match (user, identity.and_then(|i| i.user)) {
+                    (Some(user), _) => user
+                        .parse::<EntityUid>()
+                        .ok()
+                        .and_then(|euid| UserUid::try_from(euid).ok())
+                        .ok_or_else(|| warp::reject::custom(InvalidPrincipal(user))),
+                    (None, Some(client)) => Ok(client),
+                    (None, None) => Err(warp::reject::custom(MissingPrincipal)),
+                }
+            },
+        )
+}
+
+// The largest body of a request to attach a file of at most `max_attachment_bytes`: its
+// base64-encoded data, with room for its name and content type. Larger bodies are refused
+// before they are read, rather than checked by the application actor once decoded.
+fn attachment_body_limit(max_attachment_bytes: Option<usize>) -> u64 {
+    match max_attachment_bytes {
+        Some(max) => (max.div_ceil(3) * 4 + ATTACHMENT_FIELDS_BYTES) as u64,
+        None => u64::MAX,
+    }
+}
+
+// List ids in paths are just the entity id, e.g. `/lists/0` for `List::"0"`
+fn list_uid(id: &str) -> ListUid {
+    let euid = cedar_policy::EntityUid::from_type_name_and_id(TYPE_LIST.clone(), EntityId::new(id));
+    ListUid::try_from(EntityUid::from(euid)).expect("uid was constructed with type List")
+}
+
+fn reply<R: Serialize>(result: Result<R, Error>, success: StatusCode) -> Response {
+    match result {
+        Ok(r) => warp::reply::with_status(warp::reply::json(&r), success).into_response(),
+        Err(error) => error_reply(error),
+    }
+}
+
+fn no_content(result: Result<Empty, Error>) -> Response {
+    match result {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(error) => error_reply(error),
+    }
+}
+
+fn error_reply(error: Error) -> Response {
+    let status = status_code(&error);
+    warp::reply::with_status(warp::reply::json(&ErrorMsg { error }), status).into_response()
+}
+
+fn created<R: Serialize>(
+    result: Result<R, Error>,
+    location: impl FnOnce(&R) -> String,
+) -> Response {
+    match result {
+        Ok(r) => {
+            let location = location(&r);
+            let reply = warp::reply::with_status(warp::reply::json(&r), StatusCode::CREATED);
+            warp::reply::with_header(reply, LOCATION, location).into_response()
+        }
+        Err(error) => error_reply(error),
+    }
+}
+
+async fn get_lists(uid: UserUid, app: AppChannel) -> Result<Response, Rejection> {
+    let r = simple_query_inner::<Vec<List>>(app, GetLists { uid }).await;
+    Ok(reply(r, StatusCode::OK))
+}
+
+async fn get_capabilities(uid: UserUid, app: AppChannel) -> Result<Response, Rejection> {
+    let r = simple_query_inner::<Capabilities>(app, GetCapabilities { uid }).await;
+    Ok(reply(r, StatusCode::OK))
+}
+
+async fn authorize(
+    uid: UserUid,
+    app: AppChannel,
+    body: AuthorizationBatch,
+) -> Result<Response, Rejection> {
+    let q = Authorize {
+        uid,
+        requests: body.requests,
+    };
+    let r = simple_query_inner::<Decisions>(app, q).await;
+    Ok(reply(r, StatusCode::OK))
+}
+
+async fn create_list(uid: UserUid, app: AppChannel, body: NewList) -> Result<Response, Rejection> {
+    let q = CreateList {
+        uid,
+        name: body.name,
+    };
+    let r = simple_query_inner::<EntityUid>(app, q).await;
+    Ok(created(r, |euid| {
+        format!("/api/v2/lists/{}", euid.id().escaped())
+    }))
+}
+
+async fn get_list(id: String, uid: UserUid, app: AppChannel) -> Result<Response, Rejection> {
+    let q = GetList {
+        uid,
+        list: list_uid(&id),
+    };
+    Ok(reply(
+        simple_query_inner::<List>(app, q).await,
+        StatusCode::OK,
+    ))
+}
+
+async fn update_list(
+    id: String,
+    uid: UserUid,
+    app: AppChannel,
+    body: ListPatch,
+) -> Result<Response, Rejection> {
+    let q = UpdateList {
+        uid,
+        list: list_uid(&id),
+        name: body.name,
+    };
+    Ok(reply(
+        simple_query_inner::<Empty>(app, q).await,
+        StatusCode::OK,
+    ))
+}
+
+async fn delete_list(id: String, uid: UserUid, app: AppChannel) -> Result<Response, Rejection> {
+    let q = DeleteList {
+        uid,
+        list: list_uid(&id),
+    };
+    Ok(no_content(simple_query_inner::<Empty>(app, q).await))
+}
+
+async fn transfer_ownership(
+    id: String,
+    uid: UserUid,
+    app: AppChannel,
+    body: NewOwner,
+) -> Result<Response, Rejection> {
+    let q = TransferOwnership {
+        uid,
+        list: list_uid(&id),
+        owner: body.owner,
+    };
+    Ok(reply(
+        simple_query_inner::<Empty>(app, q).await,
+        StatusCode::OK,
+    ))
+}
+
+async fn duplicate_list(
+    id: String,
+    uid: UserUid,
+    app: AppChannel,
+    body: ListCopy,
+) -> Result<Response, Rejection> {
+    let q = DuplicateList {
+        uid,
+        list: list_uid(&id),
+        name: body.name,
+    };
+    let r = simple_query_inner::<EntityUid>(app, q).await;
+    Ok(created(r, |euid| {
+        format!("/api/v2/lists/{}", euid.id().escaped())
+    }))
+}
+
+async fn get_list_access(
+    id: String,
+    uid: UserUid,
+    app: AppChannel,
+    query: AccessQuery,
+) -> Result<Response, Rejection> {
+    let q = GetListAccess {
+        uid,
+        list: list_uid(&id),
+        action: query.action,
+    };
+    Ok(reply(
+        simple_query_inner::<ListAccess>(app, q).await,
+        StatusCode::OK,
+    ))
+}
+
+async fn subscribe(id: String, uid: UserUid, app: AppChannel) -> Result<Response, Rejection> {
+    let q = Subscribe {
+        uid,
+        list: list_uid(&id),
+    };
+    let recv = simple_query_inner::<broadcast::Receiver<ChangeEvent>>(app.clone(), q.clone()).await;
+    match recv {
+        Ok(recv) => {
+            let stream = event_stream(app, q.uid, q.list, recv);
+            Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream)).into_response())
+        }
+        Err(error) => Ok(error_reply(error)),
+    }
+}
+
+async fn create_task(
+    id: String,
+    uid: UserUid,
+    app: AppChannel,
+    body: NewTask,
+) -> Result<Response, Rejection> {
+    let q = CreateTask {
+        uid,
+        list: list_uid(&id),
+        name: body.name,
+        assignee: body.assignee,
+    };
+    let r = simple_query_inner::<i64>(app, q).await;
+    Ok(created(r, |task| {
+        format!("/api/v2/lists/{id}/tasks/{task}")
+    }))
+}
+
+async fn update_task(
+    id: String,
+    task: i64,
+    uid: UserUid,
+    app: AppChannel,
+    body: TaskPatch,
+) -> Result<Response, Rejection> {
+    let q = UpdateTask {
+        uid,
+        list: list_uid(&id),
+        task,
+        name: body.name,
+        state: body.state,
+        assignee: body.assignee,
+    };
+    Ok(reply(
+        simple_query_inner::<Empty>(app, q).await,
+        StatusCode::OK,
+    ))
+}
+
+async fn delete_task(
+    id: String,
+    task: i64,
+    uid: UserUid,
+    app: AppChannel,
+) -> Result<Response, Rejection> {
+    let q = DeleteTask {
+        uid,
+        list: list_uid(&id),
+        task,
+    };
+    Ok(no_content(simple_query_inner::<Empty>(app, q).await))
+}
+
+async fn comment_on_task(
+    id: String,
+    task: i64,
+    uid: UserUid,
+    app: AppChannel,
+    body: NewComment,
+) -> Result<Response, Rejection> {
+    let q = CommentOnTask {
+        uid,
+        list: list_uid(&id),
+        task,
+        body: body.body,
+    };
+    let r = simple_query_inner::<i64>(app, q).await;
+    Ok(created(r, |comment| {
+        format!("/api/v2/lists/{id}/tasks/{task}/comments/{comment}")
+    }))
+}
+
+async fn edit_comment(
+    id: String,
+    task: i64,
+    comment: i64,
+    uid: UserUid,
+    app: AppChannel,
+    body: CommentPatch,
+) -> Result<Response, Rejection> {
+    let q = EditComment {
+        uid,
+        list: list_uid(&id),
+        task,
+        comment,
+        body: body.body,
+    };
+    Ok(reply(
+        simple_query_inner::<Empty>(app, q).await,
+        StatusCode::OK,
+    ))
+}
+
+async fn delete_comment(
+    id: String,
+    task: i64,
+    comment: i64,
+    uid: UserUid,
+    app: AppChannel,
+) -> Result<Response, Rejection> {
+    let q = DeleteComment {
+        uid,
+        list: list_uid(&id),
+        task,
+        comment,
+    };
+    Ok(no_content(simple_query_inner::<Empty>(app, q).await))
+}
+
+async fn attach_to_task(
+    id: String,
+    task: i64,
+    uid: UserUid,
+    app: AppChannel,
+    body: NewAttachment,
+) -> Result<Response, Rejection> {
+    let q = AttachToTask {
+        uid,
+        list: list_uid(&id),
+        task,
+        name: body.name,
+        content_type: body.content_type,
+        data: body.data,
+    };
+    let r = simple_query_inner::<i64>(app, q).await;
+    Ok(created(r, |attachment| {
+        format!("/api/v2/lists/{id}/tasks/{task}/attachments/{attachment}")
+    }))
+}
+
+async fn delete_attachment(
+    id: String,
+    task: i64,
+    attachment: i64,
+    uid: UserUid,
+    app: AppChannel,
+) -> Result<Response, Rejection> {
+    let q = DeleteAttachment {
+        uid,
+        list: list_uid(&id),
+        task,
+        attachment,
+    };
+    Ok(no_content(simple_query_inner::<Empty>(app, q).await))
+}
+
+async fn add_share(
+    id: String,
+    uid: UserUid,
+    app: AppChannel,
+    body: NewShare,
+) -> Result<Response, Rejection> {
+    let q = AddShare {
+        uid,
+        list: list_uid(&id),
+        share_with: body.share_with,
+        role: body.role,
+        expires: body.expires,
+    };
+    Ok(reply(
+        simple_query_inner::<Empty>(app, q).await,
+        StatusCode::CREATED,
+    ))
+}
+
+async fn delete_share(
+    id: String,
+    uid: UserUid,
+    app: AppChannel,
+    query: ShareQuery,
+) -> Result<Response, Rejection> {
+    let q = DeleteShare {
+        uid,
+        list: list_uid(&id),
+        unshare_with: query.target,
+        role: query.role,
+    };
+    Ok(no_content(simple_query_inner::<Empty>(app, q).await))
+}
+
+async fn handle_rejection(r: Rejection) -> Result<Response, Infallible> {
+    let (status, message) = if r.is_not_found() {
+        (StatusCode::NOT_FOUND, "Not Found".to_string())
+    } else if let Some(MissingPrincipal) = r.find() {
+        (
+            StatusCode::UNAUTHORIZED,
+            format!("Missing request header \"{USER_HEADER}\""),
+        )
+    } else if let Some(InvalidPrincipal(user)) = r.find() {
+        (
+            StatusCode::UNAUTHORIZED,
+            format!("Expected a User entity uid in the `{USER_HEADER}` header, got: {user}"),
+        )
+    } else if let Some(e) = r.find::<warp::filters::body::BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, e.to_string())
+    } else if let Some(e) = r.find::<warp::reject::InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, e.to_string())
+    } else if let Some(e) = r.find::<warp::reject::MethodNotAllowed>() {
+        (StatusCode::METHOD_NOT_ALLOWED, e.to_string())
+    } else if let Some(e) = r.find::<warp::reject::PayloadTooLarge>() {
+        (StatusCode::PAYLOAD_TOO_LARGE, e.to_string())
+    } else if let Some(e) = r.find::<warp::reject::LengthRequired>() {
+        (StatusCode::LENGTH_REQUIRED, e.to_string())
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("{r:?}"))
+    };
+    let body = serde_json::json!({ "error": message });
+    Ok(warp::reply::with_status(warp::reply::json(&body), status).into_response())
+}
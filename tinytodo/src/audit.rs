@@ -0,0 +1,161 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Logging of authorization decisions.
+//!
+//! Every decision is logged as one `tracing` event with structured fields, so that with
+//! `log_format = "json"` each decision is a single JSON object. Values of the configured
+//! entity attributes are replaced by [`REDACTED`] in the principal's attributes. An
+//! evaluation error message may quote any value its policy reads, so the message is
+//! replaced too if the policy reads any of those attributes, of any entity. Shares revoked
+//! because they expired are logged the same way.
+
+use std::collections::HashSet;
+
+use cedar_policy::{Decision, PolicyId, PolicySet, Response};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::info;
+
+use crate::{shares::TimedShare, util::EntityUid};
+
+/// Replaces the value of a redacted attribute
+pub const REDACTED: &str = "[REDACTED]";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Entity attributes whose values are not logged
+    pub redact: Vec<String>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            redact: vec!["location".to_string()],
+        }
+    }
+}
+
+/// Logs authorization decisions, redacting sensitive attributes
+#[derive(Debug)]
+pub struct DecisionLog {
+    redact: HashSet<String>,
+}
+
+impl DecisionLog {
+    pub fn new(config: &AuditConfig) -> Self {
+        Self {
+            redact: config.redact.iter().cloned().collect(),
+        }
+    }
+
+    /// Log the decision for a request, made with `policies`. `attributes` are the
+    /// principal's attributes, if it is a known entity.
+    pub fn log(
+        &self,
+        policies: &PolicySet,
+        principal: &EntityUid,
+        attributes: Option<Map<String, Value>>,
+        action: &EntityUid,
+        resource: &EntityUid,
+        response: &Response,
+    ) {
+        let attributes = Value::Object(
+            attributes
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(k, v)| {
+                    if self.redact.contains(&k) {
+                        (k, Value::String(REDACTED.to_string()))
+                    } else {
+                        (k, v)
+                    }
+                })
+                .collect(),
+        );
+        let decision = match response.decision() {
+            Decision::Allow => "allow",
+            Decision::Deny => "deny",
+        };
+        let policies = response.diagnostics().reason().join(", ");
+        let errors = response
+            .diagnostics()
+            .errors()
+            .map(|e| {
+                if self.reads_redacted(policies, e.policy_id()) {
+                    format!("error evaluating policy {}: {REDACTED}", e.policy_id())
+                } else {
+                    e.to_string()
+                }
+            })
+            .join("; ");
+        info!(
+            %principal,
+            principal_attributes = %attributes,
+            %action,
+            %resource,
+            decision,
+            policies,
+            errors,
+            "Authorization decision"
+        );
+    }
+
+    // Whether the policy `id` of `policies`, or its template, reads a redacted attribute.
+    // A policy that cannot be found or converted to JSON is taken to.
+    fn reads_redacted(&self, policies: &PolicySet, id: &PolicyId) -> bool {
+        if self.redact.is_empty() {
+            return false;
+        }
+        let json = policies.policy(id).and_then(|p| match p.template_id() {
+            Some(tid) => policies.template(tid)?.to_json().ok(),
+            None => p.to_json().ok(),
+        });
+        match json {
+            Some(json) => reads_any(&json, &self.redact),
+            None => true,
+        }
+    }
+}
+
+// In Cedar's JSON policy format, reading the attribute `attr` of an expression is
+// `{".": {"left": <expression>, "attr": "attr"}}`, and `has` is written the same way
+fn reads_any(value: &Value, attrs: &HashSet<String>) -> bool {
+    match value {
+        Value::Object(map) => map.iter().any(|(op, arg)| {
+            let attr = arg.get("attr").and_then(Value::as_str);
+            (matches!(op.as_str(), "." | "has") && attr.is_some_and(|attr| attrs.contains(attr)))
+                || reads_any(arg, attrs)
+        }),
+        Value::Array(items) => items.iter().any(|item| reads_any(item, attrs)),
+        _ => false,
+    }
+}
+
+/// Log the revocation of a share that has expired
+pub fn share_expired(share: &TimedShare) {
+    let list: &EntityUid = share.list.as_ref();
+    let target: &EntityUid = share.target.as_ref();
+    info!(
+        %list,
+        %target,
+        role = %share.role,
+        expires = share.expires,
+        "Share expired"
+    );
+}
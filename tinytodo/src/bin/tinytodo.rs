@@ -0,0 +1,137 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::Parser;
+use tiny_todo_server::{
+    cli::{self, Line, Outcome, Session},
+    client::Client,
+    util::UserUid,
+};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+/// The TinyTodo client. With a command, it runs the command; with `--script`, it runs the
+/// commands in a file; with neither, it reads commands from standard input.
+#[derive(Debug, Parser)]
+#[command(version, after_help = commands())]
+struct Cli {
+    /// The server to talk to
+    #[arg(long, default_value = "http://localhost:8080")]
+    url: String,
+    /// The user to act as until `set_user`, by name or entity uid
+    #[arg(short, long, value_parser = cli::user_uid)]
+    user: Option<UserUid>,
+    /// Run the commands in this file, one per line, and exit with status 1 if any command
+    /// prefixed with `allow` or `deny` has another outcome
+    #[arg(long, conflicts_with = "command")]
+    script: Option<PathBuf>,
+    /// A command, e.g. `create_list "Cedar blog"`, optionally prefixed with `allow` or `deny`
+    command: Vec<String>,
+}
+
+fn commands() -> String {
+    format!("Commands:\n  {}", cli::USAGE.join("\n  "))
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut session = Session::new(Client::new(cli.url), cli.user);
+    if let Some(path) = cli.script {
+        run_script(&mut session, &path).await
+    } else if !cli.command.is_empty() {
+        run_command(&mut session, &cli.command).await
+    } else {
+        run_interactive(&mut session).await
+    }
+}
+
+async fn run_script(session: &mut Session, path: &Path) -> ExitCode {
+    let script = match std::fs::read_to_string(path).map(|src| cli::parse_script(&src)) {
+        Ok(Ok(script)) => script,
+        Ok(Err(e)) => {
+            eprintln!("{}: {e}", path.display());
+            return ExitCode::from(2);
+        }
+        Err(e) => {
+            eprintln!("Unable to read {}: {e}", path.display());
+            return ExitCode::from(2);
+        }
+    };
+    let mismatches = session.run_script(&script).await;
+    for mismatch in &mismatches {
+        eprintln!("{mismatch}");
+    }
+    let expected = script
+        .iter()
+        .filter(|(_, line)| line.expect.is_some())
+        .count();
+    if mismatches.is_empty() {
+        eprintln!("All {expected} expectations met");
+        ExitCode::SUCCESS
+    } else {
+        eprintln!("{} of {expected} expectations not met", mismatches.len());
+        ExitCode::FAILURE
+    }
+}
+
+// A single command fails if it is denied or fails, unless that is what was expected
+async fn run_command(session: &mut Session, tokens: &[String]) -> ExitCode {
+    let line = match Line::parse(tokens) {
+        Ok(line) => line,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::from(2);
+        }
+    };
+    let (shown, outcome) = session.run(&line).await;
+    println!("{shown}");
+    if outcome == line.expect.unwrap_or(Outcome::Allowed) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+async fn run_interactive(session: &mut Session) -> ExitCode {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        print!("tinytodo> ");
+        let _ = std::io::stdout().flush();
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        };
+        match line.trim() {
+            "help" => println!("{}", commands()),
+            "quit" | "exit" => return ExitCode::SUCCESS,
+            line if cli::is_blank(line) => (),
+            line => match line.parse::<Line>() {
+                Ok(line) => println!("{}", session.run(&line).await.0),
+                Err(e) => println!("{e}"),
+            },
+        }
+    }
+}
@@ -0,0 +1,239 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! An optional cache of authorization decisions.
+//!
+//! Decisions are keyed on the principal, action and resource, and a hash of the request's
+//! context. Every context holds the time as `now`, which is only hashed if a policy reads
+//! it, as otherwise no entry would outlive the second it was made in.
+//!
+//! A decision can only depend on the entities reachable from the principal, the resource
+//! and the entity uids in the context, through their parents and the entities their
+//! attributes and tags refer to. (Entities nested in context records aren't followed; the
+//! server's contexts have none.)
+//! Each entry records the versions those entities had in the entity store, and is used
+//! only while none of them has changed; changes to any other entity leave it in place.
+//! Any change to the policies empties the cache.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::Mutex,
+};
+
+use cedar_policy::{Entities, PolicySet, Response, RestrictedExpression};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{entitystore::EntityStore, metrics, shares, util::EntityUid};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// The most decisions kept; the cache is emptied when it is full
+    pub capacity: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self { capacity: 10_000 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    principal: EntityUid,
+    action: EntityUid,
+    resource: EntityUid,
+    context: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// The entities the decision could depend on, with their versions when it was made
+    dependencies: Vec<(EntityUid, u64)>,
+    response: Response,
+}
+
+#[derive(Debug)]
+pub struct DecisionCache {
+    capacity: usize,
+    /// Whether a policy reads `context.now`, so that it is part of the key
+    reads_now: bool,
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl DecisionCache {
+    pub fn new(config: &CacheConfig, policies: &PolicySet) -> Self {
+        Self {
+            capacity: config.capacity,
+            reads_now: reads_now(policies),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Forget every decision, as the policies are now `policies`
+    pub fn reset(&mut self, policies: &PolicySet) {
+        self.reads_now = reads_now(policies);
+        self.entries.get_mut().unwrap().clear();
+    }
+
+    /// The key for a request with `context`, to which the server adds `now`
+    pub fn key(
+        &self,
+        principal: &EntityUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+        context: &[(String, RestrictedExpression)],
+    ) -> Key {
+        let mut hasher = DefaultHasher::new();
+        for (name, value) in context {
+            name.hash(&mut hasher);
+            value.to_string().hash(&mut hasher);
+        }
+        if self.reads_now {
+            shares::now().hash(&mut hasher);
+        }
+        Key {
+            principal: principal.clone(),
+            action: action.clone(),
+            resource: resource.clone(),
+            context: hasher.finish(),
+        }
+    }
+
+    /// The decision for `key`, if one was made since the entities it depends on last changed
+    pub fn get(&self, key: &Key, store: &EntityStore) -> Option<Response> {
+        let entries = self.entries.lock().unwrap();
+        let response = entries
+            .get(key)
+            .filter(|entry| {
+                entry
+                    .dependencies
+                    .iter()
+                    .all(|(uid, version)| store.version(uid) == *version)
+            })
+            .map(|entry| entry.response.clone());
+        metrics::record_cache_lookup(response.is_some());
+        response
+    }
+
+    /// Remember the decision for `key`, made in `context` with `entities` built from `store`
+    pub fn insert(
+        &self,
+        key: Key,
+        context: &[(String, RestrictedExpression)],
+        response: Response,
+        entities: &Entities,
+        store: &EntityStore,
+    ) {
+        let context_uids = context
+            .iter()
+            .filter_map(|(_, value)| value.to_string().parse().ok());
+        let roots = [&key.principal, &key.resource]
+            .into_iter()
+            .map(|uid| uid.clone().into())
+            .chain(context_uids);
+        let dependencies = reachable(entities, roots)
+            .into_iter()
+            .map(|uid| {
+                let uid = EntityUid::from(uid);
+                let version = store.version(&uid);
+                (uid, version)
+            })
+            .collect();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity {
+            entries.clear();
+        }
+        entries.insert(
+            key,
+            Entry {
+                dependencies,
+                response,
+            },
+        );
+    }
+}
+
+/// The entities reachable from `roots` through parents, attributes and tags, including the
+/// roots themselves
+fn reachable(
+    entities: &Entities,
+    roots: impl IntoIterator<Item = cedar_policy::EntityUid>,
+) -> HashSet<cedar_policy::EntityUid> {
+    let mut seen = HashSet::new();
+    let mut todo: Vec<_> = roots.into_iter().collect();
+    while let Some(uid) = todo.pop() {
+        if !seen.insert(uid.clone()) {
+            continue;
+        }
+        let Some(json) = entities.get(&uid).and_then(|e| e.to_json_value().ok()) else {
+            continue;
+        };
+        let parents = json["parents"].as_array().into_iter().flatten();
+        todo.extend(parents.filter_map(|p| cedar_policy::EntityUid::from_json(p.clone()).ok()));
+        for values in [&json["attrs"], &json["tags"]] {
+            entity_refs(values, &mut todo);
+        }
+    }
+    seen
+}
+
+// The entities referred to in an attribute value, in Cedar's JSON entity format
+fn entity_refs(value: &Value, refs: &mut Vec<cedar_policy::EntityUid>) {
+    match value {
+        Value::Object(map) if map.contains_key("__entity") => {
+            refs.extend(cedar_policy::EntityUid::from_json(value.clone()).ok())
+        }
+        Value::Object(map) => map.values().for_each(|v| entity_refs(v, refs)),
+        Value::Array(items) => items.iter().for_each(|v| entity_refs(v, refs)),
+        _ => (),
+    }
+}
+
+/// Whether any policy or template reads `context.now`, or the context as a whole. Policies
+/// that can't be converted to JSON are assumed to.
+fn reads_now(policies: &PolicySet) -> bool {
+    let statics = policies
+        .policies()
+        .filter(|p| p.template_id().is_none())
+        .map(|p| p.to_json().ok());
+    let templates = policies.templates().map(|t| t.to_json().ok());
+    statics.chain(templates).any(|json| match json {
+        Some(json) => reads_context_now(&json),
+        None => true,
+    })
+}
+
+// In Cedar's JSON policy format, `context.now` is
+// `{".": {"left": {"Var": "context"}, "attr": "now"}}`
+fn reads_context_now(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => map.iter().any(|(op, arg)| {
+            let context = serde_json::json!({ "Var": "context" });
+            match (op.as_str(), arg) {
+                ("Var", Value::String(var)) => var == "context",
+                ("." | "has", Value::Object(access)) if access.get("left") == Some(&context) => {
+                    access.get("attr").and_then(Value::as_str) == Some("now")
+                }
+                _ => reads_context_now(arg),
+            }
+        }),
+        Value::Array(items) => items.iter().any(reads_context_now),
+        _ => false,
+    }
+}
@@ -0,0 +1,616 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The commands of the `tinytodo` command-line client.
+//!
+//! A command is a line such as `share_list 0 aaron Editor`, run by a [`Session`] through
+//! the typed [`Client`]. Prefixed with `allow` or `deny`, a command also states the
+//! outcome it is expected to have, so that a script of commands can check what the
+//! policies permit. Enable it with the `client` feature.
+
+use std::{
+    fmt,
+    process::{Child, Command as Process},
+    str::FromStr,
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use crate::{
+    api::{
+        AddShare, CreateList, CreateTask, DeleteList, DeleteShare, DeleteTask, GetList,
+        GetListAccess, GetLists, ShareRole, UpdateTask,
+    },
+    client::{Client, ClientError},
+    objects::{List, Task, TaskState},
+    util::{EntityUid, ListUid, UserOrTeamUid, UserUid},
+};
+
+/// Each command and its arguments
+pub const USAGE: &[&str] = &[
+    "set_user USER",
+    "start_server [PORT]",
+    "stop_server",
+    "get_lists",
+    "create_list NAME",
+    "get_list LIST",
+    "delete_list LIST",
+    "create_task LIST NAME",
+    "toggle_task LIST TASK",
+    "change_task_description LIST TASK NAME",
+    "delete_task LIST TASK",
+    "share_list LIST TARGET [ROLE]",
+    "unshare_list LIST TARGET [ROLE]",
+    "list_access LIST [ACTION]",
+];
+
+#[derive(Debug, Clone, Error)]
+pub enum ParseError {
+    #[error("Unterminated string")]
+    Unterminated,
+    #[error("Expected a command")]
+    MissingCommand,
+    #[error("Unknown command `{0}`")]
+    UnknownCommand(String),
+    #[error("Usage: {0}")]
+    Usage(&'static str),
+    #[error("Invalid {0}: `{1}`")]
+    Invalid(&'static str, String),
+    #[error("Line {0}: {1}")]
+    Line(usize, Box<ParseError>),
+}
+
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error(transparent)]
+    Client(#[from] ClientError),
+    #[error("No user set! Use `set_user`")]
+    NoUser,
+    #[error("No such task {1} on list ID {0}")]
+    NoSuchTask(String, usize),
+    #[error("Server is already running")]
+    AlreadyRunning,
+    #[error("Unable to start the server: {0}")]
+    Spawn(#[from] std::io::Error),
+    #[error("The server did not start answering on port {0}")]
+    NotStarted(u16),
+}
+
+/// How a command turned out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Allowed,
+    Denied,
+    /// The command failed for a reason other than authorization, e.g. a missing list
+    Failed,
+}
+
+impl Outcome {
+    fn of<T>(result: &Result<T, CommandError>) -> Self {
+        match result {
+            Ok(_) => Outcome::Allowed,
+            Err(CommandError::Client(ClientError::AuthDenied)) => Outcome::Denied,
+            Err(_) => Outcome::Failed,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Allowed => write!(f, "allowed"),
+            Outcome::Denied => write!(f, "denied"),
+            Outcome::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// A client command. Lists are given by their numeric ID, and tasks by their position in
+/// the list, from 1.
+#[derive(Debug, Clone)]
+pub enum Command {
+    SetUser(UserUid),
+    StartServer(u16),
+    StopServer,
+    GetLists,
+    CreateList(String),
+    GetList(ListUid),
+    DeleteList(ListUid),
+    CreateTask(ListUid, String),
+    ToggleTask(ListUid, usize),
+    ChangeTaskDescription(ListUid, usize, String),
+    DeleteTask(ListUid, usize),
+    ShareList(ListUid, UserOrTeamUid, ShareRole),
+    UnshareList(ListUid, UserOrTeamUid, ShareRole),
+    ListAccess(ListUid, EntityUid),
+}
+
+impl Command {
+    pub fn parse(tokens: &[String]) -> Result<Self, ParseError> {
+        let (name, args) = tokens.split_first().ok_or(ParseError::MissingCommand)?;
+        let usage = USAGE
+            .iter()
+            .find(|usage| usage.split(' ').next() == Some(name.as_str()))
+            .ok_or_else(|| ParseError::UnknownCommand(name.clone()))?;
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        Ok(match (name.as_str(), args.as_slice()) {
+            ("set_user", [user]) => Command::SetUser(user_uid(user)?),
+            ("start_server", []) => Command::StartServer(8080),
+            ("start_server", [port]) => match port.parse() {
+                Ok(port) => Command::StartServer(port),
+                Err(_) => return Err(ParseError::Invalid("port", port.to_string())),
+            },
+            ("stop_server", []) => Command::StopServer,
+            ("get_lists", []) => Command::GetLists,
+            ("create_list", [name]) => Command::CreateList(name.to_string()),
+            ("get_list", [list]) => Command::GetList(list_uid(list)?),
+            ("delete_list", [list]) => Command::DeleteList(list_uid(list)?),
+            ("create_task", [list, name]) => Command::CreateTask(list_uid(list)?, name.to_string()),
+            ("toggle_task", [list, task]) => Command::ToggleTask(list_uid(list)?, position(task)?),
+            ("change_task_description", [list, task, name]) => {
+                let name = name.to_string();
+                Command::ChangeTaskDescription(list_uid(list)?, position(task)?, name)
+            }
+            ("delete_task", [list, task]) => Command::DeleteTask(list_uid(list)?, position(task)?),
+            ("share_list", [list, target, role @ ..]) if role.len() <= 1 => {
+                Command::ShareList(list_uid(list)?, target_uid(target)?, share_role(role))
+            }
+            ("unshare_list", [list, target, role @ ..]) if role.len() <= 1 => {
+                Command::UnshareList(list_uid(list)?, target_uid(target)?, share_role(role))
+            }
+            ("list_access", [list, action @ ..]) if action.len() <= 1 => {
+                let action = action.first().unwrap_or(&"GetList");
+                Command::ListAccess(list_uid(list)?, uid("action", "Action", action)?)
+            }
+            _ => return Err(ParseError::Usage(usage)),
+        })
+    }
+}
+
+/// A command, with the outcome it is expected to have, if any
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub expect: Option<Outcome>,
+    pub command: Command,
+    /// The command as written, without the expectation
+    pub text: String,
+}
+
+impl Line {
+    pub fn parse(tokens: &[String]) -> Result<Self, ParseError> {
+        let (expect, tokens) = match tokens.first().map(String::as_str) {
+            Some("allow") => (Some(Outcome::Allowed), &tokens[1..]),
+            Some("deny") => (Some(Outcome::Denied), &tokens[1..]),
+            _ => (None, tokens),
+        };
+        Ok(Self {
+            expect,
+            command: Command::parse(tokens)?,
+            text: tokens
+                .iter()
+                .map(|t| quote(t))
+                .collect::<Vec<_>>()
+                .join(" "),
+        })
+    }
+}
+
+impl FromStr for Line {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(&tokenize(s)?)
+    }
+}
+
+/// Parse a script: one command per line, skipping blank lines and `#` comments
+pub fn parse_script(src: &str) -> Result<Vec<(usize, Line)>, ParseError> {
+    src.lines()
+        .enumerate()
+        .filter(|(_, line)| !is_blank(line))
+        .map(|(i, line)| {
+            let line = line
+                .parse()
+                .map_err(|e| ParseError::Line(i + 1, Box::new(e)))?;
+            Ok((i + 1, line))
+        })
+        .collect()
+}
+
+/// Whether `line` holds no command, being empty or a comment
+pub fn is_blank(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || line.starts_with('#')
+}
+
+/// Split a line into words. A word that starts with `"` runs to the next unescaped `"`,
+/// so `create_list "Cedar blog"` has two words, while `Team::"interns"` is one word
+/// as it stands.
+pub fn tokenize(line: &str) -> Result<Vec<String>, ParseError> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut token = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => token.push(chars.next().ok_or(ParseError::Unterminated)?),
+                    Some(c) => token.push(c),
+                    None => return Err(ParseError::Unterminated),
+                }
+            }
+            tokens.push(token);
+        } else {
+            let mut token = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                token.push(c);
+            }
+            tokens.push(token);
+        }
+    }
+    Ok(tokens)
+}
+
+// A word as `tokenize` reads it back
+fn quote(token: &str) -> String {
+    if token.is_empty() || token.starts_with('"') || token.contains(char::is_whitespace) {
+        format!("{token:?}")
+    } else {
+        token.to_string()
+    }
+}
+
+/// A user, by name (`kesha`) or entity uid (`User::"kesha"`)
+pub fn user_uid(s: &str) -> Result<UserUid, ParseError> {
+    uid("user", "User", s)
+}
+
+fn list_uid(s: &str) -> Result<ListUid, ParseError> {
+    uid("list", "List", s)
+}
+
+// A user by name, or a user or team by entity uid
+fn target_uid(s: &str) -> Result<UserOrTeamUid, ParseError> {
+    uid("user or team", "User", s)
+}
+
+fn uid<T: TryFrom<EntityUid>>(what: &'static str, ty: &str, s: &str) -> Result<T, ParseError> {
+    let src = if s.contains("::") {
+        s.to_string()
+    } else {
+        format!("{ty}::\"{s}\"")
+    };
+    src.parse::<EntityUid>()
+        .ok()
+        .and_then(|euid| euid.try_into().ok())
+        .ok_or_else(|| ParseError::Invalid(what, s.to_string()))
+}
+
+fn position(s: &str) -> Result<usize, ParseError> {
+    match s.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(ParseError::Invalid("task", s.to_string())),
+    }
+}
+
+fn share_role(role: &[&str]) -> ShareRole {
+    role.first()
+        .map_or_else(ShareRole::reader, |role| ShareRole::new(*role))
+}
+
+/// A command whose outcome was not the expected one
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub line: usize,
+    pub text: String,
+    pub expected: Outcome,
+    pub got: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Line {}: expected `{}` to be {}, but it was {}",
+            self.line, self.text, self.expected, self.got
+        )
+    }
+}
+
+/// The state the commands share: the server, and the user they act as
+pub struct Session {
+    client: Client,
+    user: Option<UserUid>,
+    /// The server started by `start_server`, stopped with the session
+    server: Option<Child>,
+}
+
+impl Session {
+    pub fn new(client: Client, user: Option<UserUid>) -> Self {
+        Self {
+            client,
+            user,
+            server: None,
+        }
+    }
+
+    /// Run a command, returning what to show for it and its outcome
+    pub async fn run(&mut self, line: &Line) -> (String, Outcome) {
+        let result = self.execute(&line.command).await;
+        let outcome = Outcome::of(&result);
+        let shown = match (result, &self.user) {
+            (Ok(shown), _) => shown,
+            (Err(_), Some(user)) if outcome == Outcome::Denied => format!(
+                "Access denied. User {} is not authorized to {}",
+                id(user),
+                line.text
+            ),
+            (Err(e), _) => format!("Error: {e}"),
+        };
+        (shown, outcome)
+    }
+
+    /// Run a script's commands in order, printing what each shows, and return those whose
+    /// outcome was not the expected one
+    pub async fn run_script(&mut self, script: &[(usize, Line)]) -> Vec<Mismatch> {
+        let mut mismatches = vec![];
+        for (number, line) in script {
+            let (shown, got) = self.run(line).await;
+            println!("{shown}");
+            match line.expect {
+                Some(expected) if expected != got => mismatches.push(Mismatch {
+                    line: *number,
+                    text: line.text.clone(),
+                    expected,
+                    got,
+                }),
+                _ => (),
+            }
+        }
+        mismatches
+    }
+
+    pub async fn execute(&mut self, command: &Command) -> Result<String, CommandError> {
+        Ok(match command {
+            Command::SetUser(user) => {
+                self.user = Some(user.clone());
+                format!("User is now {}", id(user))
+            }
+            Command::StartServer(port) => self.start_server(*port).await?,
+            Command::StopServer => self.stop_server(),
+            Command::GetLists => {
+                let uid = self.user()?;
+                let mut lists = self
+                    .client
+                    .get_lists(&GetLists { uid: uid.clone() })
+                    .await?;
+                lists.sort_by_key(|list| id(list.uid()).parse::<u64>().ok());
+                if lists.is_empty() {
+                    format!("No lists for {}", id(&uid))
+                } else {
+                    let names: Vec<_> = lists.iter().map(List::name).collect();
+                    format!("Lists: {}", names.join(","))
+                }
+            }
+            Command::CreateList(name) => {
+                let uid = self.user()?;
+                let name = name.clone();
+                let list = self.client.create_list(&CreateList { uid, name }).await?;
+                format!("Created list ID {}", id(&list))
+            }
+            Command::GetList(list) => {
+                let uid = self.user()?;
+                let list = list.clone();
+                display_list(&self.client.get_list(&GetList { uid, list }).await?)
+            }
+            Command::DeleteList(list) => {
+                let uid = self.user()?;
+                let list = list.clone();
+                self.client.delete_list(&DeleteList { uid, list }).await?;
+                "List Deleted".to_string()
+            }
+            Command::CreateTask(list, name) => {
+                let uid = self.user()?;
+                let r = CreateTask {
+                    uid,
+                    list: list.clone(),
+                    name: name.clone(),
+                    assignee: None,
+                };
+                self.client.create_task(&r).await?;
+                format!("Created task on list ID {}", id(list))
+            }
+            Command::ToggleTask(list, position) => {
+                let uid = self.user()?;
+                let task = self.find_task(&uid, list, *position).await?;
+                let state = match task.state() {
+                    TaskState::Checked => TaskState::Unchecked,
+                    TaskState::Unchecked => TaskState::Checked,
+                };
+                let r = UpdateTask {
+                    uid,
+                    list: list.clone(),
+                    task: task.id(),
+                    name: None,
+                    state: Some(state),
+                    assignee: None,
+                };
+                self.client.update_task(&r).await?;
+                format!("Toggled task on list ID {}", id(list))
+            }
+            Command::ChangeTaskDescription(list, position, name) => {
+                let uid = self.user()?;
+                let task = self.find_task(&uid, list, *position).await?;
+                let r = UpdateTask {
+                    uid,
+                    list: list.clone(),
+                    task: task.id(),
+                    name: Some(name.clone()),
+                    state: None,
+                    assignee: None,
+                };
+                self.client.update_task(&r).await?;
+                "Description Updated".to_string()
+            }
+            Command::DeleteTask(list, position) => {
+                let uid = self.user()?;
+                let task = self.find_task(&uid, list, *position).await?;
+                let r = DeleteTask {
+                    uid,
+                    list: list.clone(),
+                    task: task.id(),
+                };
+                self.client.delete_task(&r).await?;
+                "Task Deleted".to_string()
+            }
+            Command::ShareList(list, target, role) => {
+                let uid = self.user()?;
+                let r = AddShare {
+                    uid,
+                    list: list.clone(),
+                    share_with: target.clone(),
+                    role: role.clone(),
+                    expires: None,
+                };
+                self.client.add_share(&r).await?;
+                format!("Shared list ID {} with {} as {role}", id(list), id(target))
+            }
+            Command::UnshareList(list, target, role) => {
+                let uid = self.user()?;
+                let r = DeleteShare {
+                    uid,
+                    list: list.clone(),
+                    unshare_with: target.clone(),
+                    role: role.clone(),
+                };
+                self.client.delete_share(&r).await?;
+                format!(
+                    "Unshared {role} on list ID {} with {}",
+                    id(list),
+                    id(target)
+                )
+            }
+            Command::ListAccess(list, action) => {
+                let uid = self.user()?;
+                let r = GetListAccess {
+                    uid,
+                    list: list.clone(),
+                    action: action.clone(),
+                };
+                let access = self.client.get_list_access(&r).await?;
+                let users: Vec<_> = access.users.iter().map(id).collect();
+                let teams: Vec<_> = access.teams.iter().map(id).collect();
+                format!(
+                    "Allowed to {} on list ID {}: users [{}], teams [{}]",
+                    id(action),
+                    id(list),
+                    users.join(","),
+                    teams.join(",")
+                )
+            }
+        })
+    }
+
+    fn user(&self) -> Result<UserUid, CommandError> {
+        self.user.clone().ok_or(CommandError::NoUser)
+    }
+
+    // The task at `position` in the list, counting from 1
+    async fn find_task(
+        &self,
+        uid: &UserUid,
+        list: &ListUid,
+        position: usize,
+    ) -> Result<Task, CommandError> {
+        let r = GetList {
+            uid: uid.clone(),
+            list: list.clone(),
+        };
+        self.client
+            .get_list(&r)
+            .await?
+            .tasks()
+            .get(position - 1)
+            .cloned()
+            .ok_or_else(|| CommandError::NoSuchTask(id(list).to_string(), position))
+    }
+
+    /// Start `tiny-todo-server`, from the directory this binary is in, and talk to it
+    async fn start_server(&mut self, port: u16) -> Result<String, CommandError> {
+        if self.server.is_some() {
+            return Err(CommandError::AlreadyRunning);
+        }
+        let binary = std::env::current_exe()?
+            .with_file_name(format!("tiny-todo-server{}", std::env::consts::EXE_SUFFIX));
+        let mut server = Process::new(binary).arg(port.to_string()).spawn()?;
+        let client = Client::new(format!("http://localhost:{port}"));
+        for _ in 0..100 {
+            if client.healthz().await.is_ok() {
+                self.client = client;
+                self.server = Some(server);
+                return Ok(format!("TinyTodo server started on port {port}"));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let _ = server.kill();
+        let _ = server.wait();
+        Err(CommandError::NotStarted(port))
+    }
+
+    fn stop_server(&mut self) -> String {
+        match self.server.take() {
+            Some(mut server) => {
+                let _ = server.kill();
+                let _ = server.wait();
+                "TinyTodo server stopped".to_string()
+            }
+            None => "No server was started with `start_server`".to_string(),
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.stop_server();
+    }
+}
+
+fn display_list(list: &List) -> String {
+    let mut lines = vec![
+        format!("=== {} ===", list.name()),
+        format!("List ID: {}", id(list.uid())),
+        format!("Owner: {}", list.owner()),
+        "Tasks:".to_string(),
+    ];
+    lines.extend(list.tasks().iter().enumerate().map(|(i, task)| {
+        let mark = match task.state() {
+            TaskState::Checked => "[X]",
+            TaskState::Unchecked => "[ ]",
+        };
+        format!("{}: {mark} {}", i + 1, task.name())
+    }));
+    lines.join("\n")
+}
+
+// The id of an entity, e.g. `kesha` for `User::"kesha"`
+fn id<T: AsRef<EntityUid>>(euid: &T) -> &str {
+    euid.as_ref().id().unescaped()
+}
@@ -0,0 +1,203 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A typed client for the TinyTodo HTTP API.
+//!
+//! The client is built from the same request and response types that the server
+//! deserializes and serializes in [`crate::api`], so the two cannot drift apart.
+//! Enable it with the `client` feature.
+
+use reqwest::Method;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    api::{
+        AddShare, AttachToTask, Authorize, Capabilities, CommentOnTask, CreateList, CreateTask,
+        Decisions, DeleteAttachment, DeleteComment, DeleteList, DeleteShare, DeleteTask,
+        DuplicateList, EditComment, Empty, GetCapabilities, GetList, GetListAccess, GetLists,
+        ListAccess, TransferOwnership, UpdateList, UpdateTask,
+    },
+    objects::List,
+    util::EntityUid,
+};
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("HTTP Error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Authorization Denied")]
+    AuthDenied,
+    #[error("Server Error: {0}")]
+    Server(String),
+}
+
+type Result<T> = std::result::Result<T, ClientError>;
+
+// The server reports errors as `{ "error": "..." }` in place of the response body
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Reply<T> {
+    Error { error: String },
+    Ok(T),
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl Client {
+    /// Create a client for the server at `base_url`, e.g. `http://localhost:8080`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(reqwest::Client::new(), base_url)
+    }
+
+    pub fn with_http_client(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Self {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub async fn get_list(&self, r: &GetList) -> Result<List> {
+        self.query("/api/list/get", r).await
+    }
+
+    pub async fn create_list(&self, r: &CreateList) -> Result<EntityUid> {
+        self.send(Method::POST, "/api/list/create", r).await
+    }
+
+    pub async fn update_list(&self, r: &UpdateList) -> Result<Empty> {
+        self.send(Method::POST, "/api/list/update", r).await
+    }
+
+    pub async fn delete_list(&self, r: &DeleteList) -> Result<Empty> {
+        self.send(Method::DELETE, "/api/list/delete", r).await
+    }
+
+    pub async fn transfer_ownership(&self, r: &TransferOwnership) -> Result<Empty> {
+        self.send(Method::POST, "/api/list/transfer", r).await
+    }
+
+    pub async fn duplicate_list(&self, r: &DuplicateList) -> Result<EntityUid> {
+        self.send(Method::POST, "/api/list/duplicate", r).await
+    }
+
+    pub async fn get_list_access(&self, r: &GetListAccess) -> Result<ListAccess> {
+        self.query("/api/list/access", r).await
+    }
+
+    pub async fn create_task(&self, r: &CreateTask) -> Result<i64> {
+        self.send(Method::POST, "/api/task/create", r).await
+    }
+
+    pub async fn update_task(&self, r: &UpdateTask) -> Result<Empty> {
+        self.send(Method::POST, "/api/task/update", r).await
+    }
+
+    pub async fn delete_task(&self, r: &DeleteTask) -> Result<Empty> {
+        self.send(Method::DELETE, "/api/task/delete", r).await
+    }
+
+    pub async fn comment_on_task(&self, r: &CommentOnTask) -> Result<i64> {
+        self.send(Method::POST, "/api/comment/create", r).await
+    }
+
+    pub async fn edit_comment(&self, r: &EditComment) -> Result<Empty> {
+        self.send(Method::POST, "/api/comment/update", r).await
+    }
+
+    pub async fn delete_comment(&self, r: &DeleteComment) -> Result<Empty> {
+        self.send(Method::DELETE, "/api/comment/delete", r).await
+    }
+
+    pub async fn attach_to_task(&self, r: &AttachToTask) -> Result<i64> {
+        self.send(Method::POST, "/api/attachment/create", r).await
+    }
+
+    pub async fn delete_attachment(&self, r: &DeleteAttachment) -> Result<Empty> {
+        self.send(Method::DELETE, "/api/attachment/delete", r).await
+    }
+
+    pub async fn get_lists(&self, r: &GetLists) -> Result<Vec<List>> {
+        self.query("/api/lists/get", r).await
+    }
+
+    pub async fn get_capabilities(&self, r: &GetCapabilities) -> Result<Capabilities> {
+        self.query("/api/capabilities", r).await
+    }
+
+    pub async fn authorize(&self, r: &Authorize) -> Result<Decisions> {
+        self.send(Method::POST, "/api/authorize", r).await
+    }
+
+    pub async fn add_share(&self, r: &AddShare) -> Result<Empty> {
+        self.send(Method::POST, "/api/share", r).await
+    }
+
+    pub async fn delete_share(&self, r: &DeleteShare) -> Result<Empty> {
+        self.send(Method::DELETE, "/api/share", r).await
+    }
+
+    /// Check that the server is up, as `/healthz` does
+    pub async fn healthz(&self) -> Result<()> {
+        let resp = self.http.get(self.url("/healthz")).send().await?;
+        resp.error_for_status()?;
+        Ok(())
+    }
+
+    /// Fetch the server's OpenAPI document
+    pub async fn openapi(&self) -> Result<serde_json::Value> {
+        let resp = self.http.get(self.url("/api/openapi.json")).send().await?;
+        Ok(resp.error_for_status()?.json().await?)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    async fn query<Q: Serialize, R: DeserializeOwned>(&self, path: &str, q: &Q) -> Result<R> {
+        let resp = self.http.get(self.url(path)).query(q).send().await?;
+        Self::decode(resp).await
+    }
+
+    async fn send<B: Serialize, R: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: &B,
+    ) -> Result<R> {
+        let resp = self
+            .http
+            .request(method, self.url(path))
+            .json(body)
+            .send()
+            .await?;
+        Self::decode(resp).await
+    }
+
+    async fn decode<R: DeserializeOwned>(resp: reqwest::Response) -> Result<R> {
+        match resp.error_for_status()?.json::<Reply<R>>().await? {
+            Reply::Ok(r) => Ok(r),
+            Reply::Error { error } if error == "Authorization Denied" => {
+                Err(ClientError::AuthDenied)
+            }
+            Reply::Error { error } => Err(ClientError::Server(error)),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Server configuration, read from a TOML file and overridden on the command line.
+//!
+//! Every field has a default, so an empty file (or no file at all) gives the server's
+//! historical behaviour: listen on `127.0.0.1:8080` and read `./entities.json` and the
+//! schema and policies for the sharing mode the server was built with.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    api::ShareRole,
+    audit::AuditConfig,
+    cache::CacheConfig,
+    limits::{LimitsConfig, LimitsError},
+    lint::LintConfig,
+    profiles::PolicyProfile,
+    roles::{self, RoleConfig, RolesError},
+    telemetry::TelemetryConfig,
+    util::UserUid,
+};
+
+/// How lists are shared; see `TUTORIAL.md` and `TUTORIAL_TEMPLATE.md`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SharingMode {
+    /// Each list has a reader and an editor team
+    Teams,
+    /// Shares are template-linked policies
+    Templates,
+}
+
+impl SharingMode {
+    /// The sharing mode this server was built with (the `use-templates` feature)
+    pub fn compiled() -> Self {
+        if cfg!(feature = "use-templates") {
+            SharingMode::Templates
+        } else {
+            SharingMode::Teams
+        }
+    }
+}
+
+impl std::fmt::Display for SharingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SharingMode::Teams => write!(f, "teams"),
+            SharingMode::Templates => write!(f, "templates"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Compact,
+    Json,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM-encoded certificate chain
+    pub cert: PathBuf,
+    /// PEM-encoded private key
+    pub key: PathBuf,
+    /// PEM-encoded CA certificates; client certificates signed by them are accepted
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+    /// Refuse connections that do not present a client certificate
+    #[serde(default)]
+    pub require_client_cert: bool,
+    /// Users that client certificates act as, keyed by the certificate's subject
+    /// (e.g., `CN=billing, O=Example Corp`) or just its common name (`billing`)
+    #[serde(default)]
+    pub client_principals: HashMap<String, UserUid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: SocketAddr,
+    pub entities: PathBuf,
+    pub schema: PathBuf,
+    pub policies: PathBuf,
+    /// Model each task as a `Task` entity whose parent is its list, so that task actions are
+    /// authorized against the task. Requires a schema that declares `Task` as an entity type.
+    pub task_entities: bool,
+    pub log_format: LogFormat,
+    /// Where the entity store (and, with templates, the linked policies) is saved after
+    /// every change, and restored from at startup. Nothing is saved if unset.
+    pub persistence_dir: Option<PathBuf>,
+    pub tls: Option<TlsConfig>,
+    pub limits: LimitsConfig,
+    /// Where spans are exported; they are not exported if unset
+    pub telemetry: Option<TelemetryConfig>,
+    pub audit: AuditConfig,
+    /// Share roles beyond `Reader` and `Editor`, keyed by name
+    pub roles: BTreeMap<ShareRole, RoleConfig>,
+    /// Optional bundles of policies to add to those in `policies`; see `profiles.rs`
+    pub profiles: BTreeSet<PolicyProfile>,
+    /// Cache authorization decisions; they are made afresh every time if unset
+    pub decision_cache: Option<CacheConfig>,
+    /// Static analysis of the policies file whenever it is loaded; see `lint.rs`
+    pub lint: LintConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let (schema, policies) = match SharingMode::compiled() {
+            SharingMode::Teams => ("./tinytodo.cedarschema", "./policies.cedar"),
+            SharingMode::Templates => (
+                "./tinytodo-templates.cedarschema",
+                "./policies-templates.cedar",
+            ),
+        };
+        Self {
+            bind: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8080),
+            entities: "./entities.json".into(),
+            schema: schema.into(),
+            policies: policies.into(),
+            task_entities: false,
+            log_format: LogFormat::default(),
+            persistence_dir: None,
+            tls: None,
+            limits: LimitsConfig::default(),
+            telemetry: None,
+            audit: AuditConfig::default(),
+            roles: BTreeMap::new(),
+            profiles: BTreeSet::new(),
+            decision_cache: None,
+            lint: LintConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Couldn't read config file {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Couldn't parse config file {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("The {name} file {} does not exist", path.display())]
+    MissingFile { name: &'static str, path: PathBuf },
+    #[error("The persistence directory {} is not a directory", .0.display())]
+    NotADirectory(PathBuf),
+    #[error("TLS setting `{0}` requires `client_ca`")]
+    ClientCa(&'static str),
+    #[error("{0}")]
+    Limits(#[from] LimitsError),
+    #[error("{0}")]
+    Roles(#[from] RolesError),
+}
+
+impl Config {
+    /// Read a config file; fields missing from the file take their default values
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let src = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.into(),
+            source,
+        })?;
+        toml::from_str(&src).map_err(|source| ConfigError::Parse {
+            path: path.into(),
+            source,
+        })
+    }
+
+    /// Check the config before starting the server, so that mistakes are reported up front
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.check(true)
+    }
+
+    /// Check the config before importing a snapshot, which writes the entities, schema and
+    /// policies files rather than reading them
+    pub fn validate_for_import(&self) -> Result<(), ConfigError> {
+        self.check(false)
+    }
+
+    fn check(&self, read_files: bool) -> Result<(), ConfigError> {
+        let mut files = Vec::new();
+        if read_files {
+            files.push(("entities", &self.entities));
+            files.push(("schema", &self.schema));
+            files.push(("policies", &self.policies));
+        }
+        if let Some(tls) = &self.tls {
+            files.push(("TLS certificate", &tls.cert));
+            files.push(("TLS key", &tls.key));
+            match &tls.client_ca {
+                Some(ca) => files.push(("TLS client CA", ca)),
+                None if tls.require_client_cert => {
+                    return Err(ConfigError::ClientCa("require_client_cert"))
+                }
+                None if !tls.client_principals.is_empty() => {
+                    return Err(ConfigError::ClientCa("client_principals"))
+                }
+                None => (),
+            }
+        }
+        for (name, path) in files {
+            if !path.is_file() {
+                return Err(ConfigError::MissingFile {
+                    name,
+                    path: path.clone(),
+                });
+            }
+        }
+        if let Some(dir) = &self.persistence_dir {
+            if dir.exists() && !dir.is_dir() {
+                return Err(ConfigError::NotADirectory(dir.clone()));
+            }
+        }
+        self.limits.validate()?;
+        roles::validate(&self.roles, SharingMode::compiled())?;
+        Ok(())
+    }
+}
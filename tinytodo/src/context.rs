@@ -14,36 +14,52 @@
  * limitations under the License.
  */
 
+use base64::{prelude::BASE64_STANDARD, Engine};
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use std::path::PathBuf;
-use tracing::{error, info, trace};
+use std::{collections::BTreeMap, path::PathBuf};
+use tracing::{error, field, info, info_span, trace, Span};
 
 use cedar_policy::{
-    Authorizer, CedarSchemaError, Context, Decision, Diagnostics, Entities, ParseErrors, PolicySet,
-    PolicySetError, Request, RequestBuilder, RestrictedExpression, Schema, SchemaError,
-    ValidationMode, Validator,
+    Authorizer, CedarSchemaError, Context, Decision, Diagnostics, Entities, EntitiesError,
+    ParseErrors, Policy, PolicySet, PolicySetError, PolicyToJsonError, Request, RequestBuilder,
+    Response, RestrictedExpression, Schema, SchemaError, ValidationMode, Validator,
 };
 
 use thiserror::Error;
 use tokio::sync::{
+    broadcast,
     mpsc::{Receiver, Sender},
     oneshot,
 };
 
 use crate::{
+    api::ShareRole,
     api::{
-        AddShare, CreateList, CreateTask, DeleteList, DeleteShare, DeleteTask, Empty, GetList,
-        GetLists, UpdateList, UpdateTask,
+        ActionCapability, AddShare, AttachToTask, Authorize, Capabilities, CommentOnTask,
+        CreateList, CreateTask, Decisions, DeleteAttachment, DeleteComment, DeleteList,
+        DeleteShare, DeleteTask, DuplicateList, EditComment, Empty, GetCapabilities, GetList,
+        GetListAccess, GetLists, ListAccess, Readiness, Subscribe, TransferOwnership, UpdateList,
+        UpdateTask,
     },
+    audit::{self, DecisionLog},
+    cache::DecisionCache,
+    config::Config,
     entitystore::{EntityDecodeError, EntityStore},
-    objects::List,
-    policy_store,
-    util::{EntityUid, ListUid, TYPE_LIST},
+    events::{Change, ChangeEvent, EVENT_CAPACITY},
+    limits::{LimitsError, PrincipalLimits},
+    lint::{self, LintConfig, Locations, Warnings},
+    metrics,
+    objects::{List, Task, User},
+    persistence::{self, PersistError},
+    policy_store, profiles,
+    roles::Roles,
+    shares::{self, TimedShare},
+    util::{EntityUid, ListUid, TeamUid, UserUid, TYPE_LIST, TYPE_TASK},
 };
 
 #[cfg(feature = "use-templates")]
-use crate::{api::ShareRole, util::UserOrTeamUid};
+use crate::util::UserOrTeamUid;
 #[cfg(feature = "use-templates")]
 use cedar_policy::{PolicyId, SlotId};
 
@@ -54,7 +70,13 @@ pub enum AppResponse {
     GetList(Box<List>),
     Euid(EntityUid),
     Lists(Vec<List>),
-    TaskId(i64),
+    ListAccess(ListAccess),
+    Capabilities(Capabilities),
+    Decisions(Decisions),
+    Readiness(Readiness),
+    /// The id of a new task, comment or attachment
+    Id(i64),
+    Subscription(broadcast::Receiver<ChangeEvent>),
     Unit(()),
 }
 
@@ -69,7 +91,7 @@ impl TryInto<i64> for AppResponse {
 
     fn try_into(self) -> std::result::Result<i64, Self::Error> {
         match self {
-            AppResponse::TaskId(id) => Ok(id),
+            AppResponse::Id(id) => Ok(id),
             _ => Err(Error::Type),
         }
     }
@@ -107,6 +129,56 @@ impl TryInto<Empty> for AppResponse {
     }
 }
 
+impl TryInto<ListAccess> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<ListAccess, Self::Error> {
+        match self {
+            AppResponse::ListAccess(a) => Ok(a),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Capabilities> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Capabilities, Self::Error> {
+        match self {
+            AppResponse::Capabilities(c) => Ok(c),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Decisions> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Decisions, Self::Error> {
+        match self {
+            AppResponse::Decisions(d) => Ok(d),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<Readiness> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<Readiness, Self::Error> {
+        match self {
+            AppResponse::Readiness(r) => Ok(r),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
+impl TryInto<broadcast::Receiver<ChangeEvent>> for AppResponse {
+    type Error = Error;
+    fn try_into(self) -> std::result::Result<broadcast::Receiver<ChangeEvent>, Self::Error> {
+        match self {
+            AppResponse::Subscription(r) => Ok(r),
+            _ => Err(Error::Type),
+        }
+    }
+}
+
 impl TryInto<Vec<List>> for AppResponse {
     type Error = Error;
     fn try_into(self) -> std::result::Result<Vec<List>, Self::Error> {
@@ -124,32 +196,132 @@ pub enum AppQueryKind {
     GetList(GetList),
     UpdateList(UpdateList),
     DeleteList(DeleteList),
+    TransferOwnership(TransferOwnership),
+    DuplicateList(DuplicateList),
 
     // Task CRUD
     CreateTask(CreateTask),
     UpdateTask(UpdateTask),
     DeleteTask(DeleteTask),
 
+    // Comments and attachments
+    CommentOnTask(CommentOnTask),
+    EditComment(EditComment),
+    DeleteComment(DeleteComment),
+    AttachToTask(AttachToTask),
+    DeleteAttachment(DeleteAttachment),
+
     // Lists
     GetLists(GetLists),
+    GetListAccess(GetListAccess),
+    GetCapabilities(GetCapabilities),
+    Authorize(Authorize),
 
     // Shares
     AddShare(AddShare),
     DeleteShare(DeleteShare),
+    // Sent periodically, so that shares are revoked as they expire
+    ExpireShares,
+
+    // Change notifications
+    Subscribe(Subscribe),
+    // Re-authorizes an existing subscription; not subject to rate limits
+    CheckSubscription(Subscribe),
 
-    // Policy Set Updates
-    UpdatePolicySet(PolicySet),
+    // Policy Set Updates; the policies as parsed from the file, and where they are in it
+    UpdatePolicySet(PolicySet, Locations),
+    // The policies file changed but could not be read or parsed
+    PolicyReloadFailed(String),
+
+    // Server lifecycle
+    Readiness,
+    CloseSubscriptions,
+    Shutdown,
+}
+
+impl AppQueryKind {
+    /// The user making the query, if it is made on behalf of a user
+    pub fn principal(&self) -> Option<&UserUid> {
+        self.principal_action().map(|(principal, _)| principal)
+    }
+
+    /// The principal making the query and the action it will be authorized for first, which
+    /// it is also rate limited under, if the query is made on behalf of a user. Queries that
+    /// are not Cedar actions themselves take the action of that first check: reading who may
+    /// act on a list and sharing it are `EditShare`, and reading capabilities and batch
+    /// authorization are `GetLists`, as subscribing to a list is `GetList`.
+    pub(crate) fn principal_action(&self) -> Option<(&UserUid, &EntityUid)> {
+        match self {
+            AppQueryKind::CreateList(r) => Some((&r.uid, &*ACTION_CREATE_LIST)),
+            AppQueryKind::GetList(r) => Some((&r.uid, &*ACTION_GET_LIST)),
+            AppQueryKind::UpdateList(r) => Some((&r.uid, &*ACTION_UPDATE_LIST)),
+            AppQueryKind::DeleteList(r) => Some((&r.uid, &*ACTION_DELETE_LIST)),
+            AppQueryKind::TransferOwnership(r) => Some((&r.uid, &*ACTION_TRANSFER_OWNERSHIP)),
+            AppQueryKind::DuplicateList(r) => Some((&r.uid, &*ACTION_DUPLICATE_LIST)),
+            AppQueryKind::CreateTask(r) => Some((&r.uid, &*ACTION_CREATE_TASK)),
+            AppQueryKind::UpdateTask(r) => Some((&r.uid, &*ACTION_UPDATE_TASK)),
+            AppQueryKind::DeleteTask(r) => Some((&r.uid, &*ACTION_DELETE_TASK)),
+            AppQueryKind::CommentOnTask(r) => Some((&r.uid, &*ACTION_COMMENT_ON_TASK)),
+            AppQueryKind::EditComment(r) => Some((&r.uid, &*ACTION_EDIT_COMMENT)),
+            AppQueryKind::DeleteComment(r) => Some((&r.uid, &*ACTION_DELETE_COMMENT)),
+            AppQueryKind::AttachToTask(r) => Some((&r.uid, &*ACTION_ATTACH_TO_TASK)),
+            AppQueryKind::DeleteAttachment(r) => Some((&r.uid, &*ACTION_DELETE_ATTACHMENT)),
+            AppQueryKind::GetLists(r) => Some((&r.uid, &*ACTION_GET_LISTS)),
+            AppQueryKind::GetListAccess(r) => Some((&r.uid, &*ACTION_EDIT_SHARE)),
+            AppQueryKind::GetCapabilities(r) => Some((&r.uid, &*ACTION_GET_LISTS)),
+            AppQueryKind::Authorize(r) => Some((&r.uid, &*ACTION_GET_LISTS)),
+            AppQueryKind::AddShare(r) => Some((&r.uid, &*ACTION_EDIT_SHARE)),
+            AppQueryKind::DeleteShare(r) => Some((&r.uid, &*ACTION_EDIT_SHARE)),
+            AppQueryKind::Subscribe(r) => Some((&r.uid, &*ACTION_GET_LIST)),
+            AppQueryKind::CheckSubscription(_)
+            | AppQueryKind::ExpireShares
+            | AppQueryKind::UpdatePolicySet(..)
+            | AppQueryKind::PolicyReloadFailed(_)
+            | AppQueryKind::Readiness
+            | AppQueryKind::CloseSubscriptions
+            | AppQueryKind::Shutdown => None,
+        }
+    }
+
+    /// Whether the query changes the entity store or the template-linked policies
+    fn is_mutation(&self) -> bool {
+        matches!(
+            self,
+            AppQueryKind::CreateList(_)
+                | AppQueryKind::UpdateList(_)
+                | AppQueryKind::DeleteList(_)
+                | AppQueryKind::TransferOwnership(_)
+                | AppQueryKind::DuplicateList(_)
+                | AppQueryKind::CreateTask(_)
+                | AppQueryKind::UpdateTask(_)
+                | AppQueryKind::DeleteTask(_)
+                | AppQueryKind::CommentOnTask(_)
+                | AppQueryKind::EditComment(_)
+                | AppQueryKind::DeleteComment(_)
+                | AppQueryKind::AttachToTask(_)
+                | AppQueryKind::DeleteAttachment(_)
+                | AppQueryKind::AddShare(_)
+                | AppQueryKind::DeleteShare(_)
+        )
+    }
 }
 
 #[derive(Debug)]
 pub struct AppQuery {
     kind: AppQueryKind,
     sender: oneshot::Sender<Result<AppResponse>>,
+    /// The sender's span, which parents the spans the application server creates for the
+    /// query, so that they join the trace of the request that made it
+    span: Span,
 }
 
 impl AppQuery {
     pub fn new(kind: AppQueryKind, sender: oneshot::Sender<Result<AppResponse>>) -> Self {
-        Self { kind, sender }
+        Self {
+            kind,
+            sender,
+            span: Span::current(),
+        }
     }
 }
 
@@ -169,8 +341,19 @@ pub enum ContextError {
     PolicySet(#[from] PolicySetError),
     #[error("Validation Failed: {0}")]
     Validation(String),
+    #[error("Policy Lint Failed:\n{0}")]
+    Lint(#[from] Warnings),
     #[error("Error Deserializing Json: {0}")]
     Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    Limits(#[from] LimitsError),
+    #[error("Error restoring saved state: {0}")]
+    Persistence(#[from] PersistError),
+    #[error("The schema must declare a `Task` entity type exactly when `task_entities` is set (it is {0})")]
+    TaskEntities(bool),
+    #[cfg(feature = "use-templates")]
+    #[error("The template {1} of share role {0} is not in the policies file")]
+    RoleTemplate(ShareRole, PolicyId),
 }
 
 #[derive(Debug, Error)]
@@ -179,10 +362,27 @@ pub enum Error {
     NoSuchEntity(EntityUid),
     #[error("Entity Decode Error: {0}")]
     EntityDecode(#[from] EntityDecodeError),
+    #[error("Invalid entities: {0}")]
+    Entities(#[from] EntitiesError),
     #[error("Authorization Denied")]
     AuthDenied(Diagnostics),
     #[error("The list {0} does not contain a task with id {1}")]
     InvalidTaskId(EntityUid, i64),
+    #[error("Task {1} of the list {0} does not have a comment with id {2}")]
+    InvalidCommentId(EntityUid, i64, i64),
+    #[error("Task {1} of the list {0} does not have an attachment with id {2}")]
+    InvalidAttachmentId(EntityUid, i64, i64),
+    #[error("Attachment data must be base64 encoded: {0}")]
+    InvalidAttachment(String),
+    #[error("Attachments may be at most {0} bytes")]
+    AttachmentTooLarge(usize),
+    #[error("There is no share role {0}")]
+    UnknownRole(ShareRole),
+    #[error("The share would expire at {0}, which has passed")]
+    ShareExpired(i64),
+    #[cfg(feature = "use-templates")]
+    #[error("Error making an expiring share: {0}")]
+    Share(#[from] shares::ShareError),
     #[error("Internal Error")]
     TokioSend(#[from] tokio::sync::mpsc::error::SendError<AppQuery>),
     #[error("Internal Error")]
@@ -195,8 +395,24 @@ pub enum Error {
     Policy(#[from] ParseErrors),
     #[error("Error updating PolicySet: {0}")]
     PolicySet(#[from] PolicySetError),
+    #[error("Policy Lint Failed: {0}")]
+    Lint(#[from] Warnings),
     #[error("Error constructing authorization request: {0}")]
     Request(String),
+    #[error("Error converting a residual policy to JSON: {0}")]
+    Residual(#[from] PolicyToJsonError),
+    #[error("The client certificate identifies {0}, but the request was made as {1}")]
+    ClientMismatch(EntityUid, EntityUid),
+    #[error("Rate limit exceeded for {0} performing {1}")]
+    RateLimited(EntityUid, EntityUid),
+    #[error("A batch may hold at most {0} authorization requests")]
+    BatchTooLarge(usize),
+    #[error("{0} already owns the maximum of {1} lists")]
+    ListQuotaExceeded(EntityUid, usize),
+    #[error("The list {0} already holds the maximum of {1} tasks")]
+    TaskQuotaExceeded(EntityUid, usize),
+    #[error("Task {1} of the list {0} already has the maximum of {2} attachments")]
+    AttachmentQuotaExceeded(EntityUid, i64, usize),
 }
 
 impl Error {
@@ -211,11 +427,20 @@ lazy_static! {
     static ref ACTION_UPDATE_TASK: EntityUid = r#"Action::"UpdateTask""#.parse().unwrap();
     static ref ACTION_CREATE_TASK: EntityUid = r#"Action::"CreateTask""#.parse().unwrap();
     static ref ACTION_DELETE_TASK: EntityUid = r#"Action::"DeleteTask""#.parse().unwrap();
+    static ref ACTION_COMMENT_ON_TASK: EntityUid = r#"Action::"CommentOnTask""#.parse().unwrap();
+    static ref ACTION_EDIT_COMMENT: EntityUid = r#"Action::"EditComment""#.parse().unwrap();
+    static ref ACTION_DELETE_COMMENT: EntityUid = r#"Action::"DeleteComment""#.parse().unwrap();
+    static ref ACTION_ATTACH_TO_TASK: EntityUid = r#"Action::"AttachToTask""#.parse().unwrap();
+    static ref ACTION_DELETE_ATTACHMENT: EntityUid =
+        r#"Action::"DeleteAttachment""#.parse().unwrap();
     static ref ACTION_GET_LISTS: EntityUid = r#"Action::"GetLists""#.parse().unwrap();
     static ref ACTION_GET_LIST: EntityUid = r#"Action::"GetList""#.parse().unwrap();
     static ref ACTION_CREATE_LIST: EntityUid = r#"Action::"CreateList""#.parse().unwrap();
     static ref ACTION_UPDATE_LIST: EntityUid = r#"Action::"UpdateList""#.parse().unwrap();
     static ref ACTION_DELETE_LIST: EntityUid = r#"Action::"DeleteList""#.parse().unwrap();
+    static ref ACTION_TRANSFER_OWNERSHIP: EntityUid =
+        r#"Action::"TransferOwnership""#.parse().unwrap();
+    static ref ACTION_DUPLICATE_LIST: EntityUid = r#"Action::"DuplicateList""#.parse().unwrap();
 }
 
 pub struct AppContext {
@@ -224,6 +449,25 @@ pub struct AppContext {
     policies: PolicySet,
     schema: Schema,
     recv: Receiver<AppQuery>,
+    events: broadcast::Sender<ChangeEvent>,
+    limits: PrincipalLimits,
+    max_lists_per_user: Option<usize>,
+    max_tasks_per_list: Option<usize>,
+    max_attachment_bytes: Option<usize>,
+    max_attachments_per_task: Option<usize>,
+    persistence_dir: Option<PathBuf>,
+    policy_reload_error: Option<String>,
+    decisions: DecisionLog,
+    roles: Roles,
+    /// The policies for the configured share roles, which are added to every policy set
+    #[cfg(not(feature = "use-templates"))]
+    role_policies: Vec<Policy>,
+    /// The policies of the enabled policy profiles, which are added to every policy set
+    profile_policies: Vec<Policy>,
+    cache: Option<DecisionCache>,
+    lint: LintConfig,
+    /// When the soonest share expires, so that shares are only looked over once one has
+    next_expiry: Option<i64>,
 }
 
 impl std::fmt::Debug for AppContext {
@@ -290,41 +534,154 @@ fn rename_from_id_annotation(ps: PolicySet) -> std::result::Result<PolicySet, Re
     Ok(new_ps)
 }
 
+/// Parse policies, naming policies and templates after their `@id` annotations
+pub(crate) fn parse_policies(src: &str) -> std::result::Result<PolicySet, ContextError> {
+    Ok(rename_from_id_annotation(src.parse()?)?)
+}
+
+/// The most requests an `Authorize` batch may hold
+pub const MAX_BATCH: usize = 100;
+
+/// The context for authorizing a change to a comment or attachment by `author`
+fn author_context(author: &UserUid) -> [(String, RestrictedExpression); 1] {
+    let author = RestrictedExpression::new_entity_uid(EntityUid::from(author.clone()).into());
+    [("author".to_string(), author)]
+}
+
+/// A request context holding `pairs` and, as every context does, the time as `now`
+fn request_context(
+    pairs: impl IntoIterator<Item = (String, RestrictedExpression)>,
+) -> Result<Context> {
+    let now = (
+        "now".to_string(),
+        RestrictedExpression::new_long(shares::now()),
+    );
+    Context::from_pairs(pairs.into_iter().chain(std::iter::once(now)))
+        .map_err(|e| Error::Request(e.to_string()))
+}
+
 impl AppContext {
     #[tracing::instrument(skip_all)]
-    pub fn spawn(
-        entities_path: impl Into<PathBuf>,
-        schema_path: impl Into<PathBuf>,
-        policies_path: impl Into<PathBuf>,
-    ) -> std::result::Result<Sender<AppQuery>, ContextError> {
-        let schema_path = schema_path.into();
-        let policies_path = policies_path.into();
-        let schema_file = std::fs::File::open(&schema_path)?;
+    pub fn spawn(config: &Config) -> std::result::Result<Sender<AppQuery>, ContextError> {
+        let policies_path = config.policies.clone();
+        let schema_file = std::fs::File::open(&config.schema)?;
         let (schema, _) = Schema::from_cedarschema_file(schema_file)?;
+        if schema.entity_types().any(|ty| ty == &*TYPE_TASK) != config.task_entities {
+            return Err(ContextError::TaskEntities(config.task_entities));
+        }
 
-        let entities_file = std::fs::File::open(entities_path.into())?;
-        let entities = serde_json::from_reader(entities_file)?;
+        let persistence_dir = config.persistence_dir.clone();
+        let saved = match &persistence_dir {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)?;
+                persistence::load_entities(dir)?
+            }
+            None => None,
+        };
+        let mut entities: EntityStore = match saved {
+            Some(entities) => {
+                info!("Restored entities from the persistence directory");
+                entities
+            }
+            None => {
+                let entities_file = std::fs::File::open(&config.entities)?;
+                serde_json::from_reader(entities_file)?
+            }
+        };
+        entities.set_task_entities(config.task_entities);
 
         let policy_src = std::fs::read_to_string(&policies_path)?;
-        let policies0 = policy_src.parse()?;
-        let policies = rename_from_id_annotation(policies0)?;
+        let parsed: PolicySet = policy_src.parse()?;
+        let locations = Locations::new(&policies_path, &policy_src, &parsed);
+        let roles = Roles::new(&config.roles);
+        #[cfg(not(feature = "use-templates"))]
+        let role_policies = roles.policies(config.task_entities)?;
+        let profile_policies = profiles::policies(&config.profiles, config.task_entities)?;
+        #[cfg(not(feature = "use-templates"))]
+        let added = role_policies.iter().chain(&profile_policies);
+        #[cfg(feature = "use-templates")]
+        let added = profile_policies.iter();
+        let collisions = locations.collisions(added.map(Policy::id));
+        if !collisions.is_empty() {
+            return Err(ContextError::Lint(Warnings(collisions)));
+        }
+        #[allow(unused_mut)]
+        let mut policies = rename_from_id_annotation(parsed)?;
+        let reserved = lint::reserved(&policies, &locations, shares::EXPIRING_SHARE_PREFIX);
+        if !reserved.is_empty() {
+            return Err(ContextError::Lint(Warnings(reserved)));
+        }
+        #[cfg(feature = "use-templates")]
+        {
+            for role in roles.iter() {
+                let tid = roles.template_id(&role).expect("every role has a template");
+                if policies.template(&tid).is_none() {
+                    return Err(ContextError::RoleTemplate(role, tid));
+                }
+            }
+            if let Some(dir) = &persistence_dir {
+                persistence::restore_links(dir, &mut policies, &roles)?;
+            }
+        }
+        #[cfg(not(feature = "use-templates"))]
+        for policy in &role_policies {
+            policies.add(policy.clone())?;
+        }
+        for policy in &profile_policies {
+            policies.add(policy.clone())?;
+        }
+        if !config.profiles.is_empty() {
+            info!("Policy profiles: {}", config.profiles.iter().join(", "));
+        }
+        let limits = PrincipalLimits::new(&config.limits)?;
+        let max_lists_per_user = config.limits.max_lists_per_user;
+        let max_tasks_per_list = config.limits.max_tasks_per_list;
+        let max_attachment_bytes = config.limits.max_attachment_bytes;
+        let max_attachments_per_task = config.limits.max_attachments_per_task;
+        let decisions = DecisionLog::new(&config.audit);
+        let lint = config.lint.clone();
         let validator = Validator::new(schema.clone());
         let output = validator.validate(&policies, ValidationMode::default());
         if output.validation_passed() {
             info!("Validation passed!");
+            lint::check(&policies, &locations, &roles.templates(), &lint)?;
+            metrics::POLICIES.set(policies.policies().count() as i64);
             let authorizer = Authorizer::new();
+            let cache = config
+                .decision_cache
+                .as_ref()
+                .map(|config| DecisionCache::new(config, &policies));
             let (send, recv) = tokio::sync::mpsc::channel(100);
             let tx = send.clone();
+            let (events, _) = broadcast::channel(EVENT_CAPACITY);
             tokio::spawn(async move {
                 info!("Serving application server!");
+                shares::spawn_sweeper(tx.clone());
                 policy_store::spawn_watcher(policies_path, tx).await;
-                let c = Self {
+                let mut c = Self {
                     entities,
                     authorizer,
                     policies,
                     schema,
                     recv,
+                    events,
+                    limits,
+                    max_lists_per_user,
+                    max_tasks_per_list,
+                    max_attachment_bytes,
+                    max_attachments_per_task,
+                    persistence_dir,
+                    policy_reload_error: None,
+                    decisions,
+                    roles,
+                    #[cfg(not(feature = "use-templates"))]
+                    role_policies,
+                    profile_policies,
+                    cache,
+                    lint,
+                    next_expiry: None,
                 };
+                c.next_expiry = c.soonest_expiry();
                 c.serve().await
             });
 
@@ -340,36 +697,191 @@ impl AppContext {
 
     #[tracing::instrument]
     async fn serve(mut self) -> Result<()> {
-        loop {
-            if let Some(msg) = self.recv.recv().await {
-                let r = match msg.kind {
-                    AppQueryKind::GetList(r) => self.get_list(r),
-                    AppQueryKind::CreateList(r) => self.create_list(r),
-                    AppQueryKind::UpdateList(r) => self.update_list(r),
-                    AppQueryKind::DeleteList(r) => self.delete_list(r),
-                    AppQueryKind::CreateTask(r) => self.create_task(r),
-                    AppQueryKind::UpdateTask(r) => self.update_task(r),
-                    AppQueryKind::DeleteTask(r) => self.delete_task(r),
-                    AppQueryKind::GetLists(r) => self.get_lists(r),
-                    AppQueryKind::AddShare(r) => self.add_share(r),
-                    AppQueryKind::DeleteShare(r) => self.delete_share(r),
-                    AppQueryKind::UpdatePolicySet(set) => self.update_policy_set(set),
+        while let Some(msg) = self.recv.recv().await {
+            if let AppQueryKind::Shutdown = msg.kind {
+                // Every query sent before this one has been answered
+                self.persist();
+                info!("Application server stopped");
+                let _ = msg.sender.send(Ok(AppResponse::Unit(())));
+                break;
+            }
+            let span = info_span!(parent: &msg.span, "handle_query");
+            let r = span.in_scope(|| {
+                self.revoke_expired_shares();
+                let mutation = msg.kind.is_mutation();
+                let r = match self.rate_limit(&msg.kind) {
+                    Ok(()) => self.dispatch(msg.kind),
+                    Err(e) => Err(e),
                 };
-                if let Err(e) = msg.sender.send(r) {
-                    trace!("Failed send response: {:?}", e);
+                if mutation && r.is_ok() {
+                    self.persist();
                 }
+                r
+            });
+            if let Err(e) = msg.sender.send(r) {
+                trace!("Failed send response: {:?}", e);
             }
         }
+        Ok(())
+    }
+
+    /// Stop the application actor once every query already sent to it has been answered,
+    /// saving its state to the persistence directory, if one is configured
+    pub async fn shutdown(chan: &Sender<AppQuery>) -> Result<()> {
+        let (send, recv) = oneshot::channel();
+        chan.send(AppQuery::new(AppQueryKind::Shutdown, send))
+            .await?;
+        recv.await??;
+        Ok(())
+    }
+
+    fn dispatch(&mut self, kind: AppQueryKind) -> Result<AppResponse> {
+        match kind {
+            AppQueryKind::GetList(r) => self.get_list(r),
+            AppQueryKind::CreateList(r) => self.create_list(r),
+            AppQueryKind::UpdateList(r) => self.update_list(r),
+            AppQueryKind::DeleteList(r) => self.delete_list(r),
+            AppQueryKind::TransferOwnership(r) => self.transfer_ownership(r),
+            AppQueryKind::DuplicateList(r) => self.duplicate_list(r),
+            AppQueryKind::CreateTask(r) => self.create_task(r),
+            AppQueryKind::UpdateTask(r) => self.update_task(r),
+            AppQueryKind::DeleteTask(r) => self.delete_task(r),
+            AppQueryKind::CommentOnTask(r) => self.comment_on_task(r),
+            AppQueryKind::EditComment(r) => self.edit_comment(r),
+            AppQueryKind::DeleteComment(r) => self.delete_comment(r),
+            AppQueryKind::AttachToTask(r) => self.attach_to_task(r),
+            AppQueryKind::DeleteAttachment(r) => self.delete_attachment(r),
+            AppQueryKind::GetLists(r) => self.get_lists(r),
+            AppQueryKind::GetListAccess(r) => self.get_list_access(r),
+            AppQueryKind::GetCapabilities(r) => self.get_capabilities(r),
+            AppQueryKind::Authorize(r) => self.authorize(r),
+            AppQueryKind::AddShare(r) => self.add_share(r),
+            AppQueryKind::DeleteShare(r) => self.delete_share(r),
+            // Expired shares are revoked before every query is dispatched
+            AppQueryKind::ExpireShares => Ok(AppResponse::Unit(())),
+            AppQueryKind::Subscribe(r) => self.subscribe(r),
+            AppQueryKind::CheckSubscription(r) => self.check_subscription(r),
+            AppQueryKind::UpdatePolicySet(set, locations) => {
+                let r = self.update_policy_set(set, &locations);
+                if let Err(e) = &r {
+                    self.policy_reload_error = Some(e.to_string());
+                }
+                metrics::record_reload(self.policy_reload_error.is_none());
+                r
+            }
+            AppQueryKind::PolicyReloadFailed(e) => {
+                metrics::record_reload(false);
+                self.policy_reload_error = Some(e);
+                Ok(AppResponse::Unit(()))
+            }
+            AppQueryKind::Readiness => Ok(self.readiness()),
+            AppQueryKind::CloseSubscriptions => {
+                // Subscribers see the old channel close, which ends their event streams
+                self.events = broadcast::channel(EVENT_CAPACITY).0;
+                Ok(AppResponse::Unit(()))
+            }
+            AppQueryKind::Shutdown => Ok(AppResponse::Unit(())),
+        }
+    }
+
+    fn readiness(&self) -> AppResponse {
+        AppResponse::Readiness(Readiness {
+            policies: self.policies.policies().count(),
+            templates: self.policies.templates().count(),
+            policy_reload_error: self.policy_reload_error.clone(),
+        })
+    }
+
+    // Save the application state, if a persistence directory is configured.
+    // Failures are logged rather than reported, since the change itself has already been made.
+    fn persist(&self) {
+        let Some(dir) = &self.persistence_dir else {
+            return;
+        };
+        if let Err(e) = persistence::save_entities(dir, &self.entities) {
+            error!("Error saving entities: {e}");
+        }
+        #[cfg(feature = "use-templates")]
+        if let Err(e) = persistence::save_links(dir, &self.policies, &self.roles) {
+            error!("Error saving linked policies: {e}");
+        }
+    }
+
+    fn record_policy_count(&self) {
+        metrics::POLICIES.set(self.policies.policies().count() as i64);
+    }
+
+    // Every change to the policies may change any decision
+    fn invalidate_decisions(&mut self) {
+        if let Some(cache) = &mut self.cache {
+            cache.reset(&self.policies);
+        }
+    }
+
+    fn rate_limit(&mut self, kind: &AppQueryKind) -> Result<()> {
+        match kind.principal_action() {
+            Some((principal, action)) if !self.limits.check(principal.as_ref(), action) => {
+                Err(Error::RateLimited(principal.clone().into(), action.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The policies the server adds to those in the policies file
+    fn added_policies(&self) -> impl Iterator<Item = &Policy> {
+        #[cfg(not(feature = "use-templates"))]
+        let role_policies = self.role_policies.iter();
+        #[cfg(feature = "use-templates")]
+        let role_policies = std::iter::empty();
+        role_policies.chain(&self.profile_policies)
     }
 
-    #[tracing::instrument(skip(policy_set))]
-    fn update_policy_set(&mut self, policy_set: PolicySet) -> Result<AppResponse> {
+    #[tracing::instrument(skip(policy_set, locations))]
+    fn update_policy_set(
+        &mut self,
+        policy_set: PolicySet,
+        locations: &Locations,
+    ) -> Result<AppResponse> {
+        let collisions = locations.collisions(self.added_policies().map(Policy::id));
+        if !collisions.is_empty() {
+            return Err(Warnings(collisions).into());
+        }
         let mut new_policies = rename_from_id_annotation(policy_set)?;
-        let mut err = false;
+        let reserved = lint::reserved(&new_policies, locations, shares::EXPIRING_SHARE_PREFIX);
+        if !reserved.is_empty() {
+            return Err(Warnings(reserved).into());
+        }
+        for policy in self.added_policies() {
+            new_policies.add(policy.clone())?;
+        }
+        let mut err = None;
         // for each existing template-linked policy,
         //   link against the new version of the template in the new policy set if present
         for p in self.policies.policies() {
             match p.template_id() {
+                #[cfg(feature = "use-templates")]
+                None => {
+                    // an expiring share; remake it from the new version of its template
+                    if let Some(share) = shares::timed_share(p) {
+                        let policy = self
+                            .roles
+                            .template_id(&share.role)
+                            .ok_or_else(|| shares::ShareError::NoRole(share.role.clone()))
+                            .and_then(|tid| {
+                                shares::expiring_policy(&new_policies, tid, p.id().clone(), &share)
+                            });
+                        match policy {
+                            Ok(policy) => new_policies.add(policy)?,
+                            Err(e) => {
+                                let msg =
+                                    format!("Could not remake the expiring share {}: {e}", p.id());
+                                error!("Error when reloading policies: {msg}");
+                                err = Some(msg);
+                            }
+                        }
+                    }
+                }
+                #[cfg(not(feature = "use-templates"))]
                 None => (), // not a template-linked policy
                 Some(tid) => {
                     // template-linked policy
@@ -377,8 +889,9 @@ impl AppContext {
                         None => {
                             // template not in new policy set
                             let pid = p.id();
-                            err = true;
-                            error!("Error when reloading policies: Could not find policy template {tid} to link {pid}")
+                            let msg = format!("Could not find policy template {tid} to link {pid}");
+                            error!("Error when reloading policies: {msg}");
+                            err = Some(msg);
                         }
                         Some(_) => {
                             // found template in new policy set; link into new policy set
@@ -392,29 +905,41 @@ impl AppContext {
             }
         }
         // no error during relinking; now validate policies
-        if !err {
+        if err.is_none() {
             let validator = Validator::new(self.schema.clone());
             let output = validator.validate(&new_policies, ValidationMode::default());
             if !output.validation_passed() {
                 for e in output.validation_errors() {
                     error!("Error validating linked policies: {e}")
                 }
+                let errors = output.validation_errors().map(|e| e.to_string()).join("\n");
+                err = Some(format!("Validation Failed: {errors}"));
+            } else if let Err(warnings) = lint::check(
+                &new_policies,
+                locations,
+                &self.roles.templates(),
+                &self.lint,
+            ) {
+                error!("Error when reloading policies: policy lint failed");
+                err = Some(format!("Policy Lint Failed: {warnings}"));
             } else {
                 self.policies = new_policies;
-                info!("Reloaded policy set")
+                self.invalidate_decisions();
+                self.record_policy_count();
+                info!("Reloaded policy set");
+                self.notify(None, Change::PoliciesReloaded);
             }
         }
+        self.policy_reload_error = err;
         Ok(AppResponse::Unit(()))
     }
 
     // Computes the name of the template-linked policy; only relevant with "use-templates" feature enabled
     // This function is injective, ensuring that different share permissions will have different policy IDs
+    // (role names differ in more than case)
     #[cfg(feature = "use-templates")]
-    fn linked_policy_id(role: ShareRole, target: UserOrTeamUid, list: ListUid) -> PolicyId {
-        let pid_prefix = match role {
-            ShareRole::Reader => "reader",
-            ShareRole::Editor => "editor",
-        };
+    fn linked_policy_id(role: &ShareRole, target: UserOrTeamUid, list: ListUid) -> PolicyId {
+        let pid_prefix = role.as_str().to_lowercase();
         let target_eid = target.as_ref().id().escaped();
         // Note: A List EID is controlled by TinyTodo, and will always be a number
         let list_eid = list.as_ref().id().escaped();
@@ -423,100 +948,443 @@ impl AppContext {
 
     fn add_share(&mut self, r: AddShare) -> Result<AppResponse> {
         self.is_authorized(&r.uid, &*ACTION_EDIT_SHARE, &r.list)?;
+        if let Some(expires) = r.expires {
+            if expires <= shares::now() {
+                return Err(Error::ShareExpired(expires));
+            }
+        }
+        if !self.roles.contains(&r.role) {
+            return Err(Error::UnknownRole(r.role));
+        }
         #[cfg(feature = "use-templates")]
         {
             // Confirm that the identified list and sharer are known
             let _list = self.entities.get_list(&r.list)?;
-            let _target_entity = self.entities.get_user_or_team_mut(&r.share_with)?;
-            // Link a template to register the new permission
-            let tid = match r.role {
-                ShareRole::Reader => PolicyId::new("reader-template"),
-                ShareRole::Editor => PolicyId::new("editor-template"),
+            let _target_entity = self.entities.get_user_or_team(&r.share_with)?;
+            let tid = self.roles.template_id(&r.role).expect("the role is known");
+            let linked = Self::linked_policy_id(&r.role, r.share_with.clone(), r.list.clone());
+            // As with teams, sharing again replaces the share, and its expiry
+            let expiring = shares::expiring_id(&linked);
+            for pid in [&linked, &expiring] {
+                if self.policies.policy(pid).is_some() {
+                    self.remove_share_policy(pid.clone())?;
+                }
+            }
+            let pid = match r.expires {
+                // An expiring share is the linked template with a condition on the time
+                Some(expires) => {
+                    let share = TimedShare {
+                        list: r.list.clone(),
+                        target: r.share_with,
+                        role: r.role,
+                        expires,
+                    };
+                    let policy =
+                        shares::expiring_policy(&self.policies, tid, expiring.clone(), &share)?;
+                    self.policies.add(policy)?;
+                    self.next_expiry = Some(self.next_expiry.map_or(expires, |t| t.min(expires)));
+                    expiring
+                }
+                None => {
+                    // Link a template to register the new permission
+                    // Construct template linking environment
+                    let target_euid: &cedar_policy::EntityUid = r.share_with.as_ref();
+                    let list_euid: &cedar_policy::EntityUid = r.list.as_ref();
+                    let env: std::collections::HashMap<SlotId, cedar_policy::EntityUid> = [
+                        (SlotId::principal(), target_euid.clone()),
+                        (SlotId::resource(), list_euid.clone()),
+                    ]
+                    .into_iter()
+                    .collect();
+                    // Link it!
+                    self.policies.link(tid, linked.clone(), env)?;
+                    linked
+                }
             };
-            // Construct template linking environment
-            let target_euid: &cedar_policy::EntityUid = r.share_with.as_ref();
-            let list_euid: &cedar_policy::EntityUid = r.list.as_ref();
-            let env: std::collections::HashMap<SlotId, cedar_policy::EntityUid> = [
-                (SlotId::principal(), target_euid.clone()),
-                (SlotId::resource(), list_euid.clone()),
-            ]
-            .into_iter()
-            .collect();
-            // Link it!
-            let pid = Self::linked_policy_id(r.role, r.share_with, r.list);
-            self.policies.link(tid, pid.clone(), env)?;
+            self.invalidate_decisions();
+            self.record_policy_count();
             info!("Created policy {pid}");
         }
         #[cfg(not(feature = "use-templates"))]
         {
-            let list = self.entities.get_list(&r.list)?;
-            let team_uid = list.get_team(r.role).clone();
+            let _target_entity = self.entities.get_user_or_team(&r.share_with)?;
+            let team_uid = self.entities.role_team(&r.list, &r.role)?;
             let target_entity = self.entities.get_user_or_team_mut(&r.share_with)?;
             target_entity.insert_parent(team_uid);
+            self.entities
+                .set_share_expiry(&r.list, &r.share_with, &r.role, r.expires);
+            if let Some(expires) = r.expires {
+                self.next_expiry = Some(self.next_expiry.map_or(expires, |t| t.min(expires)));
+            }
         }
+        self.notify(Some(&r.list), Change::ShareAdded);
         Ok(AppResponse::Unit(()))
     }
 
+    // Unlink the policies sharing a deleted list. Otherwise they would still permit their
+    // actions on the (now nonexistent) list, unlike the teams of the team-based build.
+    #[cfg(feature = "use-templates")]
+    fn unlink_shares(&mut self, list: &ListUid) -> Result<()> {
+        let list_euid: &cedar_policy::EntityUid = list.as_ref();
+        let linked = self
+            .policies
+            .policies()
+            .filter(|p| {
+                p.template_links()
+                    .is_some_and(|links| links.get(&SlotId::resource()) == Some(list_euid))
+                    || shares::timed_share(p).is_some_and(|share| &share.list == list)
+            })
+            .map(|p| p.id().clone())
+            .collect::<Vec<_>>();
+        for pid in linked {
+            self.remove_share_policy(pid)?;
+        }
+        self.record_policy_count();
+        Ok(())
+    }
+
+    // Shares are template-linked policies, except for expiring shares, which are static
+    #[cfg(feature = "use-templates")]
+    fn remove_share_policy(&mut self, pid: PolicyId) -> Result<()> {
+        match self
+            .policies
+            .policy(&pid)
+            .map(|p| p.template_id().is_some())
+        {
+            Some(false) => self.policies.remove_static(pid.clone()).map(|_| ())?,
+            _ => self.policies.unlink(pid.clone()).map(|_| ())?,
+        }
+        self.invalidate_decisions();
+        info!("Removed policy {pid}");
+        Ok(())
+    }
+
+    /// When the soonest of the expiring shares expires, if there are any
+    fn soonest_expiry(&self) -> Option<i64> {
+        #[cfg(feature = "use-templates")]
+        let expiries = self.policies.policies().filter_map(shares::timed_share);
+        #[cfg(not(feature = "use-templates"))]
+        let expiries = self.entities.timed_shares();
+        expiries.map(|share| share.expires).min()
+    }
+
+    /// Revoke the shares that have expired, logging each one. Nothing is looked over until
+    /// the soonest expiry passes; removing a share early leaves it, at worst, too soon.
+    fn revoke_expired_shares(&mut self) {
+        let now = shares::now();
+        if !self.next_expiry.is_some_and(|expires| expires <= now) {
+            return;
+        }
+        #[cfg(feature = "use-templates")]
+        let expired: Vec<TimedShare> = {
+            let expired = self
+                .policies
+                .policies()
+                .filter_map(|p| Some((p.id().clone(), shares::timed_share(p)?)))
+                .filter(|(_, share)| share.is_expired(now))
+                .collect::<Vec<_>>();
+            for (pid, _) in &expired {
+                if let Err(e) = self.remove_share_policy(pid.clone()) {
+                    error!("Error revoking the expired share {pid}: {e}");
+                }
+            }
+            expired.into_iter().map(|(_, share)| share).collect()
+        };
+        #[cfg(not(feature = "use-templates"))]
+        let expired: Vec<TimedShare> = {
+            let expired = self.entities.take_expired_shares(now);
+            for share in &expired {
+                let Ok(list) = self.entities.get_list(&share.list) else {
+                    continue;
+                };
+                let Some(team_uid) = list.get_team(&share.role).cloned() else {
+                    continue;
+                };
+                if let Ok(target_entity) = self.entities.get_user_or_team_mut(&share.target) {
+                    target_entity.delete_parent(&team_uid);
+                }
+            }
+            expired
+        };
+        self.next_expiry = self.soonest_expiry();
+        if expired.is_empty() {
+            return;
+        }
+        #[cfg(feature = "use-templates")]
+        self.record_policy_count();
+        for share in expired {
+            audit::share_expired(&share);
+            self.notify(Some(&share.list), Change::ShareRemoved);
+        }
+        self.persist();
+    }
+
     fn delete_share(&mut self, r: DeleteShare) -> Result<AppResponse> {
         self.is_authorized(&r.uid, &*ACTION_EDIT_SHARE, &r.list)?;
+        if !self.roles.contains(&r.role) {
+            return Err(Error::UnknownRole(r.role));
+        }
         #[cfg(feature = "use-templates")]
         {
             // Confirm that the identified list and un-sharer are known
             let _list = self.entities.get_list(&r.list)?;
-            let _target_entity = self.entities.get_user_or_team_mut(&r.unshare_with)?;
-            // Unlink the policy that provided the permission
-            let pid = Self::linked_policy_id(r.role, r.unshare_with, r.list);
-            self.policies.unlink(pid.clone())?;
-            info!("Removed policy {pid}");
+            let _target_entity = self.entities.get_user_or_team(&r.unshare_with)?;
+            // Unlink the policy that provided the permission. As with teams, removing a share
+            // that was never made does nothing.
+            let linked = Self::linked_policy_id(&r.role, r.unshare_with, r.list.clone());
+            let expiring = shares::expiring_id(&linked);
+            let mut removed = false;
+            for pid in [linked, expiring] {
+                if self.policies.policy(&pid).is_some() {
+                    self.remove_share_policy(pid)?;
+                    removed = true;
+                }
+            }
+            if removed {
+                self.record_policy_count();
+            }
         }
         #[cfg(not(feature = "use-templates"))]
         {
             let list = self.entities.get_list(&r.list)?;
-            let team_uid = list.get_team(r.role).clone();
-            let target_entity = self.entities.get_user_or_team_mut(&r.unshare_with)?;
-            target_entity.delete_parent(&team_uid);
+            // The list has no team for a role it was never shared in
+            let team_uid = list.get_team(&r.role).cloned();
+            let _target_entity = self.entities.get_user_or_team(&r.unshare_with)?;
+            if let Some(team_uid) = team_uid {
+                self.entities
+                    .get_user_or_team_mut(&r.unshare_with)?
+                    .delete_parent(&team_uid);
+            }
+            self.entities
+                .set_share_expiry(&r.list, &r.unshare_with, &r.role, None);
         }
+        self.notify(Some(&r.list), Change::ShareRemoved);
         Ok(AppResponse::Unit(()))
     }
 
     fn update_task(&mut self, r: UpdateTask) -> Result<AppResponse> {
-        self.is_authorized(&r.uid, &*ACTION_UPDATE_TASK, &r.list)?;
+        let resource = self.task_resource(&r.uid, &r.list, r.task)?;
+        self.is_authorized(&r.uid, &*ACTION_UPDATE_TASK, &resource)?;
+        if let Some(Some(assignee)) = &r.assignee {
+            self.entities.get_user(assignee)?;
+        }
         let list = self.entities.get_list_mut(&r.list)?;
         let task = list
             .get_task_mut(r.task)
-            .ok_or_else(|| Error::InvalidTaskId(r.list.into(), r.task))?;
+            .ok_or_else(|| Error::InvalidTaskId(r.list.clone().into(), r.task))?;
         if let Some(state) = r.state {
             task.set_state(state);
         }
         if let Some(name) = r.name {
             task.set_name(name);
         }
+        if let Some(assignee) = r.assignee {
+            task.set_assignee(assignee);
+        }
+        self.notify(Some(&r.list), Change::TaskUpdated { task: r.task });
         Ok(AppResponse::Unit(()))
     }
 
     fn create_task(&mut self, r: CreateTask) -> Result<AppResponse> {
         self.is_authorized(&r.uid, &*ACTION_CREATE_TASK, &r.list)?;
+        if let Some(assignee) = &r.assignee {
+            self.entities.get_user(assignee)?;
+        }
         let list = self.entities.get_list_mut(&r.list)?;
-        let task_id = list.create_task(r.name);
-        Ok(AppResponse::TaskId(task_id))
+        if let Some(max) = self.max_tasks_per_list {
+            if list.num_tasks() >= max {
+                return Err(Error::TaskQuotaExceeded(r.list.into(), max));
+            }
+        }
+        let task_id = list.create_task(r.name, r.assignee);
+        self.notify(Some(&r.list), Change::TaskCreated { task: task_id });
+        Ok(AppResponse::Id(task_id))
     }
 
     fn delete_task(&mut self, r: DeleteTask) -> Result<AppResponse> {
-        self.is_authorized(&r.uid, &*ACTION_DELETE_TASK, &r.list)?;
+        let resource = self.task_resource(&r.uid, &r.list, r.task)?;
+        self.is_authorized(&r.uid, &*ACTION_DELETE_TASK, &resource)?;
         let list = self.entities.get_list_mut(&r.list)?;
         list.delete_task(r.task)
-            .ok_or_else(|| Error::InvalidTaskId(r.list.into(), r.task))?;
+            .ok_or_else(|| Error::InvalidTaskId(r.list.clone().into(), r.task))?;
+        self.notify(Some(&r.list), Change::TaskDeleted { task: r.task });
+        Ok(AppResponse::Unit(()))
+    }
+
+    /// The resource that `uid`'s actions on a task are authorized against
+    fn task_resource(&self, uid: &UserUid, list: &ListUid, task: i64) -> Result<EntityUid> {
+        self.entities
+            .task_resource(list, task)
+            .or_else(|e| self.not_found(uid, list, e))
+    }
+
+    // That a task, comment or attachment is missing is only reported to users who may read
+    // its list, so that others cannot find out which ones exist
+    fn not_found<T>(&self, uid: &UserUid, list: &ListUid, e: Error) -> Result<T> {
+        self.is_authorized(uid, &*ACTION_GET_LIST, list)?;
+        Err(e)
+    }
+
+    fn get_task(&self, list: &ListUid, task: i64) -> Result<&Task> {
+        self.entities
+            .get_list(list)?
+            .get_task(task)
+            .ok_or_else(|| Error::InvalidTaskId(list.clone().into(), task))
+    }
+
+    fn get_task_mut(&mut self, list: &ListUid, task: i64) -> Result<&mut Task> {
+        self.entities
+            .get_list_mut(list)?
+            .get_task_mut(task)
+            .ok_or_else(|| Error::InvalidTaskId(list.clone().into(), task))
+    }
+
+    fn comment_on_task(&mut self, r: CommentOnTask) -> Result<AppResponse> {
+        let resource = self.task_resource(&r.uid, &r.list, r.task)?;
+        self.is_authorized(&r.uid, &*ACTION_COMMENT_ON_TASK, &resource)?;
+        let comment = self
+            .get_task_mut(&r.list, r.task)?
+            .add_comment(r.uid, r.body);
+        self.notify(
+            Some(&r.list),
+            Change::CommentAdded {
+                task: r.task,
+                comment,
+            },
+        );
+        Ok(AppResponse::Id(comment))
+    }
+
+    // Changes to a comment are authorized with its author in the context, so that
+    // policies can let authors edit their own comments
+    fn edit_comment(&mut self, r: EditComment) -> Result<AppResponse> {
+        let author = self.comment_author(&r.uid, &r.list, r.task, r.comment)?;
+        self.is_authorized_in_context(
+            &r.uid,
+            &*ACTION_EDIT_COMMENT,
+            &self.task_resource(&r.uid, &r.list, r.task)?,
+            author_context(&author),
+        )?;
+        if let Some(comment) = self
+            .get_task_mut(&r.list, r.task)?
+            .get_comment_mut(r.comment)
+        {
+            comment.edit(r.body);
+        }
+        self.notify(
+            Some(&r.list),
+            Change::CommentUpdated {
+                task: r.task,
+                comment: r.comment,
+            },
+        );
+        Ok(AppResponse::Unit(()))
+    }
+
+    fn delete_comment(&mut self, r: DeleteComment) -> Result<AppResponse> {
+        let author = self.comment_author(&r.uid, &r.list, r.task, r.comment)?;
+        self.is_authorized_in_context(
+            &r.uid,
+            &*ACTION_DELETE_COMMENT,
+            &self.task_resource(&r.uid, &r.list, r.task)?,
+            author_context(&author),
+        )?;
+        self.get_task_mut(&r.list, r.task)?
+            .delete_comment(r.comment);
+        self.notify(
+            Some(&r.list),
+            Change::CommentDeleted {
+                task: r.task,
+                comment: r.comment,
+            },
+        );
+        Ok(AppResponse::Unit(()))
+    }
+
+    fn comment_author(
+        &self,
+        uid: &UserUid,
+        list: &ListUid,
+        task: i64,
+        comment: i64,
+    ) -> Result<UserUid> {
+        self.get_task(list, task)
+            .and_then(|t| {
+                t.get_comment(comment)
+                    .map(|c| c.author().clone())
+                    .ok_or_else(|| Error::InvalidCommentId(list.clone().into(), task, comment))
+            })
+            .or_else(|e| self.not_found(uid, list, e))
+    }
+
+    fn attach_to_task(&mut self, r: AttachToTask) -> Result<AppResponse> {
+        let resource = self.task_resource(&r.uid, &r.list, r.task)?;
+        self.is_authorized(&r.uid, &*ACTION_ATTACH_TO_TASK, &resource)?;
+        let size = BASE64_STANDARD
+            .decode(&r.data)
+            .map_err(|e| Error::InvalidAttachment(e.to_string()))?
+            .len();
+        if let Some(max) = self.max_attachment_bytes {
+            if size > max {
+                return Err(Error::AttachmentTooLarge(max));
+            }
+        }
+        let max_attachments = self.max_attachments_per_task;
+        let task = self.get_task_mut(&r.list, r.task)?;
+        if let Some(max) = max_attachments {
+            if task.num_attachments() >= max {
+                return Err(Error::AttachmentQuotaExceeded(r.list.into(), r.task, max));
+            }
+        }
+        let attachment = task.add_attachment(r.uid, r.name, r.content_type, r.data);
+        self.notify(
+            Some(&r.list),
+            Change::AttachmentAdded {
+                task: r.task,
+                attachment,
+            },
+        );
+        Ok(AppResponse::Id(attachment))
+    }
+
+    fn delete_attachment(&mut self, r: DeleteAttachment) -> Result<AppResponse> {
+        let author = self
+            .get_task(&r.list, r.task)
+            .and_then(|t| {
+                t.get_attachment(r.attachment)
+                    .map(|a| a.author().clone())
+                    .ok_or_else(|| {
+                        Error::InvalidAttachmentId(r.list.clone().into(), r.task, r.attachment)
+                    })
+            })
+            .or_else(|e| self.not_found(&r.uid, &r.list, e))?;
+        self.is_authorized_in_context(
+            &r.uid,
+            &*ACTION_DELETE_ATTACHMENT,
+            &self.task_resource(&r.uid, &r.list, r.task)?,
+            author_context(&author),
+        )?;
+        self.get_task_mut(&r.list, r.task)?
+            .delete_attachment(r.attachment);
+        self.notify(
+            Some(&r.list),
+            Change::AttachmentDeleted {
+                task: r.task,
+                attachment: r.attachment,
+            },
+        );
         Ok(AppResponse::Unit(()))
     }
 
     fn get_lists(&self, r: GetLists) -> Result<AppResponse> {
         self.is_authorized(&r.uid, &*ACTION_GET_LISTS, &*APPLICATION_TINY_TODO)?;
-        let entities: Entities = self.entities.as_entities(&self.schema);
+        let entities: Entities = self.entities.as_entities(&self.schema)?;
         let partial_request = RequestBuilder::default()
             .action(ACTION_GET_LIST.as_ref().clone().into())
             .principal(cedar_policy::EntityUid::from(EntityUid::from(
                 r.uid.clone(),
             )))
+            .context(request_context([])?)
             .build();
         let partial_response =
             self.authorizer
@@ -538,8 +1406,142 @@ impl AppContext {
         ))
     }
 
+    // The reverse of `get_lists`: the principal is left unknown and each user and team
+    // in the store is substituted into the residual policies.
+    // A team is reported when the team itself would be allowed, which means its members
+    // are allowed by virtue of belonging to it.
+    fn get_list_access(&self, r: GetListAccess) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_EDIT_SHARE, &r.list)?;
+        let _list = self.entities.get_list(&r.list)?;
+        let entities: Entities = self.entities.as_entities(&self.schema)?;
+        let partial_request = RequestBuilder::default()
+            .action(r.action.clone().into())
+            .resource(cedar_policy::EntityUid::from(EntityUid::from(
+                r.list.clone(),
+            )))
+            .context(request_context([])?)
+            .build();
+        let partial_response =
+            self.authorizer
+                .is_authorized_partial(&partial_request, &self.policies, &entities);
+
+        let is_allowed = |euid: &EntityUid| {
+            matches!(
+                partial_response.reauthorize_with_bindings(
+                    std::iter::once((
+                        "principal".into(),
+                        &RestrictedExpression::new_entity_uid(euid.clone().into())
+                    )),
+                    &self.authorizer,
+                    &entities
+                ),
+                Ok(r) if matches!(r.decision(), Some(Decision::Allow))
+            )
+        };
+
+        let users = self
+            .entities
+            .get_users()
+            .map(|u| u.uid())
+            .filter(|uid| is_allowed(uid.as_ref()))
+            .sorted_by_key(|uid| uid.as_ref().to_string())
+            .cloned()
+            .collect();
+        let teams = self
+            .entities
+            .get_teams()
+            .map(|t| t.uid())
+            .filter(|uid| is_allowed(uid.as_ref()))
+            .sorted_by_key(|uid| uid.as_ref().to_string())
+            .cloned()
+            .collect();
+
+        Ok(AppResponse::ListAccess(ListAccess { users, teams }))
+    }
+
+    // `get_lists` for every action: the resource is left unknown, and what remains of the
+    // policies once the caller, the time and the entities are substituted is reported. The
+    // author of a comment or attachment is left unknown too, so that the policies on
+    // authors keep their condition on `context.author`.
+    fn get_capabilities(&self, r: GetCapabilities) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_GET_LISTS, &*APPLICATION_TINY_TODO)?;
+        let entities: Entities = self.entities.as_entities(&self.schema)?;
+        let principal = cedar_policy::EntityUid::from(EntityUid::from(r.uid.clone()));
+        let teams = entities
+            .ancestors(&principal)
+            .into_iter()
+            .flatten()
+            .filter_map(|euid| TeamUid::try_from(EntityUid::from(euid.clone())).ok())
+            .sorted_by_key(|uid| uid.as_ref().to_string())
+            .collect();
+        let author = (
+            "author".to_string(),
+            RestrictedExpression::new_unknown("author"),
+        );
+
+        let mut actions = BTreeMap::new();
+        for action in self.schema.actions() {
+            let Some(resource_types) = self.schema.resources_for_action(action) else {
+                continue;
+            };
+            let partial_request = RequestBuilder::default()
+                .principal(principal.clone())
+                .action(action.clone())
+                .context(request_context([author.clone()])?)
+                .build();
+            let partial_response =
+                self.authorizer
+                    .is_authorized_partial(&partial_request, &self.policies, &entities);
+            let residuals = partial_response
+                .all_residuals()
+                .map(|policy| policy.to_json())
+                .collect::<std::result::Result<_, _>>()?;
+            let capability = ActionCapability {
+                resource_types: resource_types.map(|ty| ty.to_string()).sorted().collect(),
+                allowed: partial_response
+                    .decision()
+                    .map(|decision| decision == Decision::Allow),
+                residuals,
+            };
+            actions.insert(action.id().escaped().to_string(), capability);
+        }
+
+        Ok(AppResponse::Capabilities(Capabilities { teams, actions }))
+    }
+
+    // Each request is authorized as its action's handler would authorize it, including
+    // against the task when tasks are entities
+    fn authorize(&self, r: Authorize) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_GET_LISTS, &*APPLICATION_TINY_TODO)?;
+        if r.requests.len() > MAX_BATCH {
+            return Err(Error::BatchTooLarge(MAX_BATCH));
+        }
+        let allowed = r
+            .requests
+            .iter()
+            .map(|request| {
+                let resource = match (&request.list, request.task) {
+                    (None, _) => Ok(APPLICATION_TINY_TODO.clone()),
+                    (Some(list), None) => Ok(list.clone().into()),
+                    (Some(list), Some(task)) => self.task_resource(&r.uid, list, task),
+                };
+                let context = request.author.iter().flat_map(author_context);
+                let decision = resource.and_then(|resource| {
+                    self.is_authorized_in_context(&r.uid, &request.action, &resource, context)
+                });
+                match decision {
+                    Ok(()) => Ok(true),
+                    Err(Error::AuthDenied(_)) => Ok(false),
+                    Err(e) => Err(e),
+                }
+            })
+            .collect::<Result<_>>()?;
+        Ok(AppResponse::Decisions(Decisions { allowed }))
+    }
+
     fn create_list(&mut self, r: CreateList) -> Result<AppResponse> {
         self.is_authorized(&r.uid, &*ACTION_CREATE_LIST, &*APPLICATION_TINY_TODO)?;
+        self.check_list_quota(&r.uid)?;
 
         let euid = self
             .entities
@@ -551,6 +1553,59 @@ impl AppContext {
         Ok(AppResponse::euid(euid))
     }
 
+    // Check that `owner` may own another list
+    fn check_list_quota(&self, owner: &UserUid) -> Result<()> {
+        if let Some(max) = self.max_lists_per_user {
+            let owned = self
+                .entities
+                .get_lists()
+                .filter(|l| l.owner() == owner)
+                .count();
+            if owned >= max {
+                return Err(Error::ListQuotaExceeded(owner.clone().into(), max));
+            }
+        }
+        Ok(())
+    }
+
+    fn transfer_ownership(&mut self, r: TransferOwnership) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_TRANSFER_OWNERSHIP, &r.list)?;
+        let _new_owner = self.entities.get_user(&r.owner)?;
+        if self.entities.get_list(&r.list)?.owner() == &r.owner {
+            return Ok(AppResponse::Unit(()));
+        }
+        self.check_list_quota(&r.owner)?;
+        // Policies refer to `resource.owner`, so they apply to the new owner from now on
+        let list = self.entities.get_list_mut(&r.list)?;
+        list.set_owner(r.owner.clone());
+        self.notify(
+            Some(&r.list),
+            Change::OwnershipTransferred { owner: r.owner },
+        );
+        Ok(AppResponse::Unit(()))
+    }
+
+    // The copy is a new list, so the caller must also be allowed to create lists. Its tasks
+    // are copied, but not the original's shares, comments or attachments.
+    fn duplicate_list(&mut self, r: DuplicateList) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_DUPLICATE_LIST, &r.list)?;
+        self.is_authorized(&r.uid, &*ACTION_CREATE_LIST, &*APPLICATION_TINY_TODO)?;
+        self.check_list_quota(&r.uid)?;
+        let original = self.entities.get_list(&r.list)?;
+        let name = r.name.unwrap_or_else(|| original.name().to_string());
+        let tasks = original.copy_tasks();
+
+        let euid = self
+            .entities
+            .fresh_euid::<ListUid>(TYPE_LIST.clone())
+            .unwrap();
+        let mut l = List::new(&mut self.entities, euid.clone(), r.uid, name);
+        l.set_tasks(tasks);
+        self.entities.insert_list(l);
+
+        Ok(AppResponse::euid(euid))
+    }
+
     fn get_list(&self, r: GetList) -> Result<AppResponse> {
         self.is_authorized(&r.uid, &*ACTION_GET_LIST, &r.list)?;
         let list = self.entities.get_list(&r.list)?.clone();
@@ -560,43 +1615,156 @@ impl AppContext {
     fn update_list(&mut self, r: UpdateList) -> Result<AppResponse> {
         self.is_authorized(&r.uid, &*ACTION_UPDATE_LIST, &r.list)?;
         let list = self.entities.get_list_mut(&r.list)?;
-        list.update_name(r.name);
+        list.update_name(r.name.clone());
+        self.notify(Some(&r.list), Change::ListUpdated { name: r.name });
         Ok(AppResponse::Unit(()))
     }
 
     fn delete_list(&mut self, r: DeleteList) -> Result<AppResponse> {
         self.is_authorized(&r.uid, &*ACTION_DELETE_LIST, &r.list)?;
         self.entities.delete_entity(&r.list)?;
+        #[cfg(feature = "use-templates")]
+        self.unlink_shares(&r.list)?;
+        #[cfg(not(feature = "use-templates"))]
+        self.entities.forget_timed_shares(&r.list);
+        self.notify(Some(&r.list), Change::ListDeleted);
         Ok(AppResponse::Unit(()))
     }
 
-    #[tracing::instrument(skip_all)]
+    fn subscribe(&self, r: Subscribe) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_GET_LIST, &r.list)?;
+        let _list = self.entities.get_list(&r.list)?;
+        Ok(AppResponse::Subscription(self.events.subscribe()))
+    }
+
+    fn check_subscription(&self, r: Subscribe) -> Result<AppResponse> {
+        self.is_authorized(&r.uid, &*ACTION_GET_LIST, &r.list)?;
+        let _list = self.entities.get_list(&r.list)?;
+        Ok(AppResponse::Unit(()))
+    }
+
+    fn notify(&self, list: Option<&ListUid>, change: Change) {
+        // Sending only fails when there are no subscribers
+        let _ = self.events.send(ChangeEvent {
+            list: list.cloned(),
+            change,
+        });
+    }
+
     pub fn is_authorized(
         &self,
         principal: impl AsRef<EntityUid>,
         action: impl AsRef<EntityUid>,
         resource: impl AsRef<EntityUid>,
     ) -> Result<()> {
-        let es = self.entities.as_entities(&self.schema);
-        let q = Request::new(
-            principal.as_ref().clone().into(),
-            action.as_ref().clone().into(),
-            resource.as_ref().clone().into(),
-            Context::empty(),
-            Some(&self.schema),
+        self.is_authorized_in_context(principal, action, resource, [])
+    }
+
+    #[tracing::instrument(
+        name = "authorize",
+        skip_all,
+        fields(
+            principal = %principal.as_ref(),
+            action = %action.as_ref(),
+            resource = %resource.as_ref(),
+            decision = field::Empty,
+            cached = field::Empty,
         )
-        .map_err(|e| Error::Request(e.to_string()))?;
-        info!(
-            "is_authorized request: principal: {}, action: {}, resource: {}",
-            principal.as_ref(),
-            action.as_ref(),
-            resource.as_ref()
+    )]
+    pub fn is_authorized_in_context(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        action: impl AsRef<EntityUid>,
+        resource: impl AsRef<EntityUid>,
+        context: impl IntoIterator<Item = (String, RestrictedExpression)>,
+    ) -> Result<()> {
+        let context: Vec<_> = context.into_iter().collect();
+        let (principal, action, resource) =
+            (principal.as_ref(), action.as_ref(), resource.as_ref());
+        let key = self
+            .cache
+            .as_ref()
+            .map(|cache| cache.key(principal, action, resource, &context));
+        let cached = self
+            .cache
+            .as_ref()
+            .zip(key.as_ref())
+            .and_then(|(cache, key)| cache.get(key, &self.entities));
+        Span::current().record("cached", cached.is_some());
+        let response = match cached {
+            Some(response) => response,
+            None => {
+                let (response, es) = self.evaluate(principal, action, resource, &context)?;
+                if let (Some(cache), Some(key)) = (&self.cache, key) {
+                    cache.insert(key, &context, response.clone(), &es, &self.entities);
+                }
+                response
+            }
+        };
+        let attributes = UserUid::try_from(principal.clone())
+            .ok()
+            .and_then(|uid| self.entities.get_user(&uid).ok())
+            .map(User::attributes);
+        self.decisions.log(
+            &self.policies,
+            principal,
+            attributes,
+            action,
+            resource,
+            &response,
         );
-        let response = self.authorizer.is_authorized(&q, &self.policies, &es);
-        info!("Auth response: {:?}", response);
+        let decision = match response.decision() {
+            Decision::Allow => "allow",
+            Decision::Deny => "deny",
+        };
+        Span::current().record("decision", decision);
+        metrics::record_decision(&action.to_string(), response.decision() == Decision::Allow);
         match response.decision() {
             Decision::Allow => Ok(()),
             Decision::Deny => Err(Error::AuthDenied(response.diagnostics().clone())),
         }
     }
+
+    // Evaluate a request against the current entities, which are returned with the response
+    fn evaluate(
+        &self,
+        principal: &EntityUid,
+        action: &EntityUid,
+        resource: &EntityUid,
+        context: &[(String, RestrictedExpression)],
+    ) -> Result<(Response, Entities)> {
+        let snapshot = info_span!(
+            "as_entities",
+            principal = %principal,
+            action = %action,
+            decision = field::Empty,
+        );
+        let es = snapshot.in_scope(|| self.entities.as_entities(&self.schema))?;
+        let q = Request::new(
+            principal.clone().into(),
+            action.clone().into(),
+            resource.clone().into(),
+            request_context(context.iter().cloned())?,
+            Some(&self.schema),
+        )
+        .map_err(|e| Error::Request(e.to_string()))?;
+        let evaluation = info_span!(
+            "evaluate",
+            principal = %principal,
+            action = %action,
+            decision = field::Empty,
+        );
+        let response = evaluation.in_scope(|| {
+            let _timer = metrics::IS_AUTHORIZED_SECONDS.start_timer();
+            self.authorizer.is_authorized(&q, &self.policies, &es)
+        });
+        let decision = match response.decision() {
+            Decision::Allow => "allow",
+            Decision::Deny => "deny",
+        };
+        for span in [&snapshot, &evaluation] {
+            span.record("decision", decision);
+        }
+        Ok((response, es))
+    }
 }
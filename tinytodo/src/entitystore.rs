@@ -17,23 +17,43 @@
 use std::collections::HashMap;
 use thiserror::Error;
 
-use cedar_policy::{Entities, EntityId, EntityTypeName, EvaluationError, Schema};
+use cedar_policy::{Entities, EntitiesError, EntityId, EntityTypeName, EvaluationError, Schema};
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    api::ShareRole,
     context::Error,
+    metrics,
     objects::{Application, List, Team, User, UserOrTeam},
-    util::{EntityUid, ListUid, TeamUid, UserOrTeamUid, UserUid},
+    shares::TimedShare,
+    util::{EntityUid, ListUid, TaskUid, TeamUid, UserOrTeamUid, UserUid},
 };
 
+#[cfg(not(feature = "use-templates"))]
+use crate::util::TYPE_TEAM;
+
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct EntityStore {
     users: HashMap<EntityUid, User>,
     teams: HashMap<EntityUid, Team>,
     lists: HashMap<EntityUid, List>,
     app: Application,
+    /// The team memberships that expire, which are the expiring shares when lists are
+    /// shared with teams
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    timed_shares: Vec<TimedShare>,
     #[serde(skip)]
     uid: usize,
+    // Whether tasks are entities, whose parent is their list, rather than records in the list
+    #[serde(skip)]
+    task_entities: bool,
+    // The version of each entity that changed since the store was loaded, from `clock`.
+    // Tasks have none of their own, as they are changed through their list. Only entities
+    // that exist are touched, so that requests naming unknown ones do not grow the map.
+    #[serde(skip)]
+    versions: HashMap<EntityUid, u64>,
+    #[serde(skip)]
+    clock: u64,
 }
 
 impl EntityStore {
@@ -41,6 +61,14 @@ impl EntityStore {
         self.lists.values()
     }
 
+    pub fn get_users(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+
+    pub fn get_teams(&self) -> impl Iterator<Item = &Team> {
+        self.teams.values()
+    }
+
     pub fn euids(&self) -> impl Iterator<Item = &EntityUid> {
         self.users
             .keys()
@@ -49,13 +77,100 @@ impl EntityStore {
             .chain(std::iter::once(self.app.euid()))
     }
 
-    pub fn as_entities(&self, schema: &Schema) -> Entities {
+    /// The store as Cedar entities, checked against `schema`
+    pub fn as_entities(&self, schema: &Schema) -> Result<Entities, EntitiesError> {
+        let _timer = metrics::AS_ENTITIES_SECONDS.start_timer();
         let users = self.users.values().map(|user| user.clone().into());
         let teams = self.teams.values().map(|team| team.clone().into());
-        let lists = self.lists.values().map(|list| list.clone().into());
+        let lists = self.lists.values().flat_map(|list| {
+            if self.task_entities {
+                list.clone().into_entities_with_tasks()
+            } else {
+                vec![list.clone().into()]
+            }
+        });
         let app = std::iter::once(self.app.clone().into());
         let all = users.chain(teams).chain(lists).chain(app);
-        Entities::from_entities(all, Some(schema)).unwrap()
+        Entities::from_entities(all, Some(schema))
+    }
+
+    /// A number that changes whenever the entity with `euid` may have, including when it
+    /// is inserted or deleted
+    pub fn version(&self, euid: &EntityUid) -> u64 {
+        self.versions.get(euid).copied().unwrap_or(0)
+    }
+
+    fn touch(&mut self, euid: &EntityUid) {
+        self.clock += 1;
+        self.versions.insert(euid.clone(), self.clock);
+    }
+
+    pub fn set_task_entities(&mut self, task_entities: bool) {
+        self.task_entities = task_entities;
+    }
+
+    /// The resource that actions on a task are authorized against: the task itself when
+    /// tasks are entities, and otherwise its list
+    pub fn task_resource(&self, list: &ListUid, task: i64) -> Result<EntityUid, Error> {
+        if !self.task_entities {
+            return Ok(list.clone().into());
+        }
+        self.get_list(list)?
+            .get_task(task)
+            .map(|_| TaskUid::new(list, task).into())
+            .ok_or_else(|| Error::InvalidTaskId(list.clone().into(), task))
+    }
+
+    /// The team for `role` on `list`, which is made if the list has none yet
+    #[cfg(not(feature = "use-templates"))]
+    pub fn role_team(&mut self, list: &ListUid, role: &ShareRole) -> Result<TeamUid, Error> {
+        if let Some(team) = self.get_list(list)?.get_team(role) {
+            return Ok(team.clone());
+        }
+        let team = self.fresh_euid::<TeamUid>(TYPE_TEAM.clone()).unwrap();
+        self.insert_team(Team::new(team.clone()));
+        self.get_list_mut(list)?
+            .set_role_team(role.clone(), team.clone());
+        Ok(team)
+    }
+
+    /// Record when a share ends, or with `expires` unset, that it no longer does
+    pub fn set_share_expiry(
+        &mut self,
+        list: &ListUid,
+        target: &UserOrTeamUid,
+        role: &ShareRole,
+        expires: Option<i64>,
+    ) {
+        self.timed_shares
+            .retain(|share| !share.grants(list, target, role));
+        if let Some(expires) = expires {
+            self.timed_shares.push(TimedShare {
+                list: list.clone(),
+                target: target.clone(),
+                role: role.clone(),
+                expires,
+            });
+        }
+    }
+
+    pub fn timed_shares(&self) -> impl Iterator<Item = &TimedShare> {
+        self.timed_shares.iter()
+    }
+
+    /// Forget the expiry of every share of `list`
+    pub fn forget_timed_shares(&mut self, list: &ListUid) {
+        self.timed_shares.retain(|share| &share.list != list);
+    }
+
+    /// Remove and return the records of the shares that have expired by `now`. The
+    /// memberships themselves are left for the caller to remove.
+    pub fn take_expired_shares(&mut self, now: i64) -> Vec<TimedShare> {
+        let (expired, live) = std::mem::take(&mut self.timed_shares)
+            .into_iter()
+            .partition(|share| share.is_expired(now));
+        self.timed_shares = live;
+        expired
     }
 
     pub fn fresh_euid<T: TryFrom<EntityUid>>(&mut self, ty: EntityTypeName) -> Result<T, T::Error> {
@@ -77,31 +192,30 @@ impl EntityStore {
     }
 
     pub fn insert_user(&mut self, e: User) {
+        self.touch(e.uid().as_ref());
         self.users.insert(e.uid().clone().into(), e);
     }
 
     pub fn insert_team(&mut self, e: Team) {
+        self.touch(e.uid().as_ref());
         self.teams.insert(e.uid().clone().into(), e);
     }
 
     pub fn insert_list(&mut self, e: List) {
+        self.touch(e.uid().as_ref());
         self.lists.insert(e.uid().clone().into(), e);
     }
 
     pub fn delete_entity(&mut self, e: impl AsRef<EntityUid>) -> Result<(), Error> {
         let r = e.as_ref();
-        if self.users.contains_key(r) {
-            self.users.remove(r);
-            Ok(())
-        } else if self.teams.contains_key(r) {
-            self.teams.remove(r);
-            Ok(())
-        } else if self.lists.contains_key(r) {
-            self.lists.remove(r);
-            Ok(())
-        } else {
-            Err(Error::NoSuchEntity(r.clone()))
+        if self.users.remove(r).is_none()
+            && self.teams.remove(r).is_none()
+            && self.lists.remove(r).is_none()
+        {
+            return Err(Error::NoSuchEntity(r.clone()));
         }
+        self.touch(r);
+        Ok(())
     }
 
     pub fn get_user(&self, euid: &UserUid) -> Result<&User, Error> {
@@ -111,9 +225,9 @@ impl EntityStore {
     }
 
     pub fn get_user_mut(&mut self, euid: &UserUid) -> Result<&mut User, Error> {
-        self.users
-            .get_mut(euid.as_ref())
-            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+        self.get_user(euid)?;
+        self.touch(euid.as_ref());
+        Ok(self.users.get_mut(euid.as_ref()).unwrap())
     }
 
     pub fn get_team(&self, euid: &TeamUid) -> Result<&Team, Error> {
@@ -123,9 +237,20 @@ impl EntityStore {
     }
 
     pub fn get_team_mut(&mut self, euid: &TeamUid) -> Result<&mut Team, Error> {
-        self.teams
-            .get_mut(euid.as_ref())
-            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+        self.get_team(euid)?;
+        self.touch(euid.as_ref());
+        Ok(self.teams.get_mut(euid.as_ref()).unwrap())
+    }
+
+    pub fn get_user_or_team(&self, euid: &UserOrTeamUid) -> Result<&dyn UserOrTeam, Error> {
+        let euid_ref = euid.as_ref();
+        if let Some(u) = self.users.get(euid_ref) {
+            Ok(u)
+        } else if let Some(t) = self.teams.get(euid_ref) {
+            Ok(t)
+        } else {
+            Err(Error::no_such_entity(euid_ref.clone()))
+        }
     }
 
     pub fn get_user_or_team_mut(
@@ -134,9 +259,11 @@ impl EntityStore {
     ) -> Result<&mut dyn UserOrTeam, Error> {
         let euid_ref = euid.as_ref();
         if self.users.contains_key(euid_ref) {
+            self.touch(euid_ref);
             let u = self.users.get_mut(euid_ref).unwrap();
             Ok(u)
         } else if self.teams.contains_key(euid_ref) {
+            self.touch(euid_ref);
             let t = self.teams.get_mut(euid_ref).unwrap();
             Ok(t)
         } else {
@@ -151,9 +278,9 @@ impl EntityStore {
     }
 
     pub fn get_list_mut(&mut self, euid: &ListUid) -> Result<&mut List, Error> {
-        self.lists
-            .get_mut(euid.as_ref())
-            .ok_or_else(|| Error::no_such_entity(euid.clone()))
+        self.get_list(euid)?;
+        self.touch(euid.as_ref());
+        Ok(self.lists.get_mut(euid.as_ref()).unwrap())
     }
 }
 
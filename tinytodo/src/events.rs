@@ -0,0 +1,191 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Change notifications for lists.
+//!
+//! The application actor publishes a [`ChangeEvent`] on a broadcast channel after every
+//! successful mutation. Subscribers receive them as server-sent events, but each event is
+//! re-authorized with `GetList` against the subscriber before delivery, so a revoked share
+//! ends the stream at the next event.
+
+use futures::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::warn;
+use utoipa::ToSchema;
+use warp::sse::Event;
+
+use crate::{
+    api::{simple_query_inner, AppChannel, Empty, Subscribe},
+    context::{AppQueryKind, Error},
+    util::{ListUid, UserUid},
+};
+
+/// How many events a slow subscriber may fall behind before it starts missing them
+pub const EVENT_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChangeEvent {
+    /// The list that changed, or `None` for changes that may affect every list
+    pub list: Option<ListUid>,
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type")]
+pub enum Change {
+    ListUpdated {
+        name: String,
+    },
+    ListDeleted,
+    OwnershipTransferred {
+        owner: UserUid,
+    },
+    TaskCreated {
+        task: i64,
+    },
+    TaskUpdated {
+        task: i64,
+    },
+    TaskDeleted {
+        task: i64,
+    },
+    CommentAdded {
+        task: i64,
+        comment: i64,
+    },
+    CommentUpdated {
+        task: i64,
+        comment: i64,
+    },
+    CommentDeleted {
+        task: i64,
+        comment: i64,
+    },
+    AttachmentAdded {
+        task: i64,
+        attachment: i64,
+    },
+    AttachmentDeleted {
+        task: i64,
+        attachment: i64,
+    },
+    ShareAdded,
+    ShareRemoved,
+    PoliciesReloaded,
+    /// Sent just before a subscriber's stream ends because it may no longer read the list
+    AccessRevoked,
+}
+
+impl Change {
+    /// The SSE `event` name for this change
+    pub fn name(&self) -> &'static str {
+        match self {
+            Change::ListUpdated { .. } => "list_updated",
+            Change::ListDeleted => "list_deleted",
+            Change::OwnershipTransferred { .. } => "ownership_transferred",
+            Change::TaskCreated { .. } => "task_created",
+            Change::TaskUpdated { .. } => "task_updated",
+            Change::TaskDeleted { .. } => "task_deleted",
+            Change::CommentAdded { .. } => "comment_added",
+            Change::CommentUpdated { .. } => "comment_updated",
+            Change::CommentDeleted { .. } => "comment_deleted",
+            Change::AttachmentAdded { .. } => "attachment_added",
+            Change::AttachmentDeleted { .. } => "attachment_deleted",
+            Change::ShareAdded => "share_added",
+            Change::ShareRemoved => "share_removed",
+            Change::PoliciesReloaded => "policies_reloaded",
+            Change::AccessRevoked => "access_revoked",
+        }
+    }
+}
+
+struct Subscriber {
+    app: AppChannel,
+    uid: UserUid,
+    list: ListUid,
+    recv: broadcast::Receiver<ChangeEvent>,
+}
+
+impl Subscriber {
+    // Re-check that the subscriber may still read the list
+    async fn authorize(&self) -> Result<Empty, Error> {
+        let q = AppQueryKind::CheckSubscription(Subscribe {
+            uid: self.uid.clone(),
+            list: self.list.clone(),
+        });
+        simple_query_inner::<Empty>(self.app.clone(), q).await
+    }
+
+    fn event(&self, change: Change) -> Result<Event, warp::Error> {
+        let name = change.name();
+        Event::default().event(name).json_data(ChangeEvent {
+            list: Some(self.list.clone()),
+            change,
+        })
+    }
+}
+
+/// The server-sent event stream for `uid`'s subscription to `list`
+pub fn event_stream(
+    app: AppChannel,
+    uid: UserUid,
+    list: ListUid,
+    recv: broadcast::Receiver<ChangeEvent>,
+) -> impl Stream<Item = Result<Event, warp::Error>> + Send + 'static {
+    let sub = Subscriber {
+        app,
+        uid,
+        list,
+        recv,
+    };
+    stream::unfold(Some(sub), |sub| async move {
+        let mut sub = match sub {
+            Some(sub) => sub,
+            None => return None,
+        };
+        loop {
+            let event = match sub.recv.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Subscriber to {} missed {n} events", sub.list.as_ref());
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            match &event.list {
+                Some(list) if list == &sub.list => (),
+                Some(_) => continue,
+                None => (),
+            }
+            match (sub.authorize().await, event.change) {
+                (Ok(_), Change::PoliciesReloaded) => continue,
+                (Ok(_), change) => {
+                    let e = sub.event(change);
+                    return Some((e, Some(sub)));
+                }
+                (Err(_), Change::ListDeleted) => {
+                    let e = sub.event(Change::ListDeleted);
+                    return Some((e, None));
+                }
+                (Err(_), _) => {
+                    let e = sub.event(Change::AccessRevoked);
+                    return Some((e, None));
+                }
+            }
+        }
+    })
+}
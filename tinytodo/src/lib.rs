@@ -0,0 +1,45 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The TinyTodo server, exposed as a library so that the request and response types in
+//! [`api`] can be shared with clients.
+
+pub mod api;
+pub mod audit;
+pub mod cache;
+#[cfg(feature = "client")]
+pub mod cli;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod config;
+pub mod context;
+pub mod entitystore;
+pub mod events;
+pub mod limits;
+pub mod lint;
+pub mod metrics;
+pub mod objects;
+pub mod openapi;
+pub mod persistence;
+pub mod policy_store;
+pub mod profiles;
+pub mod roles;
+pub mod shares;
+pub mod snapshot;
+pub mod telemetry;
+pub mod tls;
+pub mod util;
+pub mod web;
@@ -0,0 +1,288 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Rate limits and quotas.
+//!
+//! Requests are rate limited with token buckets at two levels: per client IP address,
+//! by a filter in front of every route, and per principal and action, by the application
+//! actor before a query is authorized. Quotas bound how many lists a user may own and
+//! how many tasks a list may hold, so a single user cannot grow the `EntityStore`
+//! without bound.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
+use warp::{http::StatusCode, reject::Reject, Filter, Rejection, Reply};
+
+use crate::{tls::PeerAddr, util::EntityUid};
+
+/// Buckets are pruned once a limiter tracks this many keys
+pub const PRUNE_THRESHOLD: usize = 4096;
+
+/// A token bucket: up to `burst` requests may be made at once, and the bucket refills
+/// at `per_second` requests per second.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bucket {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+/// Nothing is rate limited unless configured
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Per-principal limits, keyed by action name (e.g., `CreateTask`)
+    pub actions: HashMap<String, Bucket>,
+    /// The per-principal limit for actions not listed in `actions`
+    pub default_action: Option<Bucket>,
+    /// The limit on all requests from a single IP address
+    pub per_ip: Option<Bucket>,
+    /// The most lists a user may own
+    pub max_lists_per_user: Option<usize>,
+    /// The most tasks a list may hold
+    pub max_tasks_per_list: Option<usize>,
+    /// The largest file, in bytes, that may be attached to a task
+    pub max_attachment_bytes: Option<usize>,
+    /// The most attachments a task may have
+    pub max_attachments_per_task: Option<usize>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            actions: HashMap::new(),
+            default_action: None,
+            per_ip: None,
+            max_lists_per_user: None,
+            max_tasks_per_list: None,
+            max_attachment_bytes: Some(64 * 1024),
+            max_attachments_per_task: Some(16),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LimitsError {
+    #[error("Invalid action name in rate limits: {0}")]
+    Action(String),
+    #[error("Invalid rate limit for {0}: `burst` and `per_second` must be positive")]
+    Bucket(String),
+}
+
+impl LimitsConfig {
+    /// Check that every bucket can admit a request and every action name is well formed
+    pub fn validate(&self) -> Result<(), LimitsError> {
+        self.action_limits().map(|_| ())
+    }
+
+    fn action_limits(&self) -> Result<HashMap<EntityUid, Bucket>, LimitsError> {
+        let named = self
+            .default_action
+            .iter()
+            .map(|b| ("default_action", b))
+            .chain(self.per_ip.iter().map(|b| ("per_ip", b)));
+        for (name, bucket) in named {
+            bucket.validate(name)?;
+        }
+        self.actions
+            .iter()
+            .map(|(name, bucket)| {
+                bucket.validate(name)?;
+                let euid = format!(r#"Action::"{name}""#)
+                    .parse()
+                    .map_err(|_| LimitsError::Action(name.clone()))?;
+                Ok((euid, *bucket))
+            })
+            .collect()
+    }
+}
+
+impl Bucket {
+    fn validate(&self, name: &str) -> Result<(), LimitsError> {
+        if self.burst > 0 && self.per_second.is_finite() && self.per_second > 0.0 {
+            Ok(())
+        } else {
+            Err(LimitsError::Bucket(name.to_string()))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    limit: Bucket,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: Bucket, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: f64::from(limit.burst),
+            updated: now,
+        }
+    }
+
+    // Returns whether the bucket is full after refilling
+    fn refill(&mut self, now: Instant) -> bool {
+        let burst = f64::from(self.limit.burst);
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(burst);
+        self.updated = now;
+        self.tokens >= burst
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A set of token buckets, one per key
+#[derive(Debug, Clone)]
+pub struct RateLimiter<K> {
+    buckets: HashMap<K, TokenBucket>,
+}
+
+impl<K> Default for RateLimiter<K> {
+    fn default() -> Self {
+        Self {
+            buckets: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    /// Take a token from `key`'s bucket, returning `false` if it is empty
+    pub fn check(&mut self, key: K, limit: &Bucket) -> bool {
+        let now = Instant::now();
+        if self.buckets.len() >= PRUNE_THRESHOLD {
+            // A full bucket is indistinguishable from a fresh one, so it can be dropped
+            self.buckets.retain(|_, b| !b.refill(now));
+        }
+        self.buckets
+            .entry(key)
+            .or_insert_with(|| TokenBucket::full(*limit, now))
+            .try_take(now)
+    }
+
+    /// The number of keys with a bucket
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+}
+
+/// The per-principal limits enforced by the application actor
+#[derive(Debug, Clone)]
+pub struct PrincipalLimits {
+    actions: HashMap<EntityUid, Bucket>,
+    default_action: Option<Bucket>,
+    limiter: RateLimiter<(EntityUid, EntityUid)>,
+}
+
+impl PrincipalLimits {
+    pub fn new(config: &LimitsConfig) -> Result<Self, LimitsError> {
+        Ok(Self {
+            actions: config.action_limits()?,
+            default_action: config.default_action,
+            limiter: RateLimiter::default(),
+        })
+    }
+
+    /// Take a token for `principal` performing `action`, returning `false` if it is rate limited
+    pub fn check(&mut self, principal: &EntityUid, action: &EntityUid) -> bool {
+        match self.actions.get(action).or(self.default_action.as_ref()) {
+            Some(limit) => self
+                .limiter
+                .check((principal.clone(), action.clone()), limit),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimited;
+
+impl Reject for RateLimited {}
+
+/// Rejects requests from an IP address that has exhausted its bucket. The address is warp's
+/// remote address or, over HTTPS, the connection's [`PeerAddr`]. Requests with neither share
+/// one bucket, so that the limit is never silently lifted.
+pub fn per_ip(limit: Option<Bucket>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let limiter: Arc<Mutex<RateLimiter<Option<IpAddr>>>> = Arc::default();
+    let warned = Arc::new(AtomicBool::new(false));
+    warp::addr::remote()
+        .and(warp::ext::optional::<PeerAddr>())
+        .and_then(move |addr: Option<SocketAddr>, peer: Option<PeerAddr>| {
+            let limiter = limiter.clone();
+            let warned = warned.clone();
+            async move {
+                let allowed = match limit {
+                    Some(limit) => {
+                        let ip = addr.or(peer.map(|PeerAddr(addr)| addr)).map(|a| a.ip());
+                        if ip.is_none() && !warned.swap(true, Ordering::Relaxed) {
+                            warn!("Client address unknown; such requests share one per-IP limit");
+                        }
+                        limiter
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .check(ip, &limit)
+                    }
+                    None => true,
+                };
+                if allowed {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(RateLimited))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Responds `429 Too Many Requests` to requests rejected by [`per_ip`]
+pub async fn handle_rejection(r: Rejection) -> Result<warp::reply::Response, Rejection> {
+    if r.find::<RateLimited>().is_some() {
+        let body = serde_json::json!({ "error": "Too Many Requests" });
+        Ok(
+            warp::reply::with_status(warp::reply::json(&body), StatusCode::TOO_MANY_REQUESTS)
+                .into_response(),
+        )
+    } else {
+        Err(r)
+    }
+}
@@ -0,0 +1,499 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Static analysis of the policies file, run whenever it is loaded.
+//!
+//! Validation rejects policies that are ill-typed; these checks find well-typed policies
+//! that probably don't do what their author meant: permits that another permit makes
+//! redundant, policies whose conditions can never hold, forbids that no permit could
+//! overrule, templates that nothing links, and `@id` annotations that give two policies the
+//! same id, or an id reserved for the server's own policies. Each finding is a [`Warning`]
+//! naming the policy and where it is in the file.
+//!
+//! The checks work on the policies' scopes, comparing conditions only for equality, so they
+//! find the plain cases rather than every one. Policies the server adds (for share roles
+//! and profiles) are checked against, but not warned about.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use cedar_policy::{Effect, EntityUid, Policy, PolicyId, PolicySet, Template};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+use tracing::warn;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LintConfig {
+    /// Refuse to load policies with warnings, rather than only logging them
+    pub deny_warnings: bool,
+}
+
+/// A position in a policies file; lines and columns count from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where a policy or template is in a policies file, from its first character to its `;`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub file: PathBuf,
+    pub start: Position,
+    pub end: Position,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Position { line, column } = self.start;
+        write!(f, "{}:{line}:{column}", self.file.display())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum WarningKind {
+    #[error("it is redundant: {0} permits every request it does")]
+    Shadowed(PolicyId),
+    #[error("it can never apply: {0}")]
+    Unreachable(String),
+    #[error("it never applies: no permit allows a request it matches")]
+    IneffectiveForbid,
+    #[error("the template is never linked, and no share role links it")]
+    UnlinkedTemplate,
+    #[error("its id is also the id of {0}")]
+    IdCollision(String),
+    #[error("its id starts with `{0}`, which is reserved for the policies the server makes")]
+    ReservedId(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub policy: PolicyId,
+    /// Unset for the policies the server adds
+    pub location: Option<Location>,
+    pub kind: WarningKind,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{location}: ")?;
+        }
+        write!(f, "policy {}: {}", self.policy, self.kind)
+    }
+}
+
+/// Warnings that stopped the policies from loading, one per line
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{}", .0.iter().join("\n"))]
+pub struct Warnings(pub Vec<Warning>);
+
+/// Where each policy and template of a policies file is, by the id it has once it is named
+/// after its `@id` annotation
+#[derive(Debug, Clone, Default)]
+pub struct Locations {
+    by_id: HashMap<PolicyId, Vec<Location>>,
+}
+
+impl Locations {
+    /// The locations of the policies and templates in `src`, read from `file`, which parse
+    /// as `policies`
+    pub fn new(file: &Path, src: &str, policies: &PolicySet) -> Self {
+        let mut by_id: HashMap<PolicyId, Vec<Location>> = HashMap::new();
+        // Cedar names the policies and templates of a file `policy0`, `policy1`, and so on,
+        // in the order they appear
+        for (i, (start, end)) in statements(src).into_iter().enumerate() {
+            let raw = PolicyId::new(format!("policy{i}"));
+            let annotation = match (policies.policy(&raw), policies.template(&raw)) {
+                (Some(policy), _) => policy.annotation("id"),
+                (None, Some(template)) => template.annotation("id"),
+                (None, None) => continue,
+            };
+            let id = annotation.map_or(raw, PolicyId::new);
+            let location = Location {
+                file: file.into(),
+                start,
+                end,
+            };
+            by_id.entry(id).or_default().push(location);
+        }
+        Self { by_id }
+    }
+
+    /// Where the policy or template `id` is, if it is in the file
+    pub fn get(&self, id: &PolicyId) -> Option<&Location> {
+        self.by_id.get(id).and_then(|locations| locations.first())
+    }
+
+    /// The policies whose ids collide, either with another policy in the file or with one of
+    /// the `added` policies. Unlike other warnings, these stop the policies from loading.
+    pub fn collisions<'a>(&self, added: impl IntoIterator<Item = &'a PolicyId>) -> Vec<Warning> {
+        let added: HashSet<_> = added.into_iter().collect();
+        let mut warnings = Vec::new();
+        for (id, locations) in &self.by_id {
+            let (first, rest) = locations.split_first().expect("every id has a location");
+            let other = |location: &Location| Warning {
+                policy: id.clone(),
+                location: Some(location.clone()),
+                kind: WarningKind::IdCollision(format!("the policy at {first}")),
+            };
+            warnings.extend(rest.iter().map(other));
+            if added.contains(id) {
+                warnings.extend(locations.iter().map(|location| Warning {
+                    policy: id.clone(),
+                    location: Some(location.clone()),
+                    kind: WarningKind::IdCollision(
+                        "a policy the server adds for a share role or profile".to_string(),
+                    ),
+                }));
+            }
+        }
+        sort(&mut warnings);
+        warnings
+    }
+}
+
+/// The policies and templates of `policies` whose ids start with `prefix`, which is reserved
+/// for the policies the server makes. Like collisions, these stop the policies from loading.
+pub fn reserved(policies: &PolicySet, locations: &Locations, prefix: &'static str) -> Vec<Warning> {
+    let ids = policies
+        .policies()
+        .map(Policy::id)
+        .chain(policies.templates().map(Template::id));
+    let mut warnings = ids
+        .filter(|id| id.to_string().starts_with(prefix))
+        .map(|id| Warning {
+            policy: id.clone(),
+            location: locations.get(id).cloned(),
+            kind: WarningKind::ReservedId(prefix),
+        })
+        .collect_vec();
+    sort(&mut warnings);
+    warnings
+}
+
+/// Lints `policies`, whose file policies are at `locations`, and logs each warning. With
+/// `deny_warnings`, any warning is an error. The `role_templates` are never unlinked.
+pub fn check(
+    policies: &PolicySet,
+    locations: &Locations,
+    role_templates: &[PolicyId],
+    config: &LintConfig,
+) -> Result<(), Warnings> {
+    let warnings = lint(policies, locations, role_templates);
+    for warning in &warnings {
+        warn!("Policy lint: {warning}");
+    }
+    if config.deny_warnings && !warnings.is_empty() {
+        Err(Warnings(warnings))
+    } else {
+        Ok(())
+    }
+}
+
+/// The warnings for the policies and templates of `policies` that are in the policies file,
+/// in the order they appear there
+pub fn lint(
+    policies: &PolicySet,
+    locations: &Locations,
+    role_templates: &[PolicyId],
+) -> Vec<Warning> {
+    let statics = policies.policies().map(|policy| {
+        Shape::new(
+            policy.id(),
+            locations,
+            policy.effect(),
+            policy.to_json().ok(),
+        )
+    });
+    let templates = policies.templates().map(|template| {
+        Shape::new(
+            template.id(),
+            locations,
+            template.effect(),
+            template.to_json().ok(),
+        )
+    });
+    let shapes = statics
+        .chain(templates)
+        .sorted_by_key(|shape| (shape.location.is_none(), shape.location.clone()))
+        .collect_vec();
+    let linked: HashSet<_> = policies
+        .policies()
+        .filter_map(|p| p.template_id())
+        .collect();
+    let is_template = |id: &PolicyId| policies.template(id).is_some();
+
+    let mut warnings = Vec::new();
+    for (i, shape) in shapes.iter().enumerate() {
+        let Some(location) = &shape.location else {
+            continue;
+        };
+        let warning = |kind: WarningKind| Warning {
+            policy: shape.id.clone(),
+            location: Some(location.clone()),
+            kind,
+        };
+        if let Some(reason) = shape.unreachable() {
+            warnings.push(warning(WarningKind::Unreachable(reason.to_string())));
+        } else if is_template(&shape.id) {
+            if !linked.contains(&shape.id) && !role_templates.contains(&shape.id) {
+                warnings.push(warning(WarningKind::UnlinkedTemplate));
+            }
+        } else if shape.permit {
+            // Of two permits that make each other redundant, only the later is reported
+            let shadowing = shapes.iter().enumerate().find(|(j, other)| {
+                *j != i
+                    && other.permit
+                    && !is_template(&other.id)
+                    && other.unreachable().is_none()
+                    && other.covers(shape)
+                    && (*j < i || !shape.covers(other))
+            });
+            if let Some((_, other)) = shadowing {
+                warnings.push(warning(WarningKind::Shadowed(other.id.clone())));
+            }
+        } else {
+            let overruled = shapes.iter().any(|other| {
+                other.permit && other.unreachable().is_none() && other.overlaps(shape)
+            });
+            if !overruled {
+                warnings.push(warning(WarningKind::IneffectiveForbid));
+            }
+        }
+    }
+    sort(&mut warnings);
+    warnings
+}
+
+fn sort(warnings: &mut [Warning]) {
+    warnings.sort_by_key(|w| {
+        (
+            w.location.is_none(),
+            w.location.clone(),
+            w.policy.to_string(),
+        )
+    });
+}
+
+// The start and end of each policy and template in Cedar source. Each ends with a `;` outside
+// any brackets, strings and comments.
+fn statements(src: &str) -> Vec<(Position, Position)> {
+    let mut statements = Vec::new();
+    let mut start = None;
+    let mut depth = 0usize;
+    let (mut in_string, mut escaped, mut in_comment) = (false, false, false);
+    let (mut line, mut column) = (1, 0);
+    let mut chars = src.chars();
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            (line, column) = (line + 1, 0);
+            in_comment = false;
+            continue;
+        }
+        column += 1;
+        let here = Position { line, column };
+        if in_comment {
+            continue;
+        }
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match c {
+            '/' if chars.clone().next() == Some('/') => {
+                in_comment = true;
+                continue;
+            }
+            ';' if depth == 0 => {
+                if let Some(start) = start.take() {
+                    statements.push((start, here));
+                }
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            '"' => in_string = true,
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+        start.get_or_insert(here);
+    }
+    statements
+}
+
+/// A scope constraint on the principal, action or resource, in the terms these checks use
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Scope {
+    Any,
+    Eq(EntityUid),
+    /// In any of these entities; only an action is constrained to be in a list of them
+    In(Vec<EntityUid>),
+    /// Of this type, and in this entity if set
+    Is(String, Option<EntityUid>),
+    /// A slot, or anything these checks do not understand
+    Unknown,
+}
+
+impl Scope {
+    // In Cedar's JSON policy format, a scope constraint is e.g. `{"op": "All"}`,
+    // `{"op": "==", "entity": {"type": "List", "id": "0"}}` or
+    // `{"op": "is", "entity_type": "List", "in": {"entity": ...}}`
+    fn from_json(json: &Value) -> Self {
+        let entity = |value: &Value| EntityUid::from_json(value.clone()).ok();
+        let scope = match json["op"].as_str() {
+            Some("All") => Some(Scope::Any),
+            Some("==") => json.get("entity").and_then(entity).map(Scope::Eq),
+            Some("in") => match (json.get("entity"), &json["entities"]) {
+                (Some(e), _) => entity(e).map(|e| Scope::In(vec![e])),
+                (None, Value::Array(es)) => {
+                    es.iter().map(entity).collect::<Option<_>>().map(Scope::In)
+                }
+                (None, _) => None,
+            },
+            Some("is") => {
+                let ty = json["entity_type"].as_str().map(str::to_string);
+                match json.get("in") {
+                    None => ty.map(|ty| Scope::Is(ty, None)),
+                    Some(within) => ty
+                        .zip(within.get("entity").and_then(entity))
+                        .map(|(ty, e)| Scope::Is(ty, Some(e))),
+                }
+            }
+            _ => None,
+        };
+        scope.unwrap_or(Scope::Unknown)
+    }
+
+    fn entity_type(&self) -> Option<String> {
+        match self {
+            Scope::Eq(uid) => Some(uid.type_name().to_string()),
+            Scope::Is(ty, _) => Some(ty.clone()),
+            _ => None,
+        }
+    }
+
+    /// Whether everything `other` matches, this matches
+    fn covers(&self, other: &Scope) -> bool {
+        match (self, other) {
+            (Scope::Any, _) => true,
+            (Scope::Eq(a), Scope::Eq(b)) => a == b,
+            (Scope::In(a), Scope::Eq(b) | Scope::Is(_, Some(b))) => a.contains(b),
+            (Scope::In(a), Scope::In(b)) => b.iter().all(|e| a.contains(e)),
+            (Scope::Is(ty, None), _) => other.entity_type().as_ref() == Some(ty),
+            (Scope::Is(ty, Some(a)), Scope::Is(other_ty, Some(b))) => ty == other_ty && a == b,
+            _ => false,
+        }
+    }
+
+    /// Whether no principal or resource matches both
+    fn disjoint_entities(&self, other: &Scope) -> bool {
+        match (self, other) {
+            (Scope::Eq(a), Scope::Eq(b)) => a != b,
+            _ => matches!(
+                (self.entity_type(), other.entity_type()),
+                (Some(a), Some(b)) if a != b
+            ),
+        }
+    }
+
+    /// Whether no action matches both. Action groups are taken to be just the actions listed.
+    fn disjoint_actions(&self, other: &Scope) -> bool {
+        let actions = |scope: &Scope| match scope {
+            Scope::Eq(a) => Some(vec![a.clone()]),
+            Scope::In(a) => Some(a.clone()),
+            _ => None,
+        };
+        match (actions(self), actions(other)) {
+            (Some(a), Some(b)) => a.iter().all(|action| !b.contains(action)),
+            _ => false,
+        }
+    }
+}
+
+/// A policy or template, as far as these checks understand it
+struct Shape {
+    id: PolicyId,
+    location: Option<Location>,
+    permit: bool,
+    principal: Scope,
+    action: Scope,
+    resource: Scope,
+    /// In Cedar's JSON policy format: `{"kind": "when" | "unless", "body": ...}`
+    conditions: Vec<Value>,
+}
+
+impl Shape {
+    // A policy that can't be converted to JSON is taken to match any request, under some
+    // condition
+    fn new(id: &PolicyId, locations: &Locations, effect: Effect, json: Option<Value>) -> Self {
+        let json = json.unwrap_or_else(|| json!({ "conditions": [{ "kind": "unknown" }] }));
+        Self {
+            id: id.clone(),
+            location: locations.get(id).cloned(),
+            permit: effect == Effect::Permit,
+            principal: Scope::from_json(&json["principal"]),
+            action: Scope::from_json(&json["action"]),
+            resource: Scope::from_json(&json["resource"]),
+            conditions: json["conditions"].as_array().cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Why no request satisfies the conditions, if that is plain
+    fn unreachable(&self) -> Option<&'static str> {
+        let bodies = |kind: &str| {
+            self.conditions
+                .iter()
+                .filter(move |c| c["kind"] == kind)
+                .map(|c| &c["body"])
+        };
+        if bodies("when").any(|body| body == &json!({ "Value": false })) {
+            Some("a `when` condition is `false`")
+        } else if bodies("unless").any(|body| body == &json!({ "Value": true })) {
+            Some("an `unless` condition is `true`")
+        } else if bodies("when").any(|body| bodies("unless").any(|other| other == body)) {
+            Some("a `when` condition is also an `unless` condition")
+        } else {
+            None
+        }
+    }
+
+    /// Whether this matches every request `other` does: its scope is as wide, and its
+    /// conditions are among `other`'s
+    fn covers(&self, other: &Shape) -> bool {
+        self.principal.covers(&other.principal)
+            && self.action.covers(&other.action)
+            && self.resource.covers(&other.resource)
+            && self.conditions.iter().all(|c| other.conditions.contains(c))
+    }
+
+    /// Whether the scopes of this and `other` might both match a request
+    fn overlaps(&self, other: &Shape) -> bool {
+        !self.principal.disjoint_entities(&other.principal)
+            && !self.action.disjoint_actions(&other.action)
+            && !self.resource.disjoint_entities(&other.resource)
+    }
+}
@@ -14,66 +14,240 @@
  * limitations under the License.
  */
 
-mod api;
-mod context;
-mod entitystore;
-mod objects;
-mod policy_store;
-mod util;
+use std::{net::SocketAddr, path::PathBuf};
 
-use context::AppContext;
-use std::num::ParseIntError;
-use thiserror::Error;
-use tracing::error;
-use tracing_subscriber::EnvFilter;
+use clap::{Parser, Subcommand};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tiny_todo_server::{
+    api::serve_api,
+    config::{Config, ConfigError, LogFormat, TlsConfig},
+    context::AppContext,
+    profiles::PolicyProfile,
+    snapshot,
+    telemetry::{self, TelemetryConfig, TelemetryError},
+};
+use tracing::{error, info};
+use tracing_subscriber::{filter::LevelFilter, prelude::*, EnvFilter};
+
+/// The TinyTodo server
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// The port to listen on, overriding the port of the bind address
+    port: Option<u16>,
+    /// A TOML config file; see `tinytodo.toml` for the available settings
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// The address and port to listen on [default: 127.0.0.1:8080]
+    #[arg(long)]
+    bind: Option<SocketAddr>,
+    /// The initial entities (users, teams and lists)
+    #[arg(long)]
+    entities: Option<PathBuf>,
+    /// The Cedar schema
+    #[arg(long)]
+    schema: Option<PathBuf>,
+    /// The Cedar policies, which are reloaded when the file changes
+    #[arg(long)]
+    policies: Option<PathBuf>,
+    /// Model tasks as entities, for per-task authorization; see `task_entities` in `tinytodo.toml`
+    #[arg(long)]
+    task_entities: bool,
+    /// Add a policy profile's policies to those in the policies file; may be repeated
+    #[arg(long = "profile", value_enum)]
+    profiles: Vec<PolicyProfile>,
+    /// Cache authorization decisions, with the default capacity unless configured
+    #[arg(long)]
+    decision_cache: bool,
+    /// Refuse to load policies that the policy linter warns about
+    #[arg(long)]
+    deny_lint_warnings: bool,
+    #[arg(long, value_enum)]
+    log_format: Option<LogFormat>,
+    /// Save the entity store here after every change, and restore it at startup
+    #[arg(long)]
+    persistence_dir: Option<PathBuf>,
+    /// Serve HTTPS using this PEM certificate chain (requires `--tls-key`)
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// The PEM private key for `--tls-cert`
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Admin commands, run instead of the server
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Export the entities, schema, policies, templates and template links to a snapshot archive
+    Export { archive: PathBuf },
+    /// Replace the entities, schema, policies, templates and template links with those in a
+    /// snapshot archive. Stop the server first, or it will save its own state over them.
+    Import { archive: PathBuf },
+}
+
+impl Cli {
+    /// Read the config file, if any, and apply the command line overrides. The result is
+    /// validated by the caller, as what is checked depends on the command.
+    fn config(self) -> Result<Config, ConfigError> {
+        let mut config = match &self.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        if let Some(bind) = self.bind {
+            config.bind = bind;
+        }
+        if let Some(port) = self.port {
+            config.bind.set_port(port);
+        }
+        if let Some(entities) = self.entities {
+            config.entities = entities;
+        }
+        if let Some(schema) = self.schema {
+            config.schema = schema;
+        }
+        if let Some(policies) = self.policies {
+            config.policies = policies;
+        }
+        if self.task_entities {
+            config.task_entities = true;
+        }
+        config.profiles.extend(self.profiles);
+        if self.decision_cache {
+            config.decision_cache.get_or_insert_with(Default::default);
+        }
+        if self.deny_lint_warnings {
+            config.lint.deny_warnings = true;
+        }
+        if let Some(log_format) = self.log_format {
+            config.log_format = log_format;
+        }
+        if let Some(dir) = self.persistence_dir {
+            config.persistence_dir = Some(dir);
+        }
+        if let (Some(cert), Some(key)) = (self.tls_cert, self.tls_key) {
+            config.tls = Some(TlsConfig {
+                cert,
+                key,
+                ..config.tls.unwrap_or_default()
+            });
+        }
+        Ok(config)
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    init_logger();
-    let (schema_path, policies_path) = if cfg!(feature = "use-templates") {
-        (
-            "./tinytodo-templates.cedarschema",
-            "./policies-templates.cedar",
-        )
-    } else {
-        ("./tinytodo.cedarschema", "./policies.cedar")
+    let mut cli = Cli::parse();
+    let command = cli.command.take();
+    let config = cli.config().and_then(|config| {
+        match &command {
+            Some(Command::Import { .. }) => config.validate_for_import()?,
+            _ => config.validate()?,
+        }
+        Ok(config)
+    });
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            std::process::exit(2);
+        }
     };
-    let app = match AppContext::spawn("./entities.json", schema_path, policies_path) {
-        Ok(app) => app,
+    if let Some(command) = command {
+        let (result, done) = match command {
+            Command::Export { archive } => (snapshot::export(&config, &archive), "Exported"),
+            Command::Import { archive } => (snapshot::import(&config, &archive), "Imported"),
+        };
+        match result {
+            Ok(()) => eprintln!("{done} snapshot"),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let tracer_provider = match init_logger(config.log_format, config.telemetry.as_ref()) {
+        Ok(provider) => provider,
         Err(e) => {
-            error!("Failed to load entities, policies, or schema: {e}");
+            eprintln!("Error setting up telemetry: {e}");
             std::process::exit(1);
         }
     };
-    let args = std::env::args().collect::<Vec<_>>();
-
-    match get_port(&args) {
-        Ok(port) => crate::api::serve_api(app, port).await,
+    let app = match AppContext::spawn(&config) {
+        Ok(app) => app,
         Err(e) => {
-            eprintln!("Usage: {} <port>?\n{}", args[0], e);
+            error!("Failed to load entities, policies, or schema: {e}");
             std::process::exit(1);
         }
+    };
+    serve_api(app.clone(), &config, shutdown_signal()).await;
+    info!("Draining queries");
+    if let Err(e) = AppContext::shutdown(&app).await {
+        error!("Error stopping the application server: {e}");
+        std::process::exit(1);
     }
-}
-
-fn init_logger() {
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .pretty()
-        .with_env_filter(EnvFilter::from_default_env())
-        .finish();
-    if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
-        eprintln!("Error setting up tracing: {e}");
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Error flushing spans: {e}");
+        }
     }
 }
 
-#[derive(Debug, Clone, Error)]
-enum ArgError {
-    #[error("Couldn't parse port number. Expected a valid integer port number. {0}")]
-    Parse(#[from] ParseIntError),
+// Completes on SIGTERM or Ctrl-C
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Error listening for Ctrl-C: {e}");
+            std::future::pending::<()>().await
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(e) => {
+                error!("Error listening for SIGTERM: {e}");
+                std::future::pending::<()>().await
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = ctrl_c => (),
+        () = terminate => (),
+    }
 }
 
-fn get_port(args: &[String]) -> Result<u16, ArgError> {
-    let arg = args.get(1).map(String::as_str).unwrap_or("8080");
-    let port: u16 = arg.parse()?;
-    Ok(port)
+/// Log with `format`, filtered by `RUST_LOG`, and export spans if telemetry is configured.
+/// The tracer provider, if any, must be shut down before exiting.
+fn init_logger(
+    format: LogFormat,
+    telemetry: Option<&TelemetryConfig>,
+) -> Result<Option<SdkTracerProvider>, TelemetryError> {
+    let provider = telemetry.map(telemetry::tracer_provider).transpose()?;
+    let fmt = tracing_subscriber::fmt::layer();
+    let fmt = match format {
+        LogFormat::Pretty => fmt.pretty().boxed(),
+        LogFormat::Compact => fmt.compact().boxed(),
+        LogFormat::Json => fmt.json().boxed(),
+    };
+    // Spans are exported whatever the log level
+    let spans = provider
+        .as_ref()
+        .map(|p| telemetry::layer(p).with_filter(LevelFilter::INFO));
+    let result = tracing_subscriber::registry()
+        .with(fmt.with_filter(EnvFilter::from_default_env()))
+        .with(spans)
+        .try_init();
+    if let Err(e) = result {
+        eprintln!("Error setting up tracing: {e}");
+    }
+    Ok(provider)
 }
@@ -0,0 +1,186 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Prometheus metrics, served at `/metrics`.
+
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge, Encoder, Histogram, HistogramVec, IntCounterVec, IntGauge, TextEncoder,
+};
+use warp::{filters::log::Info, http::StatusCode, Reply};
+
+lazy_static! {
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "tinytodo_http_requests_total",
+        "HTTP requests, by route, method and status",
+        &["route", "method", "status"]
+    )
+    .unwrap();
+    pub static ref HTTP_REQUEST_SECONDS: HistogramVec = register_histogram_vec!(
+        "tinytodo_http_request_duration_seconds",
+        "HTTP request latency, by route and method",
+        &["route", "method"]
+    )
+    .unwrap();
+    pub static ref AUTHZ_DECISIONS: IntCounterVec = register_int_counter_vec!(
+        "tinytodo_authorization_decisions_total",
+        "Authorization decisions, by action and decision",
+        &["action", "decision"]
+    )
+    .unwrap();
+    pub static ref IS_AUTHORIZED_SECONDS: Histogram = register_histogram!(
+        "tinytodo_is_authorized_duration_seconds",
+        "Latency of Authorizer::is_authorized",
+        authz_buckets()
+    )
+    .unwrap();
+    pub static ref AS_ENTITIES_SECONDS: Histogram = register_histogram!(
+        "tinytodo_as_entities_duration_seconds",
+        "Latency of building the Cedar entities from the entity store",
+        authz_buckets()
+    )
+    .unwrap();
+    pub static ref DECISION_CACHE: IntCounterVec = register_int_counter_vec!(
+        "tinytodo_decision_cache_total",
+        "Lookups in the decision cache, by result (hit or miss)",
+        &["result"]
+    )
+    .unwrap();
+    pub static ref POLICY_RELOADS: IntCounterVec = register_int_counter_vec!(
+        "tinytodo_policy_reloads_total",
+        "Attempts to reload the policies file, by result (success or failure)",
+        &["result"]
+    )
+    .unwrap();
+    pub static ref POLICIES: IntGauge = register_int_gauge!(
+        "tinytodo_policies",
+        "The number of policies in effect, including template-linked policies"
+    )
+    .unwrap();
+}
+
+// Authorization takes microseconds to milliseconds: 10us up to about 2.6s
+fn authz_buckets() -> Vec<f64> {
+    exponential_buckets(0.000_01, 4.0, 10).unwrap()
+}
+
+// The routes without path parameters. Any other path that is not a v2 list route is
+// reported as "other", so request paths can't create unbounded label values.
+const ROUTES: &[&str] = &[
+    "/api/list/get",
+    "/api/list/create",
+    "/api/list/update",
+    "/api/list/delete",
+    "/api/list/transfer",
+    "/api/list/duplicate",
+    "/api/list/access",
+    "/api/list/events",
+    "/api/task/create",
+    "/api/task/update",
+    "/api/task/delete",
+    "/api/comment/create",
+    "/api/comment/update",
+    "/api/comment/delete",
+    "/api/attachment/create",
+    "/api/attachment/delete",
+    "/api/lists/get",
+    "/api/share",
+    "/api/capabilities",
+    "/api/authorize",
+    "/api/openapi.json",
+    "/api/v2/lists",
+    "/api/v2/capabilities",
+    "/api/v2/authorize",
+    "/",
+    "/app.js",
+    "/style.css",
+    "/healthz",
+    "/readyz",
+    "/metrics",
+];
+
+/// The route a request path matched, with ids replaced by placeholders
+fn route(path: &str) -> String {
+    let trimmed = path.trim_matches('/');
+    let segments: Vec<&str> = trimmed.split('/').collect();
+    match segments.as_slice() {
+        ["api", "v2", "lists", _] => "/api/v2/lists/{id}".to_string(),
+        ["api", "v2", "lists", _, sub @ ("access" | "events" | "tasks" | "shares")] => {
+            format!("/api/v2/lists/{{id}}/{sub}")
+        }
+        ["api", "v2", "lists", _, sub @ ("owner" | "duplicate")] => {
+            format!("/api/v2/lists/{{id}}/{sub}")
+        }
+        ["api", "v2", "lists", _, "tasks", _] => "/api/v2/lists/{id}/tasks/{task}".to_string(),
+        ["api", "v2", "lists", _, "tasks", _, sub @ ("comments" | "attachments")] => {
+            format!("/api/v2/lists/{{id}}/tasks/{{task}}/{sub}")
+        }
+        ["api", "v2", "lists", _, "tasks", _, "comments", _] => {
+            "/api/v2/lists/{id}/tasks/{task}/comments/{comment}".to_string()
+        }
+        ["api", "v2", "lists", _, "tasks", _, "attachments", _] => {
+            "/api/v2/lists/{id}/tasks/{task}/attachments/{attachment}".to_string()
+        }
+        _ => ROUTES
+            .iter()
+            .find(|r| r.trim_matches('/') == trimmed)
+            .map_or_else(|| "other".to_string(), |r| r.to_string()),
+    }
+}
+
+/// Record a served request; used with `warp::log::custom`
+pub fn record_request(info: Info<'_>) {
+    let route = route(info.path());
+    let method = info.method().as_str();
+    HTTP_REQUESTS
+        .with_label_values(&[&route, method, info.status().as_str()])
+        .inc();
+    HTTP_REQUEST_SECONDS
+        .with_label_values(&[&route, method])
+        .observe(info.elapsed().as_secs_f64());
+}
+
+/// Record the decision for an authorization request
+pub fn record_decision(action: &str, allowed: bool) {
+    let decision = if allowed { "allow" } else { "deny" };
+    AUTHZ_DECISIONS.with_label_values(&[action, decision]).inc();
+}
+
+/// Record whether the decision cache held a decision
+pub fn record_cache_lookup(hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    DECISION_CACHE.with_label_values(&[result]).inc();
+}
+
+/// Record the outcome of reloading the policies file
+pub fn record_reload(success: bool) {
+    let result = if success { "success" } else { "failure" };
+    POLICY_RELOADS.with_label_values(&[result]).inc();
+}
+
+/// The current metrics, in the Prometheus text format
+pub fn metrics() -> warp::reply::Response {
+    let encoder = TextEncoder::new();
+    let mut buf = vec![];
+    match encoder.encode(&prometheus::gather(), &mut buf) {
+        Ok(()) => {
+            warp::reply::with_header(buf, "content-type", encoder.format_type()).into_response()
+        }
+        Err(e) => warp::reply::with_status(e.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+            .into_response(),
+    }
+}
@@ -14,15 +14,20 @@
  * limitations under the License.
  */
 
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::Arc};
+
+#[cfg(not(feature = "use-templates"))]
+use std::collections::BTreeMap;
 
 use cedar_policy::{Entity, EvalResult, RestrictedExpression};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use crate::{
     context::APPLICATION_TINY_TODO,
     entitystore::{EntityDecodeError, EntityStore},
-    util::{EntityUid, ListUid, TeamUid, UserUid},
+    shares::now,
+    util::{EntityUid, ListUid, TaskUid, TeamUid, UserUid},
 };
 
 #[cfg(not(feature = "use-templates"))]
@@ -80,6 +85,16 @@ impl User {
             parents: [parent].into_iter().collect(),
         }
     }
+
+    /// The attributes of the user's Cedar entity, for logging
+    pub fn attributes(&self) -> serde_json::Map<String, serde_json::Value> {
+        [
+            ("joblevel".to_string(), self.joblevel.into()),
+            ("location".to_string(), self.location.clone().into()),
+        ]
+        .into_iter()
+        .collect()
+    }
 }
 
 impl From<User> for Entity {
@@ -152,16 +167,25 @@ impl UserOrTeam for Team {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct List {
     uid: ListUid,
     owner: UserUid,
     name: String,
     tasks: Vec<Task>, // Invariant, `tasks` must be sorted
+    // The id of the next task, so that ids are never reused, even of deleted tasks. Lists
+    // saved without it continue from their newest task.
+    #[serde(default)]
+    next_task: i64,
     #[cfg(not(feature = "use-templates"))]
     readers: TeamUid,
     #[cfg(not(feature = "use-templates"))]
     editors: TeamUid,
+    /// The teams for the configured share roles, each made when the list is first shared
+    /// in the role; they are the tags of the list's entity
+    #[cfg(not(feature = "use-templates"))]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    roles: BTreeMap<ShareRole, TeamUid>,
 }
 
 impl List {
@@ -180,8 +204,10 @@ impl List {
                 owner,
                 name,
                 tasks: vec![],
+                next_task: 0,
                 readers: readers_uid,
                 editors: writers_uid,
+                roles: BTreeMap::new(),
             }
         }
         #[cfg(feature = "use-templates")]
@@ -190,6 +216,7 @@ impl List {
             owner,
             name,
             tasks: vec![],
+            next_task: 0,
         }
     }
 
@@ -197,13 +224,50 @@ impl List {
         &self.uid
     }
 
-    pub fn create_task(&mut self, description: String) -> i64 {
-        let id = self.tasks.len() as i64;
-        let task = Task::new(id, description);
+    pub fn owner(&self) -> &UserUid {
+        &self.owner
+    }
+
+    pub fn set_owner(&mut self, owner: UserUid) {
+        self.owner = owner;
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn num_tasks(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// The list's tasks, ordered by id
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    /// The id the list's next task will have
+    pub fn next_task_id(&self) -> i64 {
+        self.tasks
+            .last()
+            .map_or(0, |t| t.id + 1)
+            .max(self.next_task)
+    }
+
+    pub fn create_task(&mut self, description: String, assignee: Option<UserUid>) -> i64 {
+        let id = self.next_task_id();
+        self.next_task = id + 1;
+        let task = Task {
+            assignee,
+            ..Task::new(id, description)
+        };
         self.tasks.push(task);
         id
     }
 
+    pub fn get_task(&self, id: i64) -> Option<&Task> {
+        self.tasks.iter().find(|task| task.id == id)
+    }
+
     pub fn get_task_mut(&mut self, id: i64) -> Option<&mut Task> {
         self.tasks.iter_mut().find(|task| task.id == id)
     }
@@ -222,57 +286,123 @@ impl List {
         self.name = name;
     }
 
+    /// Copies of the list's tasks, without their assignees, comments and attachments
+    pub fn copy_tasks(&self) -> Vec<Task> {
+        self.tasks
+            .iter()
+            .map(|task| Task {
+                state: task.state,
+                ..Task::new(task.id, task.name.clone())
+            })
+            .collect()
+    }
+
+    /// Replace the list's tasks with `tasks`, which must be sorted by id
+    pub fn set_tasks(&mut self, tasks: Vec<Task>) {
+        self.tasks = tasks;
+    }
+
+    /// The team for `role`, if the list has one
     #[cfg(not(feature = "use-templates"))]
-    pub fn get_team(&self, role: ShareRole) -> &TeamUid {
-        match role {
-            ShareRole::Reader => &self.readers,
-            ShareRole::Editor => &self.editors,
+    pub fn get_team(&self, role: &ShareRole) -> Option<&TeamUid> {
+        if role == &ShareRole::reader() {
+            Some(&self.readers)
+        } else if role == &ShareRole::editor() {
+            Some(&self.editors)
+        } else {
+            self.roles.get(role)
         }
     }
+
+    #[cfg(not(feature = "use-templates"))]
+    pub fn set_role_team(&mut self, role: ShareRole, team: TeamUid) {
+        self.roles.insert(role, team);
+    }
 }
 
-impl From<List> for Entity {
-    fn from(value: List) -> Self {
-        let attrs = [
-            (
-                "owner",
-                format!("{}", value.owner.as_ref()).parse().unwrap(),
-            ),
-            ("name", RestrictedExpression::new_string(value.name)),
-            (
-                "tasks",
-                RestrictedExpression::new_set(value.tasks.into_iter().map(|t| t.into())),
-            ),
+impl List {
+    /// The list and its tasks as Cedar entities, for when tasks are modeled as entities.
+    /// Each task's parent is the list, which then has no `tasks` attribute.
+    pub fn into_entities_with_tasks(mut self) -> Vec<Entity> {
+        let tasks = std::mem::take(&mut self.tasks);
+        let uid = self.uid.clone();
+        std::iter::once(self.into_entity(false))
+            .chain(tasks.into_iter().map(|task| task.into_entity(&uid)))
+            .collect()
+    }
+
+    fn into_entity(self, with_tasks: bool) -> Entity {
+        let mut attrs = vec![
+            ("owner", format!("{}", self.owner.as_ref()).parse().unwrap()),
+            ("name", RestrictedExpression::new_string(self.name)),
             #[cfg(not(feature = "use-templates"))]
             (
                 "readers",
-                format!("{}", value.readers.as_ref()).parse().unwrap(),
+                format!("{}", self.readers.as_ref()).parse().unwrap(),
             ),
             #[cfg(not(feature = "use-templates"))]
             (
                 "editors",
-                format!("{}", value.editors.as_ref()).parse().unwrap(),
+                format!("{}", self.editors.as_ref()).parse().unwrap(),
             ),
-        ]
-        .into_iter()
-        .map(|(x, v)| (x.into(), v))
-        .collect();
+        ];
+        if with_tasks {
+            attrs.push((
+                "tasks",
+                RestrictedExpression::new_set(self.tasks.into_iter().map(|t| t.into())),
+            ));
+        }
+        let attrs = attrs.into_iter().map(|(x, v)| (x.into(), v)).collect();
 
         // We always have the single parent of the application, so we just hard code that here
         let parents = [APPLICATION_TINY_TODO.clone().into()]
             .into_iter()
             .collect::<HashSet<_>>();
 
-        let euid: EntityUid = value.uid.into();
-        Entity::new(euid.into(), attrs, parents).unwrap()
+        #[cfg(not(feature = "use-templates"))]
+        let tags = self
+            .roles
+            .into_iter()
+            .map(|(role, team)| {
+                let team: EntityUid = team.into();
+                (
+                    role.as_str().to_string(),
+                    RestrictedExpression::new_entity_uid(team.into()),
+                )
+            })
+            .collect::<Vec<_>>();
+        #[cfg(feature = "use-templates")]
+        let tags: Vec<(String, RestrictedExpression)> = vec![];
+
+        let euid: EntityUid = self.uid.into();
+        Entity::new_with_tags(euid.into(), attrs, parents, tags).unwrap()
+    }
+}
+
+impl From<List> for Entity {
+    fn from(value: List) -> Self {
+        value.into_entity(true)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub struct Task {
     id: i64,
     name: String,
     state: TaskState,
+    /// The user the task is assigned to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    assignee: Option<UserUid>,
+    #[serde(default)]
+    comments: Vec<Comment>, // Invariant, `comments` must be sorted
+    #[serde(default)]
+    attachments: Vec<Attachment>, // Invariant, `attachments` must be sorted
+    // The ids of the next comment and attachment, so that ids are never reused, even of
+    // deleted ones. Tasks saved without them continue from their newest.
+    #[serde(default)]
+    next_comment: i64,
+    #[serde(default)]
+    next_attachment: i64,
 }
 
 impl Task {
@@ -281,9 +411,30 @@ impl Task {
             id,
             name,
             state: TaskState::Unchecked,
+            assignee: None,
+            comments: vec![],
+            attachments: vec![],
+            next_comment: 0,
+            next_attachment: 0,
         }
     }
 
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> TaskState {
+        self.state
+    }
+
+    pub fn assignee(&self) -> Option<&UserUid> {
+        self.assignee.as_ref()
+    }
+
     pub fn set_name(&mut self, new: String) {
         self.name = new;
     }
@@ -291,6 +442,145 @@ impl Task {
     pub fn set_state(&mut self, new: TaskState) {
         self.state = new;
     }
+
+    pub fn set_assignee(&mut self, new: Option<UserUid>) {
+        self.assignee = new;
+    }
+
+    /// The id the task's next comment will have
+    pub fn next_comment_id(&self) -> i64 {
+        self.comments
+            .last()
+            .map_or(0, |c| c.id + 1)
+            .max(self.next_comment)
+    }
+
+    pub fn add_comment(&mut self, author: UserUid, body: String) -> i64 {
+        let id = self.next_comment_id();
+        self.next_comment = id + 1;
+        self.comments.push(Comment {
+            id,
+            author,
+            body,
+            timestamp: now(),
+        });
+        id
+    }
+
+    pub fn get_comment(&self, id: i64) -> Option<&Comment> {
+        self.comments.iter().find(|comment| comment.id == id)
+    }
+
+    pub fn get_comment_mut(&mut self, id: i64) -> Option<&mut Comment> {
+        self.comments.iter_mut().find(|comment| comment.id == id)
+    }
+
+    pub fn delete_comment(&mut self, id: i64) -> Option<Comment> {
+        let indx = self.comments.iter().position(|comment| comment.id == id)?;
+        Some(self.comments.remove(indx))
+    }
+
+    pub fn attachments(&self) -> &[Attachment] {
+        &self.attachments
+    }
+
+    pub fn num_attachments(&self) -> usize {
+        self.attachments.len()
+    }
+
+    /// The id the task's next attachment will have
+    pub fn next_attachment_id(&self) -> i64 {
+        self.attachments
+            .last()
+            .map_or(0, |a| a.id + 1)
+            .max(self.next_attachment)
+    }
+
+    pub fn add_attachment(
+        &mut self,
+        author: UserUid,
+        name: String,
+        content_type: String,
+        data: String,
+    ) -> i64 {
+        let id = self.next_attachment_id();
+        self.next_attachment = id + 1;
+        self.attachments.push(Attachment {
+            id,
+            author,
+            name,
+            content_type,
+            data: data.into(),
+        });
+        id
+    }
+
+    pub fn get_attachment(&self, id: i64) -> Option<&Attachment> {
+        self.attachments
+            .iter()
+            .find(|attachment| attachment.id == id)
+    }
+
+    pub fn delete_attachment(&mut self, id: i64) -> Option<Attachment> {
+        let indx = self
+            .attachments
+            .iter()
+            .position(|attachment| attachment.id == id)?;
+        Some(self.attachments.remove(indx))
+    }
+}
+
+/// A comment on a task. Its author may edit and delete it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Comment {
+    id: i64,
+    author: UserUid,
+    body: String,
+    /// When the comment was made or last edited, in seconds since the Unix epoch
+    timestamp: i64,
+}
+
+impl Comment {
+    pub fn author(&self) -> &UserUid {
+        &self.author
+    }
+
+    pub fn edit(&mut self, body: String) {
+        self.body = body;
+        self.timestamp = now();
+    }
+}
+
+/// A small file attached to a task. Its author may delete it. Only the id, author and name
+/// are part of the task's Cedar entity; the contents are shared rather than copied when the
+/// entities are made for an authorization request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Attachment {
+    id: i64,
+    author: UserUid,
+    name: String,
+    content_type: String,
+    /// The file's contents, base64 encoded
+    #[schema(value_type = String)]
+    data: Arc<str>,
+}
+
+impl Attachment {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn author(&self) -> &UserUid {
+        &self.author
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    pub fn data(&self) -> &str {
+        &self.data
+    }
 }
 
 impl PartialOrd for Task {
@@ -312,6 +602,9 @@ impl TryFrom<&EvalResult> for Task {
         let id_field = "id";
         let name_field = "name";
         let state_field = "state";
+        let assignee_field = "assignee";
+        let comments_field = "comments";
+        let attachments_field = "attachments";
         match value {
             EvalResult::Record(rcd) => {
                 let id = get_long(
@@ -329,22 +622,158 @@ impl TryFrom<&EvalResult> for Task {
                     .get(state_field)
                     .ok_or(EntityDecodeError::MissingAttr(state_field))?
                     .try_into()?;
-                Ok(Self { id, name, state })
+                let assignee = rcd
+                    .get(assignee_field)
+                    .map(|e| get_user(e, assignee_field))
+                    .transpose()?;
+                // Cedar sets are unordered, so restore the invariant that these are sorted
+                let mut comments = get_set(rcd.get(comments_field), comments_field)?
+                    .into_iter()
+                    .map(Comment::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+                comments.sort_by_key(|c| c.id);
+                let mut attachments = get_set(rcd.get(attachments_field), attachments_field)?
+                    .into_iter()
+                    .map(Attachment::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+                attachments.sort_by_key(|a| a.id);
+                Ok(Self {
+                    id,
+                    name,
+                    state,
+                    assignee,
+                    comments,
+                    attachments,
+                    next_comment: 0,
+                    next_attachment: 0,
+                })
             }
             _ => Err(EntityDecodeError::WrongType("task", "record")),
         }
     }
 }
 
+impl TryFrom<&EvalResult> for Comment {
+    type Error = EntityDecodeError;
+
+    fn try_from(value: &EvalResult) -> Result<Self, Self::Error> {
+        match value {
+            EvalResult::Record(rcd) => {
+                let field =
+                    |name: &'static str| rcd.get(name).ok_or(EntityDecodeError::MissingAttr(name));
+                Ok(Self {
+                    id: get_long(field("id")?, "id")?,
+                    author: get_user(field("author")?, "author")?,
+                    body: get_string(field("body")?, "body")?.clone(),
+                    timestamp: get_long(field("timestamp")?, "timestamp")?,
+                })
+            }
+            _ => Err(EntityDecodeError::WrongType("comment", "record")),
+        }
+    }
+}
+
+// The contents of an attachment are not part of its entity, so are left empty
+impl TryFrom<&EvalResult> for Attachment {
+    type Error = EntityDecodeError;
+
+    fn try_from(value: &EvalResult) -> Result<Self, Self::Error> {
+        match value {
+            EvalResult::Record(rcd) => {
+                let field =
+                    |name: &'static str| rcd.get(name).ok_or(EntityDecodeError::MissingAttr(name));
+                Ok(Self {
+                    id: get_long(field("id")?, "id")?,
+                    author: get_user(field("author")?, "author")?,
+                    name: get_string(field("name")?, "name")?.clone(),
+                    content_type: String::new(),
+                    data: "".into(),
+                })
+            }
+            _ => Err(EntityDecodeError::WrongType("attachment", "record")),
+        }
+    }
+}
+
+impl Task {
+    fn into_attrs(self) -> impl Iterator<Item = (String, RestrictedExpression)> {
+        let assignee = self.assignee.map(|user| {
+            let user: EntityUid = user.into();
+            (
+                "assignee",
+                RestrictedExpression::new_entity_uid(user.into()),
+            )
+        });
+        [
+            ("id", RestrictedExpression::new_long(self.id)),
+            ("name", RestrictedExpression::new_string(self.name)),
+            (
+                "state",
+                RestrictedExpression::new_string(format!("{}", self.state)),
+            ),
+            (
+                "comments",
+                RestrictedExpression::new_set(self.comments.into_iter().map(|c| c.into())),
+            ),
+            (
+                "attachments",
+                RestrictedExpression::new_set(self.attachments.into_iter().map(|a| a.into())),
+            ),
+        ]
+        .into_iter()
+        .chain(assignee)
+        .map(|(x, v)| (x.to_string(), v))
+    }
+
+    // As an entity, a task also refers to its list, so that policies can reach the list's
+    // owner and sharing teams
+    fn into_entity(self, list: &ListUid) -> Entity {
+        let euid: EntityUid = TaskUid::new(list, self.id).into();
+        let list: EntityUid = list.clone().into();
+        let attrs = self
+            .into_attrs()
+            .chain(std::iter::once((
+                "list".to_string(),
+                RestrictedExpression::new_entity_uid(list.clone().into()),
+            )))
+            .collect();
+        let parents = [list.into()].into_iter().collect::<HashSet<_>>();
+        Entity::new(euid.into(), attrs, parents).unwrap()
+    }
+}
+
 impl From<Task> for RestrictedExpression {
     fn from(value: Task) -> Self {
+        RestrictedExpression::new_record(value.into_attrs()).expect("no duplicate keys!")
+    }
+}
+
+impl From<Comment> for RestrictedExpression {
+    fn from(value: Comment) -> Self {
         let fields = [
             ("id", RestrictedExpression::new_long(value.id)),
-            ("name", RestrictedExpression::new_string(value.name)),
             (
-                "state",
-                RestrictedExpression::new_string(format!("{}", value.state)),
+                "author",
+                RestrictedExpression::new_entity_uid(EntityUid::from(value.author).into()),
             ),
+            ("body", RestrictedExpression::new_string(value.body)),
+            ("timestamp", RestrictedExpression::new_long(value.timestamp)),
+        ]
+        .into_iter()
+        .map(|(x, v)| (x.to_string(), v));
+        RestrictedExpression::new_record(fields).expect("no duplicate keys!")
+    }
+}
+
+impl From<Attachment> for RestrictedExpression {
+    fn from(value: Attachment) -> Self {
+        let fields = [
+            ("id", RestrictedExpression::new_long(value.id)),
+            (
+                "author",
+                RestrictedExpression::new_entity_uid(EntityUid::from(value.author).into()),
+            ),
+            ("name", RestrictedExpression::new_string(value.name)),
         ]
         .into_iter()
         .map(|(x, v)| (x.to_string(), v));
@@ -366,7 +795,27 @@ fn get_string<'a>(e: &'a EvalResult, name: &'static str) -> Result<&'a String, E
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+// Comments and attachments are absent from tasks saved before they were added
+fn get_set<'a>(
+    e: Option<&'a EvalResult>,
+    name: &'static str,
+) -> Result<Vec<&'a EvalResult>, EntityDecodeError> {
+    match e {
+        None => Ok(vec![]),
+        Some(EvalResult::Set(s)) => Ok(s.iter().collect()),
+        Some(_) => Err(EntityDecodeError::WrongType(name, "Set")),
+    }
+}
+
+fn get_user(e: &EvalResult, name: &'static str) -> Result<UserUid, EntityDecodeError> {
+    match e {
+        EvalResult::EntityUid(euid) => UserUid::try_from(EntityUid::from(euid.clone()))
+            .map_err(|_| EntityDecodeError::WrongType(name, "User")),
+        _ => Err(EntityDecodeError::WrongType(name, "User")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub enum TaskState {
     Checked,
     Unchecked,
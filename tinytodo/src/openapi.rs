@@ -0,0 +1,751 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! OpenAPI description of the TinyTodo HTTP API.
+//!
+//! The routes themselves are warp filters (see `api::serve_api`), so each route is
+//! described here by an empty function carrying a `#[utoipa::path]` attribute.
+//! Keep these in sync with `serve_api` when adding or changing a route.
+
+use utoipa::OpenApi;
+
+use crate::{
+    api::{
+        v2::{
+            AccessQuery, AuthorizationBatch, CommentPatch, ListCopy, ListPatch, NewAttachment,
+            NewComment, NewList, NewOwner, NewShare, NewTask, ShareQuery, TaskPatch,
+        },
+        ActionCapability, AddShare, AttachToTask, AuthorizationRequest, Authorize, Capabilities,
+        CommentOnTask, CreateList, CreateTask, Decisions, DeleteAttachment, DeleteComment,
+        DeleteList, DeleteShare, DeleteTask, DuplicateList, EditComment, Empty, ErrorMsg,
+        GetCapabilities, GetList, GetListAccess, GetLists, ListAccess, Readiness, ShareRole,
+        Subscribe, TransferOwnership, UpdateList, UpdateTask,
+    },
+    events::{Change, ChangeEvent},
+    objects::{Attachment, Comment, List, Task, TaskState},
+    util::{EntityUid, ListUid, TeamUid, UserOrTeamUid, UserUid},
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "TinyTodo",
+        description = "A simple application for managing task lists, using Cedar for authorization. \
+                       The v1 routes return errors, including authorization denials, with status 200 and an `ErrorMsg` body. \
+                       The v2 routes under `/api/v2` report errors with a matching HTTP status. \
+                       Any route may respond with status 429 when requests from the caller's IP address exceed the rate limit."
+    ),
+    paths(
+        paths::get_list,
+        paths::create_list,
+        paths::update_list,
+        paths::delete_list,
+        paths::transfer_ownership,
+        paths::duplicate_list,
+        paths::get_list_access,
+        paths::subscribe,
+        paths::create_task,
+        paths::update_task,
+        paths::delete_task,
+        paths::comment_on_task,
+        paths::edit_comment,
+        paths::delete_comment,
+        paths::attach_to_task,
+        paths::delete_attachment,
+        paths::get_lists,
+        paths::get_capabilities,
+        paths::authorize,
+        paths::add_share,
+        paths::delete_share,
+        paths::v2_get_lists,
+        paths::v2_get_capabilities,
+        paths::v2_authorize,
+        paths::v2_create_list,
+        paths::v2_get_list,
+        paths::v2_update_list,
+        paths::v2_delete_list,
+        paths::v2_transfer_ownership,
+        paths::v2_duplicate_list,
+        paths::v2_get_list_access,
+        paths::v2_subscribe,
+        paths::v2_create_task,
+        paths::v2_update_task,
+        paths::v2_delete_task,
+        paths::v2_comment_on_task,
+        paths::v2_edit_comment,
+        paths::v2_delete_comment,
+        paths::v2_attach_to_task,
+        paths::v2_delete_attachment,
+        paths::v2_add_share,
+        paths::v2_delete_share,
+        paths::healthz,
+        paths::readyz,
+        paths::metrics,
+    ),
+    components(schemas(
+        ActionCapability,
+        AddShare,
+        AttachToTask,
+        AuthorizationBatch,
+        AuthorizationRequest,
+        Authorize,
+        Capabilities,
+        CommentOnTask,
+        CreateList,
+        CreateTask,
+        Decisions,
+        DeleteAttachment,
+        DeleteComment,
+        DeleteList,
+        DeleteShare,
+        DeleteTask,
+        DuplicateList,
+        EditComment,
+        Empty,
+        ErrorMsg,
+        GetCapabilities,
+        GetList,
+        GetListAccess,
+        GetLists,
+        ListAccess,
+        Readiness,
+        ShareRole,
+        Subscribe,
+        TransferOwnership,
+        UpdateList,
+        UpdateTask,
+        List,
+        Task,
+        TaskState,
+        Comment,
+        Attachment,
+        EntityUid,
+        ListUid,
+        TeamUid,
+        UserOrTeamUid,
+        UserUid,
+        ListPatch,
+        ListCopy,
+        NewList,
+        NewOwner,
+        NewShare,
+        NewTask,
+        TaskPatch,
+        NewComment,
+        CommentPatch,
+        NewAttachment,
+        Change,
+        ChangeEvent,
+    )),
+    tags(
+        (name = "lists", description = "List CRUD"),
+        (name = "tasks", description = "Task CRUD"),
+        (name = "comments", description = "Comments on and attachments to tasks"),
+        (name = "shares", description = "Sharing lists with users and teams"),
+        (name = "v2", description = "Resource oriented routes, identifying the user with the `x-tinytodo-user` header"),
+        (name = "health", description = "Liveness and readiness probes, and metrics"),
+    )
+)]
+pub struct ApiDoc;
+
+// Documentation-only route stubs. They are never called.
+#[allow(dead_code)]
+mod paths {
+    // Response and body types are referenced by schema name only; see `ApiDoc` for the schemas
+    use super::*;
+
+    #[utoipa::path(
+        get,
+        path = "/api/list/get",
+        tag = "lists",
+        params(GetList),
+        responses((status = 200, description = "The requested list", body = List))
+    )]
+    pub fn get_list() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/list/create",
+        tag = "lists",
+        request_body = CreateList,
+        responses((status = 200, description = "The uid of the new list", body = EntityUid))
+    )]
+    pub fn create_list() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/list/update",
+        tag = "lists",
+        request_body = UpdateList,
+        responses((status = 200, description = "The list was renamed", body = Empty))
+    )]
+    pub fn update_list() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/api/list/delete",
+        tag = "lists",
+        request_body = DeleteList,
+        responses((status = 200, description = "The list was deleted", body = Empty))
+    )]
+    pub fn delete_list() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/list/transfer",
+        tag = "lists",
+        request_body = TransferOwnership,
+        responses((status = 200, description = "The list is owned by the new owner", body = Empty))
+    )]
+    pub fn transfer_ownership() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/list/duplicate",
+        tag = "lists",
+        request_body = DuplicateList,
+        responses((status = 200, description = "The uid of the copy, which has the list's tasks but none of its shares", body = EntityUid))
+    )]
+    pub fn duplicate_list() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/list/access",
+        tag = "lists",
+        params(GetListAccess),
+        responses((status = 200, description = "The users and teams allowed to perform the action on the list", body = ListAccess))
+    )]
+    pub fn get_list_access() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/list/events",
+        tag = "lists",
+        params(Subscribe),
+        responses((
+            status = 200,
+            description = "A stream of server-sent events, one per change to the list. \
+                           The stream ends with an `access_revoked` event once the user may no longer read the list.",
+            content_type = "text/event-stream",
+            body = ChangeEvent
+        ))
+    )]
+    pub fn subscribe() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/task/create",
+        tag = "tasks",
+        request_body = CreateTask,
+        responses((status = 200, description = "The id of the new task", body = i64))
+    )]
+    pub fn create_task() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/task/update",
+        tag = "tasks",
+        request_body = UpdateTask,
+        responses((status = 200, description = "The task was updated", body = Empty))
+    )]
+    pub fn update_task() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/api/task/delete",
+        tag = "tasks",
+        request_body = DeleteTask,
+        responses((status = 200, description = "The task was deleted", body = Empty))
+    )]
+    pub fn delete_task() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/comment/create",
+        tag = "comments",
+        request_body = CommentOnTask,
+        responses((status = 200, description = "The id of the new comment", body = i64))
+    )]
+    pub fn comment_on_task() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/comment/update",
+        tag = "comments",
+        request_body = EditComment,
+        responses((status = 200, description = "The comment was edited", body = Empty))
+    )]
+    pub fn edit_comment() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/api/comment/delete",
+        tag = "comments",
+        request_body = DeleteComment,
+        responses((status = 200, description = "The comment was deleted", body = Empty))
+    )]
+    pub fn delete_comment() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/attachment/create",
+        tag = "comments",
+        request_body = AttachToTask,
+        responses((status = 200, description = "The id of the new attachment", body = i64))
+    )]
+    pub fn attach_to_task() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/api/attachment/delete",
+        tag = "comments",
+        request_body = DeleteAttachment,
+        responses((status = 200, description = "The attachment was deleted", body = Empty))
+    )]
+    pub fn delete_attachment() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/lists/get",
+        tag = "lists",
+        params(GetLists),
+        responses((status = 200, description = "Every list the user may read", body = Vec<List>))
+    )]
+    pub fn get_lists() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/capabilities",
+        tag = "lists",
+        params(GetCapabilities),
+        responses((status = 200, description = "For each action, the residual policies constraining the resources the user may perform it on", body = Capabilities))
+    )]
+    pub fn get_capabilities() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/authorize",
+        tag = "lists",
+        request_body = Authorize,
+        responses((status = 200, description = "Whether each request is allowed, in order", body = Decisions))
+    )]
+    pub fn authorize() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/share",
+        tag = "shares",
+        request_body = AddShare,
+        responses((status = 200, description = "The list was shared", body = Empty))
+    )]
+    pub fn add_share() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/api/share",
+        tag = "shares",
+        request_body = DeleteShare,
+        responses((status = 200, description = "The share was removed", body = Empty))
+    )]
+    pub fn delete_share() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/v2/lists",
+        tag = "v2",
+        params(("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user")),
+        responses(
+            (status = 200, description = "Every list the user may read", body = Vec<List>),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_get_lists() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/v2/capabilities",
+        tag = "v2",
+        params(("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user")),
+        responses(
+            (status = 200, description = "For each action, the residual policies constraining the resources the user may perform it on", body = Capabilities),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_get_capabilities() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/v2/authorize",
+        tag = "v2",
+        params(("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user")),
+        request_body = AuthorizationBatch,
+        responses(
+            (status = 200, description = "Whether each request is allowed, in order", body = Decisions),
+            (status = 400, description = "A request is malformed (e.g., lacks the `author` its action needs), or there are too many", body = ErrorMsg),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "A request names a task that does not exist", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_authorize() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/v2/lists",
+        tag = "v2",
+        params(("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user")),
+        request_body = NewList,
+        responses(
+            (status = 201, description = "The uid of the new list; `Location` gives its path", body = EntityUid),
+            (status = 403, description = "Authorization denied, or the user already owns the maximum number of lists", body = ErrorMsg),
+            (status = 429, description = "Rate limit exceeded", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_create_list() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/v2/lists/{id}",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        responses(
+            (status = 200, description = "The requested list", body = List),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_get_list() {}
+
+    #[utoipa::path(
+        patch,
+        path = "/api/v2/lists/{id}",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        request_body = ListPatch,
+        responses(
+            (status = 200, description = "The list was renamed", body = Empty),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_update_list() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/api/v2/lists/{id}",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        responses(
+            (status = 204, description = "The list was deleted"),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_delete_list() {}
+
+    #[utoipa::path(
+        put,
+        path = "/api/v2/lists/{id}/owner",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        request_body = NewOwner,
+        responses(
+            (status = 200, description = "The list is owned by the new owner", body = Empty),
+            (status = 403, description = "Authorization denied, or the new owner already owns the maximum number of lists", body = ErrorMsg),
+            (status = 404, description = "No such list or user", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_transfer_ownership() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/v2/lists/{id}/duplicate",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        request_body = ListCopy,
+        responses(
+            (status = 201, description = "The uid of the copy, which has the list's tasks but none of its shares; `Location` gives its path", body = EntityUid),
+            (status = 403, description = "Authorization denied, or the user already owns the maximum number of lists", body = ErrorMsg),
+            (status = 404, description = "No such list", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_duplicate_list() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/v2/lists/{id}/access",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+            AccessQuery,
+        ),
+        responses(
+            (status = 200, description = "The users and teams allowed to perform the action on the list", body = ListAccess),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_get_list_access() {}
+
+    #[utoipa::path(
+        get,
+        path = "/api/v2/lists/{id}/events",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        responses(
+            (
+                status = 200,
+                description = "A stream of server-sent events, one per change to the list. \
+                               The stream ends with an `access_revoked` event once the user may no longer read the list.",
+                content_type = "text/event-stream",
+                body = ChangeEvent
+            ),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_subscribe() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/v2/lists/{id}/tasks",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        request_body = NewTask,
+        responses(
+            (status = 201, description = "The id of the new task; `Location` gives its path", body = i64),
+            (status = 403, description = "Authorization denied, or the list already holds the maximum number of tasks", body = ErrorMsg),
+            (status = 429, description = "Rate limit exceeded", body = ErrorMsg),
+            (status = 404, description = "No such list", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_create_task() {}
+
+    #[utoipa::path(
+        patch,
+        path = "/api/v2/lists/{id}/tasks/{task}",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("task" = i64, Path, description = "The task id"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        request_body = TaskPatch,
+        responses(
+            (status = 200, description = "The task was updated", body = Empty),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list or task", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_update_task() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/api/v2/lists/{id}/tasks/{task}",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("task" = i64, Path, description = "The task id"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        responses(
+            (status = 204, description = "The task was deleted"),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list or task", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_delete_task() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/v2/lists/{id}/tasks/{task}/comments",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("task" = i64, Path, description = "The task id"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        request_body = NewComment,
+        responses(
+            (status = 201, description = "The id of the new comment; `Location` gives its path", body = i64),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list or task", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_comment_on_task() {}
+
+    #[utoipa::path(
+        patch,
+        path = "/api/v2/lists/{id}/tasks/{task}/comments/{comment}",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("task" = i64, Path, description = "The task id"),
+            ("comment" = i64, Path, description = "The comment id"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        request_body = CommentPatch,
+        responses(
+            (status = 200, description = "The comment was edited", body = Empty),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list, task or comment", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_edit_comment() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/api/v2/lists/{id}/tasks/{task}/comments/{comment}",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("task" = i64, Path, description = "The task id"),
+            ("comment" = i64, Path, description = "The comment id"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        responses(
+            (status = 204, description = "The comment was deleted"),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list, task or comment", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_delete_comment() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/v2/lists/{id}/tasks/{task}/attachments",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("task" = i64, Path, description = "The task id"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        request_body = NewAttachment,
+        responses(
+            (status = 201, description = "The id of the new attachment; `Location` gives its path", body = i64),
+            (status = 400, description = "The data is not base64 encoded", body = ErrorMsg),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list or task", body = ErrorMsg),
+            (status = 413, description = "The attachment is larger than `max_attachment_bytes`", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_attach_to_task() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/api/v2/lists/{id}/tasks/{task}/attachments/{attachment}",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("task" = i64, Path, description = "The task id"),
+            ("attachment" = i64, Path, description = "The attachment id"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        responses(
+            (status = 204, description = "The attachment was deleted"),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list, task or attachment", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_delete_attachment() {}
+
+    #[utoipa::path(
+        post,
+        path = "/api/v2/lists/{id}/shares",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+        ),
+        request_body = NewShare,
+        responses(
+            (status = 201, description = "The list was shared", body = Empty),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list, user, or team", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_add_share() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/api/v2/lists/{id}/shares",
+        tag = "v2",
+        params(
+            ("id" = String, Path, description = "The list id, e.g. `0` for `List::\"0\"`"),
+            ("x-tinytodo-user" = UserUid, Header, description = "The calling user; may be omitted if the client certificate maps to a user"),
+            ShareQuery,
+        ),
+        responses(
+            (status = 204, description = "The share was removed"),
+            (status = 403, description = "Authorization denied", body = ErrorMsg),
+            (status = 404, description = "No such list, user, or team", body = ErrorMsg),
+        )
+    )]
+    pub fn v2_delete_share() {}
+
+    #[utoipa::path(
+        get,
+        path = "/healthz",
+        tag = "health",
+        responses(
+            (status = 200, description = "The server is answering queries"),
+            (status = 503, description = "The server is not answering queries"),
+        )
+    )]
+    pub fn healthz() {}
+
+    #[utoipa::path(
+        get,
+        path = "/readyz",
+        tag = "health",
+        responses(
+            (status = 200, description = "The schema and the current policies file are loaded and validated", body = Readiness),
+            (status = 503, description = "The policies file could not be reloaded (the previous policies stay in effect), or the server is not answering queries", body = Readiness),
+        )
+    )]
+    pub fn readyz() {}
+
+    #[utoipa::path(
+        get,
+        path = "/metrics",
+        tag = "health",
+        responses(
+            (status = 200, description = "Request, authorization and policy metrics in the Prometheus text format", content_type = "text/plain"),
+        )
+    )]
+    pub fn metrics() {}
+}
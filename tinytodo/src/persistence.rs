@@ -0,0 +1,188 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Saving application state to, and restoring it from, the persistence directory.
+//!
+//! The entity store is saved as `entities.json`, in the same format as the entities file
+//! the server starts from. With the `use-templates` feature, shares are template-linked
+//! policies rather than team memberships, so they are saved too, as `links.json`. Expiring
+//! shares are saved there as well, as links with an expiry.
+//! Files are written to a temporary file and renamed into place, so a crash mid-write
+//! leaves the previous state intact.
+
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::entitystore::EntityStore;
+
+#[cfg(feature = "use-templates")]
+use crate::{
+    roles::Roles,
+    shares::{self, ShareError, TimedShare},
+    util::EntityUid,
+};
+#[cfg(feature = "use-templates")]
+use cedar_policy::{PolicyId, PolicySet, PolicySetError, SlotId};
+#[cfg(feature = "use-templates")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "use-templates")]
+use std::collections::HashMap;
+
+pub const ENTITIES_FILE: &str = "entities.json";
+#[cfg(feature = "use-templates")]
+pub const LINKS_FILE: &str = "links.json";
+
+#[derive(Debug, Error)]
+pub enum PersistError {
+    #[error("{0}")]
+    IO(#[from] std::io::Error),
+    #[error("Error (de)serializing Json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "use-templates")]
+    #[error("Error restoring a linked policy: {0}")]
+    PolicySet(#[from] PolicySetError),
+    #[cfg(feature = "use-templates")]
+    #[error("The linked policy {0} is missing a slot value")]
+    Slot(PolicyId),
+    #[cfg(feature = "use-templates")]
+    #[error("Error restoring an expiring share: {0}")]
+    Share(#[from] ShareError),
+    #[cfg(feature = "use-templates")]
+    #[error(
+        "The expiring share {0} is not a share of a list with a user or team, in a known role"
+    )]
+    ExpiringShare(PolicyId),
+}
+
+type Result<T> = std::result::Result<T, PersistError>;
+
+/// Load the saved entity store, if one has been saved
+pub fn load_entities(dir: &Path) -> Result<Option<EntityStore>> {
+    let path = dir.join(ENTITIES_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = std::fs::File::open(path)?;
+    Ok(Some(serde_json::from_reader(file)?))
+}
+
+pub fn save_entities(dir: &Path, entities: &EntityStore) -> Result<()> {
+    let json = serde_json::to_vec_pretty(entities)?;
+    write_atomic(&dir.join(ENTITIES_FILE), &json)
+}
+
+/// A template-linked policy, i.e., a share
+#[cfg(feature = "use-templates")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Link {
+    pub template: String,
+    pub id: String,
+    pub principal: EntityUid,
+    pub resource: EntityUid,
+    /// Set for an expiring share, which is restored as a static policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<i64>,
+}
+
+/// Link the saved template-linked policies into `policies`
+#[cfg(feature = "use-templates")]
+pub fn restore_links(dir: &Path, policies: &mut PolicySet, roles: &Roles) -> Result<()> {
+    let path = dir.join(LINKS_FILE);
+    if !path.exists() {
+        return Ok(());
+    }
+    let file = std::fs::File::open(path)?;
+    let links: Vec<Link> = serde_json::from_reader(file)?;
+    for link in links {
+        if let Some(expires) = link.expires {
+            let id = PolicyId::new(&link.id);
+            let tid = PolicyId::new(&link.template);
+            let role = roles
+                .role_of_template(&tid)
+                .ok_or_else(|| PersistError::ExpiringShare(id.clone()))?;
+            let share = TimedShare {
+                list: link
+                    .resource
+                    .try_into()
+                    .map_err(|_| PersistError::ExpiringShare(id.clone()))?,
+                target: link
+                    .principal
+                    .try_into()
+                    .map_err(|_| PersistError::ExpiringShare(id.clone()))?,
+                role,
+                expires,
+            };
+            policies.add(shares::expiring_policy(policies, tid, id, &share)?)?;
+            continue;
+        }
+        let env: HashMap<SlotId, cedar_policy::EntityUid> = [
+            (SlotId::principal(), link.principal.into()),
+            (SlotId::resource(), link.resource.into()),
+        ]
+        .into_iter()
+        .collect();
+        policies.link(PolicyId::new(&link.template), PolicyId::new(&link.id), env)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "use-templates")]
+pub fn save_links(dir: &Path, policies: &PolicySet, roles: &Roles) -> Result<()> {
+    let links = policies
+        .policies()
+        .filter_map(|p| Some((p, p.template_id()?, p.template_links()?)))
+        .map(|(p, tid, mut vals)| {
+            let mut slot = |s: SlotId| {
+                vals.remove(&s)
+                    .ok_or_else(|| PersistError::Slot(p.id().clone()))
+            };
+            Ok(Link {
+                template: tid.to_string(),
+                id: p.id().to_string(),
+                principal: slot(SlotId::principal())?.into(),
+                resource: slot(SlotId::resource())?.into(),
+                expires: None,
+            })
+        })
+        .chain(
+            policies
+                .policies()
+                .filter_map(|p| Some((p, shares::timed_share(p)?)))
+                .map(|(p, share)| {
+                    let template = roles
+                        .template_id(&share.role)
+                        .ok_or_else(|| PersistError::ExpiringShare(p.id().clone()))?;
+                    Ok(Link {
+                        template: template.to_string(),
+                        id: p.id().to_string(),
+                        principal: share.target.into(),
+                        resource: share.list.into(),
+                        expires: Some(share.expires),
+                    })
+                }),
+        )
+        .collect::<Result<Vec<_>>>()?;
+    let json = serde_json::to_vec_pretty(&links)?;
+    write_atomic(&dir.join(LINKS_FILE), &json)
+}
+
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(tmp, path)?;
+    Ok(())
+}
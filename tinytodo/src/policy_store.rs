@@ -16,15 +16,23 @@
 
 use std::{
     path::{Path, PathBuf},
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
 use cedar_policy::{ParseErrors, PolicySet};
 use thiserror::Error;
 use tokio::sync::mpsc::Sender;
-use tracing::{debug, error};
+use tracing::{debug, error, info};
 
-use crate::context::{AppQuery, AppQueryKind};
+use crate::{
+    context::{AppQuery, AppQueryKind},
+    lint::Locations,
+};
+
+/// How long the supervisor waits before respawning a watcher that died straight away
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+/// The longest the supervisor waits before respawning a watcher
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
 
 #[derive(Debug, Clone)]
 struct PolicySetWatcher {
@@ -54,18 +62,35 @@ pub async fn spawn_watcher(policy_set: impl AsRef<Path>, tx: Sender<AppQuery>) {
     tokio::spawn(async move { watcher_supervisor(w).await });
 }
 
-// This supervises the watcher task, reporting any errors and respawning the watcher
+// This supervises the watcher task, reporting any errors and respawning the watcher.
+// Respawns back off exponentially, so a missing policies file is not polled in a tight loop.
+// The supervisor stops once the application server has shut down.
 async fn watcher_supervisor(w: PolicySetWatcher) {
+    let mut backoff = MIN_BACKOFF;
     loop {
         let cloned = w.clone();
+        let started = Instant::now();
         let handle = tokio::spawn(async { watcher(cloned).await });
         match handle.await {
             Ok(f) => match f {
                 Ok(a) => match a {},
-                Err(e) => debug!("Policy Set File Watcher died due to: {e}, respawning..."),
+                Err(Error::McspChan(_)) => {
+                    info!("Application server stopped, stopping Policy Set File Watcher");
+                    return;
+                }
+                Err(e) => {
+                    debug!("Policy Set File Watcher died due to: {e}, respawning in {backoff:?}");
+                    report_failure(&e, &w.tx).await;
+                }
             },
             Err(e) => error!("Join Error: {e}"),
         }
+        if started.elapsed() > MAX_BACKOFF {
+            // The watcher ran for a while, so this is a new failure
+            backoff = MIN_BACKOFF;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
     }
 }
 
@@ -79,26 +104,38 @@ async fn watcher(w: PolicySetWatcher) -> Result<Empty> {
         if time != last_modified {
             last_modified = time;
             match attempt_policy_reload(&w).await {
-                Ok(policies) => {
-                    send_query(policies, &w.tx).await?;
+                Ok((policies, locations)) => {
+                    send_query(policies, locations, &w.tx).await?;
+                }
+                Err(e) => {
+                    error!("Error reloading policies: {e}");
+                    report_failure(&e, &w.tx).await;
                 }
-                Err(e) => error!("Error reloading policies: {e}"),
             };
         }
     }
 }
 
-async fn send_query(p: PolicySet, tx: &Sender<AppQuery>) -> Result<()> {
+async fn send_query(p: PolicySet, locations: Locations, tx: &Sender<AppQuery>) -> Result<()> {
     let (send, recv) = tokio::sync::oneshot::channel();
-    let query = AppQuery::new(AppQueryKind::UpdatePolicySet(p), send);
+    let query = AppQuery::new(AppQueryKind::UpdatePolicySet(p, locations), send);
     tx.send(query).await?;
     let _ = recv.await?;
     Ok(())
 }
 
-async fn attempt_policy_reload(w: &PolicySetWatcher) -> Result<PolicySet> {
-    let policies: PolicySet = tokio::fs::read_to_string(&w.policy_set).await?.parse()?;
-    Ok(policies)
+// Let the application server know the policies file could not be reloaded, for `/readyz`
+async fn report_failure(e: &Error, tx: &Sender<AppQuery>) {
+    let (send, _) = tokio::sync::oneshot::channel();
+    let query = AppQuery::new(AppQueryKind::PolicyReloadFailed(e.to_string()), send);
+    let _ = tx.send(query).await;
+}
+
+async fn attempt_policy_reload(w: &PolicySetWatcher) -> Result<(PolicySet, Locations)> {
+    let src = tokio::fs::read_to_string(&w.policy_set).await?;
+    let policies: PolicySet = src.parse()?;
+    let locations = Locations::new(&w.policy_set, &src, &policies);
+    Ok((policies, locations))
 }
 
 async fn get_last_modified(path: &Path) -> std::io::Result<SystemTime> {
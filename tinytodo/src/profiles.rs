@@ -0,0 +1,124 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Policy profiles.
+//!
+//! Policies 4, 5 and 6 are defined here, and only named in the policy files. A profile is
+//! one of them, which the server adds to its policy set at startup (and keeps across
+//! reloads of the policy file) when the `profiles` setting names it. With task entities,
+//! the `location` profile also applies to tasks, by the location of their list's owner.
+
+use std::collections::BTreeSet;
+
+use cedar_policy::{ParseErrors, Policy, PolicyId};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// The annotation naming the profile a policy belongs to
+pub const PROFILE: &str = "profile";
+
+/// An optional bundle of policies
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyProfile {
+    /// Policy 4: members of `Team::"admin"` may perform any action on any resource
+    Admin,
+    /// Policy 5: members of `Team::"interns"` may not create lists
+    Interns,
+    /// Policy 6: users may only act on lists whose owner shares their location, unless they
+    /// are senior (job level above 6) and at a `DEF` location
+    Location,
+}
+
+const ADMIN: &str = r#"permit (
+    principal in Team::"admin",
+    action,
+    resource in Application::"TinyTodo"
+);"#;
+
+const INTERNS: &str = r#"forbid (
+    principal in Team::"interns",
+    action == Action::"CreateList",
+    resource == Application::"TinyTodo"
+);"#;
+
+const LOCATION: &str = r#"forbid (
+    principal,
+    action,
+    resource is List
+) unless {
+    principal.joblevel > 6 && principal.location like "DEF*" ||
+    principal.location == resource.owner.location
+};"#;
+
+const LOCATION_TASKS: &str = r#"forbid (
+    principal,
+    action,
+    resource is Task
+) unless {
+    principal.joblevel > 6 && principal.location like "DEF*" ||
+    principal.location == resource.list.owner.location
+};"#;
+
+impl PolicyProfile {
+    pub fn name(self) -> &'static str {
+        match self {
+            PolicyProfile::Admin => "admin",
+            PolicyProfile::Interns => "interns",
+            PolicyProfile::Location => "location",
+        }
+    }
+
+    /// The profile's policies, with `task_entities` including those on tasks
+    pub fn policies(self, task_entities: bool) -> Result<Vec<Policy>, ParseErrors> {
+        let sources: &[(&str, &str)] = match self {
+            PolicyProfile::Admin => &[("", ADMIN)],
+            PolicyProfile::Interns => &[("", INTERNS)],
+            PolicyProfile::Location if task_entities => {
+                &[("", LOCATION), ("-tasks", LOCATION_TASKS)]
+            }
+            PolicyProfile::Location => &[("", LOCATION)],
+        };
+        sources
+            .iter()
+            .map(|(suffix, src)| {
+                let id = PolicyId::new(format!("profile-{}{suffix}", self.name()));
+                let src = format!("@{PROFILE}({:?})\n{src}", self.name());
+                Policy::parse(Some(id), src)
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for PolicyProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The policies of every profile in `profiles`
+pub fn policies(
+    profiles: &BTreeSet<PolicyProfile>,
+    task_entities: bool,
+) -> Result<Vec<Policy>, ParseErrors> {
+    let mut policies = Vec::new();
+    for profile in profiles {
+        policies.extend(profile.policies(task_entities)?);
+    }
+    Ok(policies)
+}
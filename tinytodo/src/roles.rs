@@ -0,0 +1,243 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Share roles.
+//!
+//! `Reader` and `Editor` are built in: with teams, they are the list's `readers` and
+//! `editors` teams, which Policies 2 and 3 refer to, and with templates they link the
+//! `reader-template` and `editor-template` templates. Further roles are defined in the
+//! `[roles]` section of the config file.
+//!
+//! With teams, a configured role names the actions it permits. Each list gets a team for the
+//! role the first time it is shared in it, which the list's entity carries as the tag named
+//! after the role, and the server adds a policy permitting the role's actions to the members
+//! of that team. If the role may comment on or attach files to tasks, a second policy lets
+//! its members edit and delete their own, as Policy 7 does for readers and editors. With
+//! templates, a configured role names the template that is linked to share a list in it,
+//! which, like the built-in templates, must permit authors' own edits itself.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{api::ShareRole, config::SharingMode};
+
+#[cfg(not(feature = "use-templates"))]
+use cedar_policy::{ParseErrors, Policy};
+#[cfg(not(feature = "use-templates"))]
+use itertools::Itertools;
+
+use cedar_policy::PolicyId;
+
+/// The annotation naming the role a generated policy is for
+pub const ROLE_POLICY: &str = "role_policy";
+
+/// The actions on a comment or attachment that its author may take, as in Policy 7
+#[cfg(not(feature = "use-templates"))]
+const AUTHOR_ACTIONS: &str =
+    r#"Action::"EditComment", Action::"DeleteComment", Action::"DeleteAttachment""#;
+
+/// A share role defined in the config file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoleConfig {
+    /// With teams, the actions the role permits on a list shared in it, and on its tasks
+    /// (e.g., `["GetList", "CommentOnTask"]`)
+    pub actions: Vec<String>,
+    /// With templates, the id of the template linked to share a list in the role
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum RolesError {
+    #[error("Share role {0} is built in, and cannot be configured")]
+    BuiltIn(ShareRole),
+    #[error("Invalid share role name {0}: it must be a letter followed by letters, digits and underscores")]
+    Name(ShareRole),
+    #[error("Share roles {0} and {1} differ only in case")]
+    Duplicate(ShareRole, ShareRole),
+    #[error("Share role {0} must list the `actions` it permits")]
+    Actions(ShareRole),
+    #[error("Invalid action name {1} in share role {0}")]
+    Action(ShareRole, String),
+    #[error("Share role {0} must name its `template`")]
+    Template(ShareRole),
+    #[error("Share role {role} sets `{field}`, which does not apply with {sharing} sharing")]
+    Field {
+        role: ShareRole,
+        field: &'static str,
+        sharing: SharingMode,
+    },
+}
+
+/// The share roles a server knows: the built-in ones and those in its config
+#[derive(Debug, Clone, Default)]
+pub struct Roles {
+    configured: BTreeMap<ShareRole, RoleConfig>,
+}
+
+impl Roles {
+    pub fn new(configured: &BTreeMap<ShareRole, RoleConfig>) -> Self {
+        Self {
+            configured: configured.clone(),
+        }
+    }
+
+    fn built_in() -> [ShareRole; 2] {
+        [ShareRole::reader(), ShareRole::editor()]
+    }
+
+    /// Whether `role` is built in or configured
+    pub fn contains(&self, role: &ShareRole) -> bool {
+        Self::built_in().contains(role) || self.configured.contains_key(role)
+    }
+
+    /// The built-in and configured roles
+    pub fn iter(&self) -> impl Iterator<Item = ShareRole> + '_ {
+        Self::built_in()
+            .into_iter()
+            .chain(self.configured.keys().cloned())
+    }
+
+    /// The templates linked to share lists in some role, which are needed even while no list
+    /// is shared
+    #[cfg(feature = "use-templates")]
+    pub fn templates(&self) -> Vec<PolicyId> {
+        self.iter()
+            .filter_map(|role| self.template_id(&role))
+            .collect()
+    }
+
+    /// With teams, shares are not template-linked
+    #[cfg(not(feature = "use-templates"))]
+    pub fn templates(&self) -> Vec<PolicyId> {
+        Vec::new()
+    }
+
+    /// The template linked to share a list in `role`, if `role` is known
+    #[cfg(feature = "use-templates")]
+    pub fn template_id(&self, role: &ShareRole) -> Option<PolicyId> {
+        if role == &ShareRole::reader() {
+            Some(PolicyId::new("reader-template"))
+        } else if role == &ShareRole::editor() {
+            Some(PolicyId::new("editor-template"))
+        } else {
+            let template = self.configured.get(role)?.template.as_ref()?;
+            Some(PolicyId::new(template))
+        }
+    }
+
+    /// The role whose template is `tid`
+    #[cfg(feature = "use-templates")]
+    pub fn role_of_template(&self, tid: &PolicyId) -> Option<ShareRole> {
+        self.iter()
+            .find(|role| self.template_id(role).as_ref() == Some(tid))
+    }
+
+    /// The policies permitting each configured role's actions to the members of the list's
+    /// team for the role, and, for roles that author comments or attachments, permitting
+    /// their authors to manage them. With `task_entities`, they also cover the list's tasks.
+    #[cfg(not(feature = "use-templates"))]
+    pub fn policies(&self, task_entities: bool) -> Result<Vec<Policy>, ParseErrors> {
+        let mut policies = Vec::new();
+        for (role, config) in &self.configured {
+            let name = role.as_str();
+            let actions = config
+                .actions
+                .iter()
+                .map(|action| format!("Action::{action:?}"))
+                .join(", ");
+            let member = |list: &str| {
+                format!("{list}.hasTag({name:?}) && principal in {list}.getTag({name:?})")
+            };
+            let mut condition = format!("resource is List && {}", member("resource"));
+            if task_entities {
+                condition += &format!(" || resource is Task && {}", member("resource.list"));
+            }
+            let src = format!(
+                "@{ROLE_POLICY}({name:?})\n\
+                 permit (principal, action in [{actions}], resource)\n\
+                 when {{ {condition} }};"
+            );
+            policies.push(Policy::parse(Some(Self::policy_id(role, "")), src)?);
+            let authors = ["CommentOnTask", "AttachToTask"];
+            if config.actions.iter().any(|a| authors.contains(&a.as_str())) {
+                let src = format!(
+                    "@{ROLE_POLICY}({name:?})\n\
+                     permit (principal, action in [{AUTHOR_ACTIONS}], resource)\n\
+                     when {{ context.author == principal && ({condition}) }};"
+                );
+                policies.push(Policy::parse(Some(Self::policy_id(role, "-authors")), src)?);
+            }
+        }
+        Ok(policies)
+    }
+
+    #[cfg(not(feature = "use-templates"))]
+    fn policy_id(role: &ShareRole, suffix: &str) -> cedar_policy::PolicyId {
+        cedar_policy::PolicyId::new(format!("role-{role}{suffix}"))
+    }
+}
+
+/// Check the configured roles: their names, and that each sets what `sharing` needs
+pub fn validate(
+    configured: &BTreeMap<ShareRole, RoleConfig>,
+    sharing: SharingMode,
+) -> Result<(), RolesError> {
+    // Roles are named in policy ids in lower case, so names must differ in more than case
+    let mut seen: BTreeMap<String, &ShareRole> = BTreeMap::new();
+    for (role, config) in configured {
+        let name = role.as_str();
+        if !is_name(name) {
+            return Err(RolesError::Name(role.clone()));
+        }
+        if Roles::built_in()
+            .iter()
+            .any(|built_in| built_in.as_str().eq_ignore_ascii_case(name))
+        {
+            return Err(RolesError::BuiltIn(role.clone()));
+        }
+        if let Some(other) = seen.insert(name.to_lowercase(), role) {
+            return Err(RolesError::Duplicate(other.clone(), role.clone()));
+        }
+        let field = |field| RolesError::Field {
+            role: role.clone(),
+            field,
+            sharing,
+        };
+        match sharing {
+            SharingMode::Teams if config.actions.is_empty() => {
+                return Err(RolesError::Actions(role.clone()))
+            }
+            SharingMode::Teams if config.template.is_some() => return Err(field("template")),
+            SharingMode::Templates if config.template.is_none() => {
+                return Err(RolesError::Template(role.clone()))
+            }
+            SharingMode::Templates if !config.actions.is_empty() => return Err(field("actions")),
+            _ => (),
+        }
+        if let Some(action) = config.actions.iter().find(|action| !is_name(action)) {
+            return Err(RolesError::Action(role.clone(), action.clone()));
+        }
+    }
+    Ok(())
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
@@ -0,0 +1,193 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Shares that expire.
+//!
+//! With teams, an expiring share is a team membership plus a [`TimedShare`] record, kept in
+//! the entity store, saying when it ends. With templates, it is a static policy made by
+//! linking the reader or editor template and adding a `context.now < expiry` condition. The
+//! policy's id starts with [`EXPIRING_SHARE_PREFIX`], which the policies file may not use,
+//! and it is annotated with the share it grants, so that it can be remade from the new
+//! templates when the policies are reloaded, and saved along with the template links.
+//! Either way, the application server revokes shares once they expire.
+
+use std::time::Duration;
+
+use cedar_policy::Policy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+use tracing::info;
+
+use crate::{
+    api::ShareRole,
+    context::{AppQuery, AppQueryKind},
+    util::{ListUid, UserOrTeamUid},
+};
+
+#[cfg(feature = "use-templates")]
+use cedar_policy::{
+    PolicyFromJsonError, PolicyId, PolicySet, PolicySetError, PolicyToJsonError, SlotId,
+};
+#[cfg(feature = "use-templates")]
+use serde_json::{json, Value};
+#[cfg(feature = "use-templates")]
+use thiserror::Error;
+
+/// How often the sweeper asks the application server to revoke expired shares
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The start of the ids of the policies the server makes for expiring shares. Policies in
+/// the policies file may not have ids that start with it, so that no policy of theirs is
+/// taken for an expiring share.
+pub const EXPIRING_SHARE_PREFIX: &str = "expiring-share:";
+
+/// The current time, in seconds since the Unix epoch
+pub fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Spawns the sweeper, which periodically asks the application server to revoke the shares
+/// that have expired. It stops once the application server has shut down.
+pub fn spawn_sweeper(tx: Sender<AppQuery>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(SWEEP_INTERVAL).await;
+            let (send, recv) = tokio::sync::oneshot::channel();
+            let query = AppQuery::new(AppQueryKind::ExpireShares, send);
+            if tx.send(query).await.is_err() || recv.await.is_err() {
+                info!("Application server stopped, stopping the share sweeper");
+                return;
+            }
+        }
+    });
+}
+
+/// A share of `list` with `target`, in `role`, that ends at `expires`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimedShare {
+    pub list: ListUid,
+    pub target: UserOrTeamUid,
+    pub role: ShareRole,
+    /// In seconds since the Unix epoch
+    pub expires: i64,
+}
+
+impl TimedShare {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires <= now
+    }
+
+    /// Whether this is the share of `list` with `target` in `role`
+    pub fn grants(&self, list: &ListUid, target: &UserOrTeamUid, role: &ShareRole) -> bool {
+        &self.list == list && &self.target == target && &self.role == role
+    }
+}
+
+#[cfg(feature = "use-templates")]
+#[derive(Debug, Error)]
+pub enum ShareError {
+    #[error("{0}")]
+    PolicySet(#[from] PolicySetError),
+    #[error("{0}")]
+    ToJson(#[from] PolicyToJsonError),
+    #[error("{0}")]
+    FromJson(#[from] PolicyFromJsonError),
+    #[error("There is no template {0}")]
+    NoTemplate(PolicyId),
+    #[error("There is no share role {0}")]
+    NoRole(ShareRole),
+}
+
+// The annotations recording the share an expiring policy grants
+const LIST: &str = "share_list";
+const TARGET: &str = "share_target";
+const ROLE: &str = "share_role";
+const EXPIRES: &str = "share_expires";
+
+/// The id of the policy for an expiring share, given the id of the template-linked policy
+/// for the same share without an expiry
+#[cfg(feature = "use-templates")]
+pub fn expiring_id(linked: &PolicyId) -> PolicyId {
+    PolicyId::new(format!("{EXPIRING_SHARE_PREFIX}{linked}"))
+}
+
+/// The policy, named `id`, for an expiring share: the role's template `tid` in `policies`,
+/// linked to the share's target and list, and in effect only while `context.now < expires`
+#[cfg(feature = "use-templates")]
+pub fn expiring_policy(
+    policies: &PolicySet,
+    tid: PolicyId,
+    id: PolicyId,
+    share: &TimedShare,
+) -> Result<Policy, ShareError> {
+    let template = policies
+        .template(&tid)
+        .ok_or_else(|| ShareError::NoTemplate(tid.clone()))?;
+    let mut linked = PolicySet::new();
+    linked.add_template(template.clone())?;
+    let env = [
+        (SlotId::principal(), share.target.as_ref().clone().into()),
+        (SlotId::resource(), share.list.as_ref().clone().into()),
+    ]
+    .into_iter()
+    .collect();
+    linked.link(tid, id.clone(), env)?;
+    let mut est = linked
+        .policy(&id)
+        .expect("the policy was just linked")
+        .to_json()?;
+    let unexpired = json!({
+        "kind": "when",
+        "body": { "<": {
+            "left": { ".": { "left": { "Var": "context" }, "attr": "now" } },
+            "right": { "Value": share.expires },
+        }},
+    });
+    match est.get_mut("conditions") {
+        Some(Value::Array(conditions)) => conditions.push(unexpired),
+        _ => est["conditions"] = json!([unexpired]),
+    }
+    est["annotations"] = json!({
+        LIST: share.list.as_ref().to_string(),
+        TARGET: share.target.as_ref().to_string(),
+        ROLE: share.role.as_str(),
+        EXPIRES: share.expires.to_string(),
+    });
+    Ok(Policy::from_json(Some(id), est)?)
+}
+
+/// The share an expiring policy grants, read from its annotations, or `None` if `policy` is
+/// not an expiring share the server made. Only servers built with templates make them.
+pub fn timed_share(policy: &Policy) -> Option<TimedShare> {
+    if !policy.id().to_string().starts_with(EXPIRING_SHARE_PREFIX) {
+        return None;
+    }
+    let role = ShareRole::new(policy.annotation(ROLE)?);
+    let euid = |name| {
+        policy
+            .annotation(name)?
+            .parse::<crate::util::EntityUid>()
+            .ok()
+    };
+    Some(TimedShare {
+        list: euid(LIST)?.try_into().ok()?,
+        target: euid(TARGET)?.try_into().ok()?,
+        role,
+        expires: policy.annotation(EXPIRES)?.parse().ok()?,
+    })
+}
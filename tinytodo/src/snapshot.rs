@@ -0,0 +1,532 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Snapshots of a whole deployment, for backups, staging refreshes and bug reports.
+//!
+//! A snapshot is a tar archive holding:
+//! - `manifest.json`: the snapshot format version, the sharing mode, the ids each list and
+//!   task will give next and, with teams, when the expiring shares end
+//! - `schema.cedarschema`: the schema, as written
+//! - `entities.json`: the entity store, in Cedar's entity JSON format
+//! - `policies.json`: the policies, templates and template links, in Cedar's policy set
+//!   JSON format
+//! - `attachments.json`: the content type and contents of each attachment, which are not
+//!   part of its entity
+//!
+//! so its contents can be checked with the Cedar CLI. Snapshots are exported from, and
+//! imported into, the files named by the config: the persistence directory if there is one,
+//! and otherwise the entities file. Import replaces the schema and policies files too, and
+//! should only be run while the server is stopped, since a running server saves its own state
+//! over the imported one.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use cedar_policy::{
+    CedarSchemaError, Entities, EntitiesError, PolicyId, PolicySet, PolicySetError, Schema,
+    ValidationMode, Validator,
+};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+
+use crate::{
+    config::{Config, SharingMode},
+    context::{self, ContextError, APPLICATION_TINY_TODO},
+    entitystore::EntityStore,
+    persistence::{self, PersistError},
+    shares::{self, TimedShare},
+    util::{TYPE_LIST, TYPE_TASK, TYPE_TEAM, TYPE_USER},
+};
+
+#[cfg(feature = "use-templates")]
+use crate::roles::Roles;
+
+const VERSION: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+const SCHEMA: &str = "schema.cedarschema";
+const ENTITIES: &str = "entities.json";
+const POLICIES: &str = "policies.json";
+const ATTACHMENTS: &str = "attachments.json";
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("{0}")]
+    IO(#[from] std::io::Error),
+    #[error("Error (de)serializing Json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    Context(#[from] ContextError),
+    #[error("Error Parsing Human-readable Schema: {0}")]
+    CedarSchema(#[from] CedarSchemaError),
+    #[error("Error Processing PolicySet: {0}")]
+    PolicySet(#[from] PolicySetError),
+    #[error("Invalid entities: {0}")]
+    Entities(#[from] EntitiesError),
+    #[error("Invalid entity: {0}")]
+    Entity(String),
+    #[error("Validation Failed: {0}")]
+    Validation(String),
+    #[error("Error saving state: {0}")]
+    Persistence(#[from] PersistError),
+    #[error("The archive has no {0}")]
+    Missing(&'static str),
+    #[error("Unsupported snapshot version {0}; this server reads version {VERSION}")]
+    Version(u32),
+    #[error(
+        "The snapshot is of a server using `{snapshot}` sharing, but this server uses `{compiled}`"
+    )]
+    SharingMode {
+        snapshot: SharingMode,
+        compiled: SharingMode,
+    },
+    #[error("The snapshot's `task_entities` setting ({0}) does not match this server's")]
+    TaskEntities(bool),
+    #[error("The snapshot has template-linked policies, which can only be imported into a persistence directory")]
+    LinksNeedPersistence,
+}
+
+type Result<T> = std::result::Result<T, SnapshotError>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    sharing: SharingMode,
+    #[serde(default)]
+    task_entities: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    next_ids: Vec<NextIds>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    timed_shares: Vec<TimedShare>,
+}
+
+/// The id a list will give its next task, and each of its tasks its next comment and
+/// attachment. These are not part of the entities, but are kept so that the ids of deleted
+/// tasks, comments and attachments are not reused.
+#[derive(Debug, Serialize, Deserialize)]
+struct NextIds {
+    list: String,
+    task: i64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tasks: Vec<NextTaskIds>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NextTaskIds {
+    id: i64,
+    comment: i64,
+    attachment: i64,
+}
+
+/// What an attachment's entity leaves out
+#[derive(Debug, Serialize, Deserialize)]
+struct AttachmentContents {
+    list: String,
+    task: i64,
+    id: i64,
+    content_type: String,
+    data: String,
+}
+
+/// Write the state of the deployment described by `config` to the archive at `path`
+pub fn export(config: &Config, path: &Path) -> Result<()> {
+    let schema_src = std::fs::read_to_string(&config.schema)?;
+    let (schema, _) = Schema::from_cedarschema_str(&schema_src)?;
+    let saved = match &config.persistence_dir {
+        Some(dir) => persistence::load_entities(dir)?,
+        None => None,
+    };
+    let mut store: EntityStore = match saved {
+        Some(store) => store,
+        None => serde_json::from_reader(File::open(&config.entities)?)?,
+    };
+    store.set_task_entities(config.task_entities);
+    #[allow(unused_mut)]
+    let mut policies = context::parse_policies(&std::fs::read_to_string(&config.policies)?)?;
+    #[cfg(feature = "use-templates")]
+    if let Some(dir) = &config.persistence_dir {
+        persistence::restore_links(dir, &mut policies, &Roles::new(&config.roles))?;
+    }
+
+    let next_ids = store
+        .get_lists()
+        .map(|list| NextIds {
+            list: list.uid().as_ref().to_string(),
+            task: list.next_task_id(),
+            tasks: list
+                .tasks()
+                .iter()
+                .map(|task| NextTaskIds {
+                    id: task.id(),
+                    comment: task.next_comment_id(),
+                    attachment: task.next_attachment_id(),
+                })
+                .collect(),
+        })
+        .collect();
+    let manifest = Manifest {
+        version: VERSION,
+        sharing: SharingMode::compiled(),
+        task_entities: config.task_entities,
+        next_ids,
+        timed_shares: store.timed_shares().cloned().collect(),
+    };
+    let mut entities = vec![];
+    store.as_entities(&schema)?.write_to_json(&mut entities)?;
+    // Re-encode so the archive is as readable as the files it's made from
+    let entities: Value = serde_json::from_slice(&entities)?;
+    let attachments = store
+        .get_lists()
+        .flat_map(|list| list.tasks().iter().map(move |task| (list, task)))
+        .flat_map(|(list, task)| {
+            task.attachments().iter().map(|a| AttachmentContents {
+                list: list.uid().as_ref().to_string(),
+                task: task.id(),
+                id: a.id(),
+                content_type: a.content_type().to_string(),
+                data: a.data().to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut archive = tar::Builder::new(File::create(path)?);
+    append(
+        &mut archive,
+        MANIFEST,
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    append(&mut archive, SCHEMA, schema_src.as_bytes())?;
+    append(
+        &mut archive,
+        ENTITIES,
+        &serde_json::to_vec_pretty(&entities)?,
+    )?;
+    append(
+        &mut archive,
+        POLICIES,
+        &serde_json::to_vec_pretty(&policies.to_json()?)?,
+    )?;
+    append(
+        &mut archive,
+        ATTACHMENTS,
+        &serde_json::to_vec_pretty(&attachments)?,
+    )?;
+    archive.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+fn append(archive: &mut tar::Builder<File>, name: &str, contents: &[u8]) -> Result<()> {
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header.set_cksum();
+    archive.append_data(&mut header, name, contents)?;
+    Ok(())
+}
+
+/// Replace the state of the deployment described by `config` with the archive at `path`.
+/// Nothing is written unless the whole snapshot is valid.
+pub fn import(config: &Config, path: &Path) -> Result<()> {
+    let mut files = read_archive(path)?;
+    let mut take = |name: &'static str| files.remove(name).ok_or(SnapshotError::Missing(name));
+    let manifest: Manifest = serde_json::from_slice(&take(MANIFEST)?)?;
+    if manifest.version != VERSION {
+        return Err(SnapshotError::Version(manifest.version));
+    }
+    let compiled = SharingMode::compiled();
+    if manifest.sharing != compiled {
+        return Err(SnapshotError::SharingMode {
+            snapshot: manifest.sharing,
+            compiled,
+        });
+    }
+    if manifest.task_entities != config.task_entities {
+        return Err(SnapshotError::TaskEntities(manifest.task_entities));
+    }
+    let schema_src = String::from_utf8(take(SCHEMA)?)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let (schema, _) = Schema::from_cedarschema_str(&schema_src)?;
+
+    let entities: Value = serde_json::from_slice(&take(ENTITIES)?)?;
+    // Type-check the entities against the schema before decoding them
+    Entities::from_json_value(entities.clone(), Some(&schema))?;
+    let attachments = serde_json::from_slice(&take(ATTACHMENTS)?)?;
+    let mut store = entity_store(
+        serde_json::from_value(entities)?,
+        attachments,
+        manifest.next_ids,
+    )?;
+    for share in manifest.timed_shares {
+        store.set_share_expiry(&share.list, &share.target, &share.role, Some(share.expires));
+    }
+
+    let policies = PolicySet::from_json_value(serde_json::from_slice(&take(POLICIES)?)?)?;
+    let output = Validator::new(schema).validate(&policies, ValidationMode::default());
+    if !output.validation_passed() {
+        let errors = output.validation_errors().map(|e| e.to_string()).join("\n");
+        return Err(SnapshotError::Validation(errors));
+    }
+    // Expiring shares are saved with the template links
+    let linked = policies
+        .policies()
+        .any(|p| p.template_id().is_some() || shares::timed_share(p).is_some());
+    if linked && config.persistence_dir.is_none() {
+        return Err(SnapshotError::LinksNeedPersistence);
+    }
+
+    std::fs::write(&config.schema, schema_src)?;
+    std::fs::write(&config.policies, policies_src(&policies))?;
+    match &config.persistence_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            persistence::save_entities(dir, &store)?;
+            #[cfg(feature = "use-templates")]
+            persistence::save_links(dir, &policies, &Roles::new(&config.roles))?;
+        }
+        None => std::fs::write(&config.entities, serde_json::to_vec_pretty(&store)?)?,
+    }
+    Ok(())
+}
+
+fn read_archive(path: &Path) -> Result<HashMap<String, Vec<u8>>> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut files = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut contents = vec![];
+        entry.read_to_end(&mut contents)?;
+        files.insert(name, contents);
+    }
+    Ok(files)
+}
+
+/// The policies file for `policies`: its templates and static policies, each annotated with
+/// its id so that it keeps that id, and template links still find their template, when the
+/// file is loaded. Expiring shares are left out, as they are saved with the template links.
+fn policies_src(policies: &PolicySet) -> String {
+    fn with_id(id: &PolicyId, annotation: Option<&str>, src: String) -> String {
+        match annotation {
+            Some(_) => src,
+            None => format!("@id({:?})\n{src}", id.to_string()),
+        }
+    }
+    let templates = policies
+        .templates()
+        .map(|t| with_id(t.id(), t.annotation("id"), t.to_string()));
+    let statics = policies
+        .policies()
+        .filter(|p| p.template_id().is_none() && shares::timed_share(p).is_none())
+        .map(|p| with_id(p.id(), p.annotation("id"), p.to_string()));
+    templates.chain(statics).join("\n\n") + "\n"
+}
+
+/// An entity in Cedar's entity JSON format
+#[derive(Debug, Deserialize)]
+struct CedarEntity {
+    uid: Value,
+    #[serde(default)]
+    attrs: Map<String, Value>,
+    #[serde(default)]
+    parents: Vec<Value>,
+    #[serde(default)]
+    tags: Map<String, Value>,
+}
+
+impl CedarEntity {
+    fn attr(&mut self, uid: &str, name: &str) -> Result<Value> {
+        self.attrs
+            .remove(name)
+            .ok_or_else(|| SnapshotError::Entity(format!("{uid} has no attribute `{name}`")))
+    }
+}
+
+// An entity reference, in either of the forms Cedar's entity JSON allows, as the string
+// TinyTodo's entity store uses
+fn entity_ref(v: Value) -> Result<String> {
+    cedar_policy::EntityUid::from_json(v)
+        .map(|uid| uid.to_string())
+        .map_err(|e| SnapshotError::Entity(e.to_string()))
+}
+
+/// Decode entities from Cedar's entity JSON into an entity store, by rewriting them into the
+/// format of the entities file, with the attachments' contents and the next ids put back.
+/// Action entities, which come from the schema, are skipped.
+fn entity_store(
+    entities: Vec<CedarEntity>,
+    attachments: Vec<AttachmentContents>,
+    next_ids: Vec<NextIds>,
+) -> Result<EntityStore> {
+    let mut users = Map::new();
+    let mut teams = Map::new();
+    let mut lists = Map::new();
+    let mut tasks_of: HashMap<String, Vec<Value>> = HashMap::new();
+    for mut e in entities {
+        let uid = cedar_policy::EntityUid::from_json(e.uid.take())
+            .map_err(|err| SnapshotError::Entity(err.to_string()))?;
+        let key = uid.to_string();
+        let parents: Vec<String> = std::mem::take(&mut e.parents)
+            .into_iter()
+            .map(entity_ref)
+            .collect::<Result<_>>()?;
+        let ty = uid.type_name();
+        if ty == &*TYPE_USER {
+            let user = json!({
+                "euid": key,
+                "joblevel": e.attr(&key, "joblevel")?,
+                "location": e.attr(&key, "location")?,
+                "parents": parents,
+            });
+            users.insert(key, user);
+        } else if ty == &*TYPE_TEAM {
+            teams.insert(key.clone(), json!({ "uid": key, "parents": parents }));
+        } else if ty == &*TYPE_LIST {
+            // Lists have no `tasks` attribute when tasks are entities
+            let tasks = match e.attrs.remove("tasks") {
+                None => vec![],
+                Some(Value::Array(tasks)) => {
+                    tasks.into_iter().map(task).collect::<Result<Vec<_>>>()?
+                }
+                Some(_) => return Err(SnapshotError::Entity(format!("{key} has invalid tasks"))),
+            };
+            #[allow(unused_mut)]
+            let mut list = json!({
+                "uid": key,
+                "owner": entity_ref(e.attr(&key, "owner")?)?,
+                "name": e.attr(&key, "name")?,
+                "tasks": tasks,
+            });
+            #[cfg(not(feature = "use-templates"))]
+            {
+                for team in ["readers", "editors"] {
+                    list[team] = entity_ref(e.attr(&key, team)?)?.into();
+                }
+                // The teams for configured roles are tags, named after the role
+                let roles = std::mem::take(&mut e.tags)
+                    .into_iter()
+                    .map(|(role, team)| Ok((role, entity_ref(team)?.into())))
+                    .collect::<Result<Map<_, _>>>()?;
+                list["roles"] = roles.into();
+            }
+            lists.insert(key, list);
+        } else if ty == &*TYPE_TASK {
+            let list = entity_ref(e.attr(&key, "list")?)?;
+            tasks_of
+                .entry(list)
+                .or_default()
+                .push(task(Value::Object(e.attrs))?);
+        } else if uid != **APPLICATION_TINY_TODO && ty.to_string() != "Action" {
+            return Err(SnapshotError::Entity(format!("unexpected entity {key}")));
+        }
+    }
+    for (list, mut tasks) in tasks_of {
+        let Some(Value::Array(all)) = lists.get_mut(&list).map(|l| &mut l["tasks"]) else {
+            return Err(SnapshotError::Entity(format!(
+                "tasks of unknown list {list}"
+            )));
+        };
+        tasks.sort_by_key(|t| t["id"].as_i64());
+        all.extend(tasks);
+    }
+    let mut contents: HashMap<_, _> = attachments
+        .into_iter()
+        .map(|a| ((a.list.clone(), a.task, a.id), a))
+        .collect();
+    for (key, list) in &mut lists {
+        let Some(Value::Array(tasks)) = list.get_mut("tasks") else {
+            continue;
+        };
+        for task in tasks {
+            let id = task["id"].as_i64().unwrap_or_default();
+            let Some(Value::Array(items)) = task.get_mut("attachments") else {
+                continue;
+            };
+            for item in items {
+                let attachment = item["id"].as_i64().unwrap_or_default();
+                let Some(a) = contents.remove(&(key.clone(), id, attachment)) else {
+                    return Err(SnapshotError::Entity(format!(
+                        "an attachment to task {id} of {key} has no contents"
+                    )));
+                };
+                item["content_type"] = a.content_type.into();
+                item["data"] = a.data.into();
+            }
+        }
+    }
+    for next in next_ids {
+        let Some(list) = lists.get_mut(&next.list) else {
+            return Err(SnapshotError::Entity(format!(
+                "next ids of unknown list {}",
+                next.list
+            )));
+        };
+        list["next_task"] = next.task.into();
+        let Some(Value::Array(tasks)) = list.get_mut("tasks") else {
+            continue;
+        };
+        for ids in next.tasks {
+            let Some(task) = tasks.iter_mut().find(|t| t["id"].as_i64() == Some(ids.id)) else {
+                return Err(SnapshotError::Entity(format!(
+                    "next ids of unknown task {} of {}",
+                    ids.id, next.list
+                )));
+            };
+            task["next_comment"] = ids.comment.into();
+            task["next_attachment"] = ids.attachment.into();
+        }
+    }
+    let store = json!({
+        "users": users,
+        "teams": teams,
+        "lists": lists,
+        "app": { "euid": APPLICATION_TINY_TODO.to_string() },
+    });
+    Ok(serde_json::from_value(store)?)
+}
+
+// Cedar stores task states as `checked` and `unchecked`, and assignees and the authors of
+// comments and attachments as entity references
+fn task(mut t: Value) -> Result<Value> {
+    let state = match t["state"].as_str() {
+        Some("checked") => "Checked",
+        Some("unchecked") => "Unchecked",
+        _ => return Err(SnapshotError::Entity(format!("invalid task {t}"))),
+    };
+    t["state"] = state.into();
+    if let Some(assignee) = t.get_mut("assignee") {
+        *assignee = entity_ref(assignee.take())?.into();
+    }
+    for field in ["comments", "attachments"] {
+        if let Some(Value::Array(items)) = t.get_mut(field) {
+            for item in items {
+                let author = entity_ref(item["author"].take())?;
+                item["author"] = author.into();
+            }
+        }
+    }
+    Ok(t)
+}
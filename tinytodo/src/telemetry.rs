@@ -0,0 +1,184 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! OpenTelemetry span export.
+//!
+//! When `[telemetry]` is configured, `tracing` spans are exported with OTLP, or written to a
+//! file for local testing. Requests carrying a W3C `traceparent` header continue the caller's
+//! trace: the context is extracted in [`crate::api::with_app`], travels with the
+//! [`crate::context::AppQuery`] into the application actor, and parents the spans for
+//! authorization, entity snapshot building and policy evaluation.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider, Context, Value};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    error::{OTelSdkError, OTelSdkResult},
+    propagation::TraceContextPropagator,
+    trace::{SdkTracerProvider, SpanData, SpanExporter},
+    Resource,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+use warp::http::HeaderMap;
+
+const SERVICE_NAME: &str = "tinytodo";
+
+fn default_endpoint() -> String {
+    "http://localhost:4318/v1/traces".to_string()
+}
+
+/// Where spans are exported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "exporter", rename_all = "lowercase", deny_unknown_fields)]
+pub enum TelemetryConfig {
+    /// An OTLP collector, over HTTP with protobuf encoding
+    Otlp {
+        #[serde(default = "default_endpoint")]
+        endpoint: String,
+    },
+    /// A file, one JSON object per span and line; meant for local tests
+    File { path: PathBuf },
+}
+
+#[derive(Debug, Error)]
+pub enum TelemetryError {
+    #[error("Couldn't create span file {}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Couldn't create OTLP exporter: {0}")]
+    Otlp(#[from] opentelemetry_otlp::ExporterBuildError),
+}
+
+/// Start exporting spans. The returned provider must be shut down before exiting, to flush
+/// spans that haven't been exported yet.
+pub fn tracer_provider(config: &TelemetryConfig) -> Result<SdkTracerProvider, TelemetryError> {
+    let builder = SdkTracerProvider::builder()
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build());
+    let builder = match config {
+        TelemetryConfig::Otlp { endpoint } => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()?;
+            builder.with_batch_exporter(exporter)
+        }
+        TelemetryConfig::File { path } => {
+            let file = File::create(path).map_err(|source| TelemetryError::File {
+                path: path.clone(),
+                source,
+            })?;
+            builder.with_batch_exporter(FileExporter {
+                file: Mutex::new(BufWriter::new(file)),
+            })
+        }
+    };
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(builder.build())
+}
+
+/// A `tracing` layer that exports spans with `provider`
+pub fn layer<S>(provider: &SdkTracerProvider) -> impl Layer<S>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// The trace context of an incoming request, from its `traceparent` and `tracestate` headers.
+/// Without telemetry configured, or without those headers, this is an empty context.
+pub fn extract_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(headers)))
+}
+
+/// Writes each span as a line of JSON
+#[derive(Debug)]
+struct FileExporter {
+    file: Mutex<BufWriter<File>>,
+}
+
+impl FileExporter {
+    fn write(&self, batch: Vec<SpanData>) -> std::io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        for span in batch {
+            let attributes: serde_json::Map<_, _> = span
+                .attributes
+                .iter()
+                .map(|kv| (kv.key.to_string(), json_value(&kv.value)))
+                .collect();
+            let line = json!({
+                "name": span.name,
+                "trace_id": span.span_context.trace_id().to_string(),
+                "span_id": span.span_context.span_id().to_string(),
+                "parent_span_id": span.parent_span_id.to_string(),
+                "start_time_unix_nano": unix_nanos(span.start_time),
+                "end_time_unix_nano": unix_nanos(span.end_time),
+                "attributes": attributes,
+            });
+            serde_json::to_writer(&mut *file, &line)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()
+    }
+}
+
+impl SpanExporter for FileExporter {
+    fn export(
+        &self,
+        batch: Vec<SpanData>,
+    ) -> impl std::future::Future<Output = OTelSdkResult> + Send {
+        let result = self
+            .write(batch)
+            .map_err(|e| OTelSdkError::InternalFailure(e.to_string()));
+        std::future::ready(result)
+    }
+}
+
+fn json_value(v: &Value) -> serde_json::Value {
+    match v {
+        Value::Bool(b) => json!(b),
+        Value::I64(i) => json!(i),
+        Value::F64(f) => json!(f),
+        v => json!(v.to_string()),
+    }
+}
+
+fn unix_nanos(t: SystemTime) -> u128 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()
+}
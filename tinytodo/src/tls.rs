@@ -0,0 +1,251 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Serving the API over HTTPS, with optional mutual TLS.
+//!
+//! When client certificates are enabled, each connection's verified certificate is
+//! recorded as a [`ClientIdentity`] in the extensions of every request made on it. A
+//! subject listed in `client_principals` acts as that `User`: it need not name itself in
+//! its requests, and may not make requests on behalf of any other user. Every request also
+//! carries the connection's [`PeerAddr`], for the per-IP rate limit. Connections that do
+//! not finish the TLS handshake within [`HANDSHAKE_TIMEOUT`] are closed.
+
+use std::{
+    collections::HashMap, convert::Infallible, fs::File, future::Future, io::BufReader,
+    net::SocketAddr, path::Path, sync::Arc, time::Duration,
+};
+
+use hyper::{server::conn::Http, service::service_fn, Body, Request, Response};
+use thiserror::Error;
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, watch},
+};
+use tokio_rustls::{
+    rustls::{
+        server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient},
+        Certificate, PrivateKey, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tower_service::Service;
+use tracing::{debug, info};
+
+use crate::{config::TlsConfig, util::UserUid};
+
+/// How long a client has to finish the TLS handshake, so that connections which never do
+/// are not held open
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The verified client certificate of the connection a request was made on
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    /// The certificate's subject, e.g. `CN=billing, O=Example Corp`
+    pub subject: String,
+    /// The user the subject is mapped to, if any
+    pub user: Option<UserUid>,
+}
+
+/// The address of the client a request came from. `warp::addr::remote` only knows it for
+/// connections accepted by warp's own server, not those served here.
+#[derive(Debug, Clone, Copy)]
+pub struct PeerAddr(pub SocketAddr);
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Error reading {}: {source}", path.display())]
+    Read {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+    #[error("No certificates found in {}", .0.display())]
+    NoCertificates(std::path::PathBuf),
+    #[error("No private key found in {}", .0.display())]
+    NoKey(std::path::PathBuf),
+    #[error("TLS configuration error: {0}")]
+    Rustls(#[from] tokio_rustls::rustls::Error),
+    #[error("{0}")]
+    IO(#[from] std::io::Error),
+}
+
+fn read_pem(path: &Path) -> Result<Vec<rustls_pemfile::Item>, TlsError> {
+    let read = |source| TlsError::Read {
+        path: path.into(),
+        source,
+    };
+    let file = File::open(path).map_err(read)?;
+    rustls_pemfile::read_all(&mut BufReader::new(file)).map_err(read)
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    let certs: Vec<_> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certs.is_empty() {
+        Err(TlsError::NoCertificates(path.into()))
+    } else {
+        Ok(certs)
+    }
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::NoKey(path.into()))
+}
+
+pub fn server_config(tls: &TlsConfig) -> Result<ServerConfig, TlsError> {
+    let certs = load_certs(&tls.cert)?;
+    let key = load_key(&tls.key)?;
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let mut config = match &tls.client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca)? {
+                roots.add(&cert)?;
+            }
+            let verifier = if tls.require_client_cert {
+                AllowAnyAuthenticatedClient::new(roots).boxed()
+            } else {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+            };
+            builder
+                .with_client_cert_verifier(verifier)
+                .with_single_cert(certs, key)?
+        }
+        None => builder.with_no_client_auth().with_single_cert(certs, key)?,
+    };
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+// The subject of a (verified) client certificate, and the user it maps to.
+// `principals` is keyed by the full subject or just its common name.
+fn client_identity(
+    cert: &Certificate,
+    principals: &HashMap<String, UserUid>,
+) -> Option<ClientIdentity> {
+    let (_, cert) = x509_parser::parse_x509_certificate(&cert.0).ok()?;
+    let subject = cert.subject().to_string();
+    let common_name = cert
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok());
+    let user = principals
+        .get(&subject)
+        .or_else(|| common_name.and_then(|cn| principals.get(cn)))
+        .cloned();
+    Some(ClientIdentity { subject, user })
+}
+
+/// Serve `service` (e.g., `warp::service(routes)`) over HTTPS until `shutdown` completes,
+/// then stop accepting connections and wait for in-flight requests to finish
+pub async fn serve<S>(
+    service: S,
+    addr: SocketAddr,
+    tls: &TlsConfig,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), TlsError>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let acceptor = TlsAcceptor::from(Arc::new(server_config(tls)?));
+    let principals = Arc::new(tls.client_principals.clone());
+    let listener = TcpListener::bind(addr).await?;
+    info!("Serving HTTPS on {addr}");
+    // Connections watch `stop` to start shutting down, and each holds a clone of `done`,
+    // so `wait_done` completes once every connection has closed
+    let (stop, stopped) = watch::channel(false);
+    let (done, mut wait_done) = mpsc::channel::<()>(1);
+    tokio::pin!(shutdown);
+    loop {
+        let (tcp, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            () = &mut shutdown => break,
+        };
+        let acceptor = acceptor.clone();
+        let principals = principals.clone();
+        let service = service.clone();
+        let mut stopped = stopped.clone();
+        let done = done.clone();
+        tokio::spawn(async move {
+            let _done = done;
+            let handshake = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp));
+            let stream = match handshake.await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    debug!("TLS handshake with {peer} failed: {e}");
+                    return;
+                }
+                Err(_) => {
+                    debug!("TLS handshake with {peer} timed out");
+                    return;
+                }
+            };
+            let identity = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| client_identity(cert, &principals));
+            if let Some(identity) = &identity {
+                debug!(
+                    "{peer} presented a client certificate for {}",
+                    identity.subject
+                );
+            }
+            let service = service_fn(move |mut req: Request<Body>| {
+                req.extensions_mut().insert(PeerAddr(peer));
+                if let Some(identity) = &identity {
+                    req.extensions_mut().insert(identity.clone());
+                }
+                service.clone().call(req)
+            });
+            let conn = Http::new().serve_connection(stream, service);
+            tokio::pin!(conn);
+            let result = tokio::select! {
+                result = &mut conn => result,
+                _ = stopped.changed() => {
+                    conn.as_mut().graceful_shutdown();
+                    conn.await
+                }
+            };
+            if let Err(e) = result {
+                debug!("Error serving {peer}: {e}");
+            }
+        });
+    }
+    drop(listener);
+    let _ = stop.send(true);
+    drop(done);
+    let _ = wait_done.recv().await;
+    Ok(())
+}
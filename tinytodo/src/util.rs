@@ -16,11 +16,15 @@
 
 use std::{ops::Deref, str::FromStr};
 
-use cedar_policy::{EntityTypeName, ParseErrors, RestrictedExpression};
+use cedar_policy::{EntityId, EntityTypeName, ParseErrors, RestrictedExpression};
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use thiserror::Error;
+use utoipa::{
+    openapi::{ObjectBuilder, RefOr, Schema, SchemaType},
+    ToSchema,
+};
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(transparent)]
@@ -103,6 +107,7 @@ lazy_static! {
     pub static ref TYPE_LIST: EntityTypeName = "List".parse().unwrap();
     pub static ref TYPE_USER: EntityTypeName = "User".parse().unwrap();
     pub static ref TYPE_TEAM: EntityTypeName = "Team".parse().unwrap();
+    pub static ref TYPE_TASK: EntityTypeName = "Task".parse().unwrap();
 }
 
 // Here we defined a bunch of typed wrappers around `EntityUid`.
@@ -208,6 +213,41 @@ impl AsRef<EntityUid> for ListUid {
     }
 }
 
+/// The uid of a task, when tasks are modeled as entities: `Task::"<list id>/<task id>"`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(try_from = "EntityUid")]
+#[serde(into = "EntityUid")]
+#[repr(transparent)]
+pub struct TaskUid(EntityUid);
+
+impl TaskUid {
+    pub fn new(list: &ListUid, task: i64) -> Self {
+        let id: EntityId = format!("{}/{task}", list.as_ref().id().unescaped())
+            .parse()
+            .unwrap();
+        Self(cedar_policy::EntityUid::from_type_name_and_id(TYPE_TASK.clone(), id).into())
+    }
+}
+
+impl TryFrom<EntityUid> for TaskUid {
+    type Error = EntityTypeError;
+    fn try_from(got: EntityUid) -> Result<Self, Self::Error> {
+        entity_type_check(&TYPE_TASK, got, Self)
+    }
+}
+
+impl From<TaskUid> for EntityUid {
+    fn from(t: TaskUid) -> Self {
+        t.0
+    }
+}
+
+impl AsRef<EntityUid> for TaskUid {
+    fn as_ref(&self) -> &EntityUid {
+        &self.0
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(try_from = "EntityUid")]
 #[serde(into = "EntityUid")]
@@ -280,6 +320,47 @@ impl From<TeamUid> for EntityUid {
     }
 }
 
+// All of the uid wrappers serialize as the string form of a Cedar `EntityUid`,
+// so they share a single OpenAPI schema shape.
+macro_rules! euid_schema {
+    ($ty:ident, $description:literal, $example:literal) => {
+        impl<'s> ToSchema<'s> for $ty {
+            fn schema() -> (&'s str, RefOr<Schema>) {
+                (
+                    stringify!($ty),
+                    ObjectBuilder::new()
+                        .schema_type(SchemaType::String)
+                        .description(Some($description))
+                        .example(Some(serde_json::json!($example)))
+                        .into(),
+                )
+            }
+        }
+    };
+}
+
+euid_schema!(
+    EntityUid,
+    "A Cedar entity uid",
+    r#"Application::"TinyTodo""#
+);
+euid_schema!(
+    UserUid,
+    "A Cedar entity uid of type User",
+    r#"User::"kesha""#
+);
+euid_schema!(ListUid, "A Cedar entity uid of type List", r#"List::"0""#);
+euid_schema!(
+    TeamUid,
+    "A Cedar entity uid of type Team",
+    r#"Team::"temp""#
+);
+euid_schema!(
+    UserOrTeamUid,
+    "A Cedar entity uid of type User or Team",
+    r#"Team::"interns""#
+);
+
 fn entity_type_check<T>(
     expected: &'static EntityTypeName,
     got: EntityUid,
@@ -0,0 +1,42 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The web front-end, served at `/`.
+//!
+//! The page and its script and stylesheet, in `web/`, are compiled into the binary. The
+//! script calls the v2 API as the user it logs in as, and enables only the controls that
+//! `/api/v2/authorize` says the user may use.
+
+use warp::{http::header::CONTENT_TYPE, reply::Response, Filter, Rejection, Reply};
+
+const INDEX: &str = include_str!("../web/index.html");
+const SCRIPT: &str = include_str!("../web/app.js");
+const STYLESHEET: &str = include_str!("../web/style.css");
+
+pub fn routes() -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::get().and(
+        warp::path::end()
+            .map(|| asset(INDEX, "text/html; charset=utf-8"))
+            .or(warp::path!("app.js").map(|| asset(SCRIPT, "text/javascript; charset=utf-8")))
+            .unify()
+            .or(warp::path!("style.css").map(|| asset(STYLESHEET, "text/css; charset=utf-8")))
+            .unify(),
+    )
+}
+
+fn asset(body: &'static str, content_type: &'static str) -> Response {
+    warp::reply::with_header(body, CONTENT_TYPE, content_type).into_response()
+}
@@ -0,0 +1,158 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Tests of reading and validating config files, which need no running server.
+
+use std::{collections::HashMap, path::Path};
+
+use tiny_todo_server::{
+    config::{Config, ConfigError, TlsConfig},
+    limits::{Bucket, LimitsError},
+};
+
+/// The default config, naming the files in this crate's directory
+fn config() -> Config {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let default = Config::default();
+    Config {
+        entities: manifest.join(&default.entities),
+        schema: manifest.join(&default.schema),
+        policies: manifest.join(&default.policies),
+        ..default
+    }
+}
+
+/// The config read from a file holding `src`
+fn from_toml(src: &str) -> Result<Config, ConfigError> {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tinytodo.toml");
+    std::fs::write(&path, src).unwrap();
+    Config::from_file(path)
+}
+
+#[test]
+fn defaults_are_valid() {
+    config().validate().unwrap();
+    assert_eq!(from_toml("").unwrap(), Config::default());
+    let shipped = Path::new(env!("CARGO_MANIFEST_DIR")).join("tinytodo.toml");
+    Config::from_file(shipped).unwrap();
+}
+
+#[test]
+fn bad_buckets() {
+    let bucket = |burst, per_second| Bucket { burst, per_second };
+    let bad = [
+        ("per_ip", bucket(0, 1.0)),
+        ("default_action", bucket(1, 0.0)),
+        ("default_action", bucket(1, f64::NAN)),
+        ("CreateTask", bucket(1, -1.0)),
+    ];
+    for (name, limit) in bad {
+        let mut config = config();
+        match name {
+            "per_ip" => config.limits.per_ip = Some(limit),
+            "default_action" => config.limits.default_action = Some(limit),
+            action => {
+                config.limits.actions = HashMap::from([(action.to_string(), limit)]);
+            }
+        }
+        match config.validate() {
+            Err(ConfigError::Limits(LimitsError::Bucket(got))) => assert_eq!(got, name),
+            r => panic!("expected a bad bucket for {name}, got {r:?}"),
+        }
+    }
+
+    let mut config = config();
+    let action = r#"Create"List"#.to_string();
+    config.limits.actions = HashMap::from([(action.clone(), bucket(1, 1.0))]);
+    match config.validate() {
+        Err(ConfigError::Limits(LimitsError::Action(got))) => assert_eq!(got, action),
+        r => panic!("expected a bad action name, got {r:?}"),
+    }
+}
+
+#[test]
+fn missing_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing");
+    for name in ["entities", "schema", "policies"] {
+        let mut config = config();
+        match name {
+            "entities" => config.entities = missing.clone(),
+            "schema" => config.schema = missing.clone(),
+            _ => config.policies = missing.clone(),
+        }
+        match config.validate() {
+            Err(ConfigError::MissingFile { name: got, path }) => {
+                assert_eq!((got, path), (name, missing.clone()))
+            }
+            r => panic!("expected the {name} file to be missing, got {r:?}"),
+        }
+        // Importing a snapshot writes these files, so they need not exist yet
+        config.validate_for_import().unwrap();
+    }
+
+    // The TLS files are read either way
+    let mut config = config();
+    config.tls = Some(TlsConfig {
+        cert: missing.clone(),
+        key: missing.clone(),
+        ..TlsConfig::default()
+    });
+    for result in [config.validate(), config.validate_for_import()] {
+        assert!(
+            matches!(
+                result,
+                Err(ConfigError::MissingFile {
+                    name: "TLS certificate",
+                    ..
+                })
+            ),
+            "{result:?}"
+        );
+    }
+}
+
+#[test]
+fn client_certificate_settings_need_a_ca() {
+    let tls = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/tls");
+    let mut config = config();
+    config.tls = Some(TlsConfig {
+        cert: tls.join("cert.pem"),
+        key: tls.join("key.pem"),
+        require_client_cert: true,
+        ..TlsConfig::default()
+    });
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::ClientCa("require_client_cert"))
+    ));
+}
+
+#[test]
+fn sharing_mode_is_not_a_setting() {
+    // The sharing mode is chosen by the `use-templates` feature, so a config file naming
+    // one, whichever it is, is refused rather than silently ignored
+    for mode in ["teams", "templates"] {
+        let result = from_toml(&format!("sharing = {mode:?}\n"));
+        match result {
+            Err(ConfigError::Parse { source, .. }) => {
+                assert!(source.to_string().contains("sharing"), "{source}")
+            }
+            r => panic!("expected `sharing` to be refused, got {r:?}"),
+        }
+    }
+}
@@ -0,0 +1,926 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A property-based differential test of the server's authorization decisions.
+//!
+//! Random sequences of operations (creating, sharing, unsharing and deleting lists and
+//! tasks, by arbitrary users, sharing with arbitrary users and teams) are applied to the
+//! application server. Alongside, the test keeps its own model of the lists and their
+//! shares, from which it builds the Cedar entities and policies of *both* sharing modes:
+//! teams (`policies.cedar`) and linked templates (`policies-templates.cedar`).
+//!
+//! After each step, it checks that
+//! - the server allowed the step exactly when `Authorizer::is_authorized` allows it on the
+//!   model, for the sharing mode the crate is built with;
+//! - the server's answers to `GetList`, `GetLists` and `GetListAccess` agree with the model;
+//! - the two sharing modes give the same decision for every user, action and list.
+//!
+//! A second test runs the same operations, along with reloads of the policies, on a server
+//! with a decision cache and one without, and checks that they make the same decisions. A
+//! third runs them on a server that models tasks as entities and one that does not, and
+//! checks the same.
+//!
+//! Run them both with and without `--features use-templates`.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use cedar_policy::{
+    Authorizer, Context, Decision, Entities, Entity, EntityUid, PolicyId, PolicySet, Request,
+    RestrictedExpression, Schema, SlotId,
+};
+use proptest::{prelude::*, sample::Index, test_runner::TestCaseError};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tiny_todo_server::{
+    api::{
+        AddShare, CreateList, CreateTask, DeleteList, DeleteShare, DeleteTask, GetList,
+        GetListAccess, GetLists, UpdateList, UpdateTask,
+    },
+    cache::CacheConfig,
+    config::Config,
+    context::{AppContext, AppQuery, AppQueryKind, AppResponse, Error},
+    lint::Locations,
+    metrics, util,
+};
+use tokio::sync::{mpsc::Sender, oneshot};
+
+const USERS: [&str; 4] = ["kesha", "aaron", "emina", "andrew"];
+const TEAMS: [&str; 3] = ["temp", "admin", "interns"];
+const LIST_ACTIONS: [&str; 9] = [
+    "GetList",
+    "UpdateList",
+    "DeleteList",
+    "CreateTask",
+    "UpdateTask",
+    "DeleteTask",
+    "EditShare",
+    "CommentOnTask",
+    "AttachToTask",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Role {
+    Reader,
+    Editor,
+}
+
+impl Role {
+    fn name(self) -> &'static str {
+        match self {
+            Role::Reader => "Reader",
+            Role::Editor => "Editor",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Teams,
+    Templates,
+}
+
+impl Mode {
+    /// The sharing mode the crate is built with
+    fn compiled() -> Self {
+        if cfg!(feature = "use-templates") {
+            Mode::Templates
+        } else {
+            Mode::Teams
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    CreateList {
+        user: usize,
+    },
+    GetList {
+        user: usize,
+        list: Index,
+    },
+    UpdateList {
+        user: usize,
+        list: Index,
+    },
+    DeleteList {
+        user: usize,
+        list: Index,
+    },
+    CreateTask {
+        user: usize,
+        list: Index,
+    },
+    UpdateTask {
+        user: usize,
+        list: Index,
+        task: Index,
+    },
+    DeleteTask {
+        user: usize,
+        list: Index,
+        task: Index,
+    },
+    Share {
+        user: usize,
+        list: Index,
+        target: usize,
+        role: Role,
+    },
+    Unshare {
+        user: usize,
+        list: Index,
+        target: usize,
+        role: Role,
+    },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let user = 0..USERS.len();
+    let target = 0..USERS.len() + TEAMS.len();
+    let role = prop_oneof![Just(Role::Reader), Just(Role::Editor)];
+    prop_oneof![
+        2 => user.clone().prop_map(|user| Op::CreateList { user }),
+        1 => (user.clone(), any::<Index>()).prop_map(|(user, list)| Op::GetList { user, list }),
+        1 => (user.clone(), any::<Index>()).prop_map(|(user, list)| Op::UpdateList { user, list }),
+        1 => (user.clone(), any::<Index>()).prop_map(|(user, list)| Op::DeleteList { user, list }),
+        2 => (user.clone(), any::<Index>()).prop_map(|(user, list)| Op::CreateTask { user, list }),
+        1 => (user.clone(), any::<Index>(), any::<Index>())
+            .prop_map(|(user, list, task)| Op::UpdateTask { user, list, task }),
+        1 => (user.clone(), any::<Index>(), any::<Index>())
+            .prop_map(|(user, list, task)| Op::DeleteTask { user, list, task }),
+        3 => (user.clone(), any::<Index>(), target.clone(), role.clone())
+            .prop_map(|(user, list, target, role)| Op::Share { user, list, target, role }),
+        2 => (user, any::<Index>(), target, role)
+            .prop_map(|(user, list, target, role)| Op::Unshare { user, list, target, role }),
+    ]
+}
+
+fn euid(ty: &str, id: &str) -> EntityUid {
+    format!(r#"{ty}::"{id}""#).parse().unwrap()
+}
+
+fn user(i: usize) -> EntityUid {
+    euid("User", USERS[i])
+}
+
+// Share targets are numbered users first, then teams
+fn target(i: usize) -> EntityUid {
+    match USERS.get(i) {
+        Some(id) => euid("User", id),
+        None => euid("Team", TEAMS[i - USERS.len()]),
+    }
+}
+
+fn action(name: &str) -> EntityUid {
+    euid("Action", name)
+}
+
+fn application() -> EntityUid {
+    euid("Application", "TinyTodo")
+}
+
+/// A list, as the test expects the server to have it
+#[derive(Debug, Clone)]
+struct ModelList {
+    uid: EntityUid,
+    owner: usize,
+    tasks: Vec<i64>,
+    shares: BTreeSet<(usize, Role)>,
+    deleted: bool,
+}
+
+/// The Cedar view of the model, in both sharing modes
+struct Oracle {
+    authorizer: Authorizer,
+    teams: (Schema, PolicySet),
+    templates: (Schema, PolicySet),
+    /// The users and teams of `entities.json`, as (uid, attributes, parents)
+    principals: Vec<(
+        EntityUid,
+        HashMap<String, RestrictedExpression>,
+        HashSet<EntityUid>,
+    )>,
+}
+
+fn manifest_file(name: &str) -> String {
+    std::fs::read_to_string(format!("{}/{name}", env!("CARGO_MANIFEST_DIR"))).unwrap()
+}
+
+impl Oracle {
+    fn new() -> Self {
+        let load = |schema: &str, policies: &str| {
+            let (schema, _) = Schema::from_cedarschema_str(&manifest_file(schema)).unwrap();
+            let policies: PolicySet = manifest_file(policies).parse().unwrap();
+            (schema, policies)
+        };
+        let store: Value = serde_json::from_str(&manifest_file("entities.json")).unwrap();
+        let parents = |v: &Value| -> HashSet<EntityUid> {
+            v["parents"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p.as_str().unwrap().parse().unwrap())
+                .collect()
+        };
+        let users = store["users"].as_object().unwrap().values().map(|u| {
+            let attrs = [
+                (
+                    "joblevel".to_string(),
+                    RestrictedExpression::new_long(u["joblevel"].as_i64().unwrap()),
+                ),
+                (
+                    "location".to_string(),
+                    RestrictedExpression::new_string(u["location"].as_str().unwrap().to_string()),
+                ),
+            ];
+            let uid = u["euid"].as_str().unwrap().parse().unwrap();
+            (uid, attrs.into_iter().collect(), parents(u))
+        });
+        let teams = store["teams"].as_object().unwrap().values().map(|t| {
+            let uid = t["uid"].as_str().unwrap().parse().unwrap();
+            (uid, HashMap::new(), parents(t))
+        });
+        Self {
+            authorizer: Authorizer::new(),
+            teams: load("tinytodo.cedarschema", "policies.cedar"),
+            templates: load("tinytodo-templates.cedarschema", "policies-templates.cedar"),
+            principals: users.chain(teams).collect(),
+        }
+    }
+
+    /// The entities and policies for `mode` in the state described by `lists`
+    fn state(&self, mode: Mode, lists: &[ModelList]) -> (Entities, PolicySet) {
+        let (schema, policies) = match mode {
+            Mode::Teams => &self.teams,
+            Mode::Templates => &self.templates,
+        };
+        let mut policies = policies.clone();
+        let mut principals = self.principals.clone();
+        let mut entities = vec![Entity::new_no_attrs(application(), HashSet::new())];
+        for list in lists.iter().filter(|l| !l.deleted) {
+            let team = |role: Role| {
+                euid(
+                    "Team",
+                    &format!("{}-{}", list.uid.id().escaped(), role.name()),
+                )
+            };
+            let mut attrs: HashMap<String, RestrictedExpression> = [
+                (
+                    "owner",
+                    RestrictedExpression::new_entity_uid(user(list.owner)),
+                ),
+                ("name", RestrictedExpression::new_string("list".to_string())),
+                ("tasks", RestrictedExpression::new_set([])),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+            match mode {
+                Mode::Teams => {
+                    // Each list has a team per role, which its sharees are members of
+                    for role in [Role::Reader, Role::Editor] {
+                        let attr = match role {
+                            Role::Reader => "readers",
+                            Role::Editor => "editors",
+                        };
+                        attrs.insert(
+                            attr.to_string(),
+                            RestrictedExpression::new_entity_uid(team(role)),
+                        );
+                        entities.push(Entity::new_no_attrs(team(role), HashSet::new()));
+                    }
+                    for (t, role) in &list.shares {
+                        let uid = target(*t);
+                        let (_, _, parents) =
+                            principals.iter_mut().find(|(p, _, _)| *p == uid).unwrap();
+                        parents.insert(team(*role));
+                    }
+                }
+                Mode::Templates => {
+                    // Each share is a link of the role's template
+                    for (t, role) in &list.shares {
+                        let template = match role {
+                            Role::Reader => "reader-template",
+                            Role::Editor => "editor-template",
+                        };
+                        let tid = policies
+                            .templates()
+                            .find(|p| p.annotation("id") == Some(template))
+                            .unwrap()
+                            .id()
+                            .clone();
+                        let pid = PolicyId::new(format!("{template}[{}][{}]", t, list.uid));
+                        let env = [
+                            (SlotId::principal(), target(*t)),
+                            (SlotId::resource(), list.uid.clone()),
+                        ];
+                        policies.link(tid, pid, env.into_iter().collect()).unwrap();
+                    }
+                }
+            }
+            entities.push(Entity::new(list.uid.clone(), attrs, HashSet::new()).unwrap());
+        }
+        entities.extend(
+            principals
+                .into_iter()
+                .map(|(uid, attrs, parents)| Entity::new(uid, attrs, parents).unwrap()),
+        );
+        (
+            Entities::from_entities(entities, Some(schema)).unwrap(),
+            policies,
+        )
+    }
+
+    fn is_authorized(
+        &self,
+        (entities, policies): &(Entities, PolicySet),
+        principal: EntityUid,
+        action_name: &str,
+        resource: EntityUid,
+    ) -> bool {
+        let request = Request::new(
+            principal,
+            action(action_name),
+            resource,
+            Context::empty(),
+            None,
+        )
+        .unwrap();
+        let response = self.authorizer.is_authorized(&request, policies, entities);
+        response.decision() == Decision::Allow
+    }
+}
+
+async fn query<T>(app: &Sender<AppQuery>, request: Value) -> Result<AppResponse, Error>
+where
+    T: DeserializeOwned + Into<AppQueryKind>,
+{
+    let kind: AppQueryKind = serde_json::from_value::<T>(request).unwrap().into();
+    let (send, recv) = oneshot::channel();
+    app.send(AppQuery::new(kind, send)).await.unwrap();
+    recv.await.unwrap()
+}
+
+// Whether the server authorized a request. Other errors (e.g., a missing task) come
+// after authorization, so they mean the request was allowed.
+fn allowed(response: &Result<AppResponse, Error>) -> Result<bool, TestCaseError> {
+    match response {
+        Err(Error::AuthDenied(_)) => Ok(false),
+        Err(Error::RateLimited(..)) => Err(TestCaseError::fail("rate limited")),
+        _ => Ok(true),
+    }
+}
+
+// Whether the server authorized a request that was sent, and whether it then succeeded
+fn outcome(sent: &Option<Sent>) -> Result<Option<(bool, bool)>, TestCaseError> {
+    sent.as_ref()
+        .map(|(.., response)| Ok((allowed(response)?, response.is_ok())))
+        .transpose()
+}
+
+fn pick(index: &Index, len: usize) -> Option<usize> {
+    (len > 0).then(|| index.index(len))
+}
+
+/// An operation sent to the server: the principal, action and resource it is authorized as,
+/// and the response
+type Sent = (usize, &'static str, EntityUid, Result<AppResponse, Error>);
+
+struct Run {
+    app: Sender<AppQuery>,
+    oracle: Oracle,
+    lists: Vec<ModelList>,
+}
+
+impl Run {
+    // The id of one of a list's tasks, or an id no task has if the list has none
+    fn task_id(&self, list: usize, index: &Index) -> i64 {
+        let tasks = &self.lists[list].tasks;
+        pick(index, tasks.len()).map_or(-1, |i| tasks[i])
+    }
+
+    // Apply one operation to the server, checking its decision against the model,
+    // and update the model to match
+    async fn step(&mut self, op: &Op) -> Result<(), TestCaseError> {
+        let state = self.oracle.state(Mode::compiled(), &self.lists);
+        let Some((principal, action, resource, response)) = self.send(&self.app, op).await else {
+            return Ok(());
+        };
+        let expected = self
+            .oracle
+            .is_authorized(&state, user(principal), action, resource);
+        prop_assert_eq!(
+            allowed(&response)?,
+            expected,
+            "{:?} gave {:?}",
+            op,
+            response
+        );
+        self.update(op, response);
+        Ok(())
+    }
+
+    // Send one operation to `app`, unless there is no list to apply it to
+    async fn send(&self, app: &Sender<AppQuery>, op: &Op) -> Option<Sent> {
+        let list = |index: &Index| pick(index, self.lists.len());
+        let sent = match op {
+            Op::CreateList { user: u } => {
+                let request = json!({ "uid": user(*u).to_string(), "name": "list" });
+                let response = query::<CreateList>(app, request).await;
+                (*u, "CreateList", application(), response)
+            }
+            Op::GetList { user: u, list: l }
+            | Op::UpdateList { user: u, list: l }
+            | Op::DeleteList { user: u, list: l }
+            | Op::CreateTask { user: u, list: l } => {
+                let l = list(l)?;
+                let request = json!({
+                    "uid": user(*u).to_string(),
+                    "list": self.lists[l].uid.to_string(),
+                    "name": "name",
+                });
+                let (action, response) = match op {
+                    Op::GetList { .. } => ("GetList", query::<GetList>(app, request).await),
+                    Op::UpdateList { .. } => {
+                        ("UpdateList", query::<UpdateList>(app, request).await)
+                    }
+                    Op::DeleteList { .. } => {
+                        ("DeleteList", query::<DeleteList>(app, request).await)
+                    }
+                    _ => ("CreateTask", query::<CreateTask>(app, request).await),
+                };
+                (*u, action, self.lists[l].uid.clone(), response)
+            }
+            Op::UpdateTask {
+                user: u,
+                list: l,
+                task: t,
+            }
+            | Op::DeleteTask {
+                user: u,
+                list: l,
+                task: t,
+            } => {
+                let l = list(l)?;
+                let request = json!({
+                    "uid": user(*u).to_string(),
+                    "list": self.lists[l].uid.to_string(),
+                    "task": self.task_id(l, t),
+                    "state": "Checked",
+                });
+                let (action, response) = match op {
+                    Op::UpdateTask { .. } => {
+                        ("UpdateTask", query::<UpdateTask>(app, request).await)
+                    }
+                    _ => ("DeleteTask", query::<DeleteTask>(app, request).await),
+                };
+                (*u, action, self.lists[l].uid.clone(), response)
+            }
+            Op::Share {
+                user: u,
+                list: l,
+                target: t,
+                role,
+            }
+            | Op::Unshare {
+                user: u,
+                list: l,
+                target: t,
+                role,
+            } => {
+                let l = list(l)?;
+                let mut request = json!({
+                    "uid": user(*u).to_string(),
+                    "list": self.lists[l].uid.to_string(),
+                    "role": role.name(),
+                });
+                let response = match op {
+                    Op::Share { .. } => {
+                        request["share_with"] = json!(target(*t).to_string());
+                        query::<AddShare>(app, request).await
+                    }
+                    _ => {
+                        request["unshare_with"] = json!(target(*t).to_string());
+                        query::<DeleteShare>(app, request).await
+                    }
+                };
+                (*u, "EditShare", self.lists[l].uid.clone(), response)
+            }
+        };
+        Some(sent)
+    }
+
+    // Update the model with the effect of an operation the server answered with `response`
+    fn update(&mut self, op: &Op, response: Result<AppResponse, Error>) {
+        if matches!(response, Err(Error::AuthDenied(_))) {
+            return;
+        }
+        match (op, response) {
+            (Op::CreateList { user }, Ok(AppResponse::Euid(uid))) => self.lists.push(ModelList {
+                uid: (*uid).clone(),
+                owner: *user,
+                tasks: vec![],
+                shares: BTreeSet::new(),
+                deleted: false,
+            }),
+            (Op::DeleteList { list: l, .. }, Ok(_)) => {
+                let list = &mut self.lists[l.index(self.lists.len())];
+                list.deleted = true;
+                list.shares.clear();
+            }
+            (Op::CreateTask { list: l, .. }, Ok(AppResponse::Id(id))) => {
+                let l = l.index(self.lists.len());
+                self.lists[l].tasks.push(id);
+            }
+            (
+                Op::DeleteTask {
+                    list: l, task: t, ..
+                },
+                Ok(_),
+            ) => {
+                let l = l.index(self.lists.len());
+                let id = self.task_id(l, t);
+                self.lists[l].tasks.retain(|task| *task != id);
+            }
+            (
+                Op::Share {
+                    list: l,
+                    target,
+                    role,
+                    ..
+                },
+                _,
+            ) => {
+                let l = l.index(self.lists.len());
+                self.lists[l].shares.insert((*target, *role));
+            }
+            (
+                Op::Unshare {
+                    list: l,
+                    target,
+                    role,
+                    ..
+                },
+                _,
+            ) => {
+                let l = l.index(self.lists.len());
+                self.lists[l].shares.remove(&(*target, *role));
+            }
+            (_, _) => (),
+        }
+    }
+
+    // Apply one operation to both the server and `other`, checking that they make the same
+    // decision, and update the model to match
+    async fn step_with(
+        &mut self,
+        other: &Sender<AppQuery>,
+        op: &Op,
+        what: &str,
+    ) -> Result<(), TestCaseError> {
+        let expected = self.send(&self.app, op).await;
+        let got = self.send(other, op).await;
+        prop_assert_eq!(
+            outcome(&got)?,
+            outcome(&expected)?,
+            "{:?} gave {:?} with {} and {:?} without",
+            op,
+            got,
+            what,
+            expected
+        );
+        if let Some((.., response)) = expected {
+            self.update(op, response);
+        }
+        Ok(())
+    }
+
+    // Compare every user's access to every list on the server and `other`
+    async fn compare_access(&self, other: &Sender<AppQuery>) -> Result<(), TestCaseError> {
+        for list in &self.lists {
+            for u in 0..USERS.len() {
+                let request = json!({ "uid": user(u).to_string(), "list": list.uid.to_string() });
+                let expected = query::<GetList>(&self.app, request.clone()).await;
+                let got = query::<GetList>(other, request).await;
+                prop_assert_eq!(
+                    allowed(&got)?,
+                    allowed(&expected)?,
+                    "{} GetList on {:?}",
+                    USERS[u],
+                    list
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // Compare what the server reports with the model, and the sharing modes with each other
+    async fn check(&self) -> Result<(), TestCaseError> {
+        let teams = self.oracle.state(Mode::Teams, &self.lists);
+        let templates = self.oracle.state(Mode::Templates, &self.lists);
+        let compiled = match Mode::compiled() {
+            Mode::Teams => &teams,
+            Mode::Templates => &templates,
+        };
+
+        for list in &self.lists {
+            for u in 0..USERS.len() {
+                for action in LIST_ACTIONS {
+                    let with_teams =
+                        self.oracle
+                            .is_authorized(&teams, user(u), action, list.uid.clone());
+                    let with_templates =
+                        self.oracle
+                            .is_authorized(&templates, user(u), action, list.uid.clone());
+                    prop_assert_eq!(
+                        with_teams,
+                        with_templates,
+                        "{} {} on {:?}: the sharing modes disagree",
+                        USERS[u],
+                        action,
+                        list
+                    );
+                }
+                let request = json!({ "uid": user(u).to_string(), "list": list.uid.to_string() });
+                let response = query::<GetList>(&self.app, request).await;
+                let expected =
+                    self.oracle
+                        .is_authorized(compiled, user(u), "GetList", list.uid.clone());
+                prop_assert_eq!(
+                    allowed(&response)?,
+                    expected,
+                    "{} GetList on {:?}",
+                    USERS[u],
+                    list
+                );
+            }
+        }
+
+        // `GetLists` finds the lists a user may read by partial evaluation
+        for u in 0..USERS.len() {
+            let response =
+                query::<GetLists>(&self.app, json!({ "uid": user(u).to_string() })).await;
+            let lists = match response {
+                Ok(AppResponse::Lists(lists)) => lists,
+                other => return Err(TestCaseError::fail(format!("GetLists gave {other:?}"))),
+            };
+            let actual: BTreeSet<String> = lists
+                .iter()
+                .map(|l| util::EntityUid::from(l.uid().clone()).to_string())
+                .collect();
+            let expected: BTreeSet<String> = self
+                .lists
+                .iter()
+                .filter(|l| {
+                    self.oracle
+                        .is_authorized(compiled, user(u), "GetList", l.uid.clone())
+                })
+                .map(|l| l.uid.to_string())
+                .collect();
+            prop_assert_eq!(actual, expected, "GetLists for {}", USERS[u]);
+        }
+
+        // `GetListAccess` finds the users allowed each action by partial evaluation
+        for list in self.lists.iter().filter(|l| !l.deleted) {
+            for action_name in LIST_ACTIONS {
+                let request = json!({
+                    "uid": user(list.owner).to_string(),
+                    "list": list.uid.to_string(),
+                    "action": action(action_name).to_string(),
+                });
+                let response = query::<GetListAccess>(&self.app, request).await;
+                let access = match response {
+                    Ok(AppResponse::ListAccess(access)) => access,
+                    other => {
+                        return Err(TestCaseError::fail(format!("GetListAccess gave {other:?}")))
+                    }
+                };
+                let actual: BTreeSet<String> = access
+                    .users
+                    .iter()
+                    .map(|u| util::EntityUid::from(u.clone()).to_string())
+                    .collect();
+                let expected: BTreeSet<String> = (0..USERS.len())
+                    .filter(|u| {
+                        self.oracle
+                            .is_authorized(compiled, user(*u), action_name, list.uid.clone())
+                    })
+                    .map(|u| user(u).to_string())
+                    .collect();
+                prop_assert_eq!(actual, expected, "{} access to {:?}", action_name, list);
+            }
+        }
+        Ok(())
+    }
+}
+
+// The server's configuration, which has no rate limits for random operations to hit
+fn config() -> Config {
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let default = Config::default();
+    Config {
+        entities: format!("{dir}/entities.json").into(),
+        schema: format!("{dir}/{}", default.schema.display()).into(),
+        policies: format!("{dir}/{}", default.policies.display()).into(),
+        ..default
+    }
+}
+
+async fn run(ops: Vec<Op>) -> Result<(), TestCaseError> {
+    let mut run = Run {
+        app: AppContext::spawn(&config()).unwrap(),
+        oracle: Oracle::new(),
+        lists: vec![],
+    };
+    let result = async {
+        for op in &ops {
+            run.step(op).await?;
+            run.check().await?;
+        }
+        Ok(())
+    }
+    .await;
+    AppContext::shutdown(&run.app).await.unwrap();
+    result
+}
+
+/// An operation for the decision cache test: one of the differential test's, or a reload
+/// of the policies
+#[derive(Debug, Clone)]
+enum CacheOp {
+    App(Op),
+    /// Reload the policies file, with Policy 4 (admins may do anything) if `admin`
+    Reload {
+        admin: bool,
+    },
+}
+
+fn cache_op() -> impl Strategy<Value = CacheOp> {
+    prop_oneof![
+        10 => op().prop_map(CacheOp::App),
+        1 => any::<bool>().prop_map(|admin| CacheOp::Reload { admin }),
+    ]
+}
+
+const ADMIN_POLICY: &str = r#"permit (
+    principal in Team::"admin",
+    action,
+    resource in Application::"TinyTodo"
+);"#;
+
+/// A server with a decision cache, run alongside the differential test's server, which has
+/// none. The differential test's model keeps track of the lists, but not of the policies,
+/// so the two servers are compared with each other rather than with Cedar.
+struct CacheRun {
+    run: Run,
+    cached: Sender<AppQuery>,
+}
+
+impl CacheRun {
+    async fn step(&mut self, op: &CacheOp) -> Result<(), TestCaseError> {
+        match op {
+            CacheOp::App(op) => self.run.step_with(&self.cached, op, "the cache").await?,
+            CacheOp::Reload { admin } => {
+                let mut src = std::fs::read_to_string(&config().policies).unwrap();
+                if *admin {
+                    src.push_str(ADMIN_POLICY);
+                }
+                let policies: PolicySet = src.parse().unwrap();
+                let locations = Locations::new(&config().policies, &src, &policies);
+                for app in [&self.run.app, &self.cached] {
+                    let (send, recv) = oneshot::channel();
+                    let kind = AppQueryKind::UpdatePolicySet(policies.clone(), locations.clone());
+                    app.send(AppQuery::new(kind, send)).await.unwrap();
+                    recv.await.unwrap().unwrap();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Compare every user's access to every list on the two servers, twice. Nothing changes
+    // in between, so the cached server answers the second round from its cache.
+    async fn check(&self) -> Result<(), TestCaseError> {
+        self.run.compare_access(&self.cached).await?;
+        let hits = || metrics::DECISION_CACHE.with_label_values(&["hit"]).get();
+        let before = hits();
+        self.run.compare_access(&self.cached).await?;
+        prop_assert_eq!(hits() - before, (self.run.lists.len() * USERS.len()) as u64);
+        Ok(())
+    }
+}
+
+async fn run_cached(ops: Vec<CacheOp>) -> Result<(), TestCaseError> {
+    let mut run = CacheRun {
+        run: Run {
+            app: AppContext::spawn(&config()).unwrap(),
+            oracle: Oracle::new(),
+            lists: vec![],
+        },
+        cached: AppContext::spawn(&Config {
+            decision_cache: Some(CacheConfig::default()),
+            ..config()
+        })
+        .unwrap(),
+    };
+    let result = async {
+        for op in &ops {
+            run.step(op).await?;
+            run.check().await?;
+        }
+        Ok(())
+    }
+    .await;
+    for app in [&run.run.app, &run.cached] {
+        AppContext::shutdown(app).await.unwrap();
+    }
+    result
+}
+
+// The schema and policies for servers that model tasks as entities
+#[cfg(not(feature = "use-templates"))]
+const TASK_FILES: (&str, &str) = ("tinytodo-tasks.cedarschema", "policies-tasks.cedar");
+#[cfg(feature = "use-templates")]
+const TASK_FILES: (&str, &str) = (
+    "tinytodo-templates-tasks.cedarschema",
+    "policies-templates-tasks.cedar",
+);
+
+// Whether a task operation names a task of its list; when tasks are entities, one that
+// names no task is reported as missing to those who may read the list, rather than denied
+fn names_task(run: &Run, op: &Op) -> bool {
+    match op {
+        Op::UpdateTask { list, .. } | Op::DeleteTask { list, .. } => {
+            !pick(list, run.lists.len()).is_some_and(|l| run.lists[l].tasks.is_empty())
+        }
+        _ => true,
+    }
+}
+
+async fn run_task_entities(ops: Vec<Op>) -> Result<(), TestCaseError> {
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let (schema, policies) = TASK_FILES;
+    let mut run = Run {
+        app: AppContext::spawn(&config()).unwrap(),
+        oracle: Oracle::new(),
+        lists: vec![],
+    };
+    let tasks = AppContext::spawn(&Config {
+        schema: format!("{dir}/{schema}").into(),
+        policies: format!("{dir}/{policies}").into(),
+        task_entities: true,
+        ..config()
+    })
+    .unwrap();
+    let result = async {
+        for op in &ops {
+            if !names_task(&run, op) {
+                continue;
+            }
+            run.step_with(&tasks, op, "task entities").await?;
+            run.compare_access(&tasks).await?;
+        }
+        Ok(())
+    }
+    .await;
+    for app in [&run.app, &tasks] {
+        AppContext::shutdown(app).await.unwrap();
+    }
+    result
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig { cases: 64, ..ProptestConfig::default() })]
+
+    #[test]
+    fn decisions_match_cedar(ops in prop::collection::vec(op(), 1..40)) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(run(ops))?;
+    }
+
+    #[test]
+    fn cached_decisions_match(ops in prop::collection::vec(cache_op(), 1..40)) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(run_cached(ops))?;
+    }
+
+    #[test]
+    fn task_entity_decisions_match(ops in prop::collection::vec(op(), 1..40)) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(run_task_entities(ops))?;
+    }
+}
@@ -0,0 +1,2870 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! End-to-end tests: each test starts the server on an ephemeral port, with its own copies
+//! of the entities, schema and policies files, and drives it over HTTP.
+//!
+//! The tests run against whichever sharing mode the crate is built with, so run them both
+//! with and without `--features use-templates`.
+
+use std::{
+    future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use cedar_policy::PolicyId;
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tiny_todo_server::{
+    api::{serve_api, ShareRole},
+    cache::CacheConfig,
+    config::{Config, TlsConfig},
+    context::{AppContext, AppQuery, ContextError},
+    limits::{Bucket, LimitsConfig},
+    lint::{LintConfig, WarningKind, Warnings},
+    profiles::PolicyProfile,
+    roles::RoleConfig,
+    shares::EXPIRING_SHARE_PREFIX,
+    snapshot,
+    telemetry::{self, TelemetryConfig},
+    util,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tracing_subscriber::prelude::*;
+
+const KESHA: &str = r#"User::"kesha""#;
+const AARON: &str = r#"User::"aaron""#;
+const EMINA: &str = r#"User::"emina""#;
+const ANDREW: &str = r#"User::"andrew""#;
+
+#[cfg(not(feature = "use-templates"))]
+const FILES: (&str, &str) = ("tinytodo.cedarschema", "policies.cedar");
+#[cfg(feature = "use-templates")]
+const FILES: (&str, &str) = ("tinytodo-templates.cedarschema", "policies-templates.cedar");
+
+// The schema and policies for servers that model tasks as entities
+#[cfg(not(feature = "use-templates"))]
+const TASK_FILES: (&str, &str) = ("tinytodo-tasks.cedarschema", "policies-tasks.cedar");
+#[cfg(feature = "use-templates")]
+const TASK_FILES: (&str, &str) = (
+    "tinytodo-templates-tasks.cedarschema",
+    "policies-templates-tasks.cedar",
+);
+
+const ADMIN_POLICY: &str = r#"
+@id("admin-omnipotence")
+permit (
+    principal in Team::"admin",
+    action,
+    resource in Application::"TinyTodo"
+);
+"#;
+
+/// A running server, stopped when dropped
+struct TestServer {
+    url: String,
+    client: reqwest::Client,
+    policies: PathBuf,
+    app: mpsc::Sender<AppQuery>,
+    shutdown: Option<oneshot::Sender<()>>,
+    server: Option<JoinHandle<()>>,
+    _dir: TempDir,
+}
+
+impl TestServer {
+    async fn start() -> Self {
+        Self::start_with(FILES, |_| ()).await
+    }
+
+    /// Start a server with the given schema and policies, and the default config as changed
+    /// by `configure`, which may also change the copies of the files it names
+    async fn start_with(
+        (schema, policies): (&str, &str),
+        configure: impl FnOnce(&mut Config),
+    ) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let copy = |name: &str| {
+            let to = dir.path().join(name);
+            let from = Path::new(env!("CARGO_MANIFEST_DIR")).join(name);
+            std::fs::copy(from, &to).unwrap();
+            to
+        };
+        let mut config = Config {
+            bind: ([127, 0, 0, 1], free_port()).into(),
+            entities: copy("entities.json"),
+            schema: copy(schema),
+            policies: copy(policies),
+            ..Config::default()
+        };
+        configure(&mut config);
+        config.validate().unwrap();
+        let app = AppContext::spawn(&config).unwrap();
+        let (send, recv) = oneshot::channel();
+        let server = {
+            let app = app.clone();
+            let config = config.clone();
+            tokio::spawn(async move {
+                serve_api(app, &config, async {
+                    let _ = recv.await;
+                })
+                .await
+            })
+        };
+        // Over HTTPS, the client trusts the server's certificate, which is for `localhost`.
+        // If the server requires a client certificate, it presents one that maps to no user.
+        let (url, client) = match &config.tls {
+            Some(tls) => {
+                let identity = tls.require_client_cert.then_some("stranger.pem");
+                let client = tls_client(config.bind, identity);
+                (format!("https://localhost:{}", config.bind.port()), client)
+            }
+            None => (format!("http://{}", config.bind), reqwest::Client::new()),
+        };
+        let server = Self {
+            url,
+            client,
+            policies: config.policies.clone(),
+            app,
+            shutdown: Some(send),
+            server: Some(server),
+            _dir: dir,
+        };
+        let s = &server;
+        eventually("the server to start", || async move {
+            let resp = s.get("/healthz").send().await;
+            matches!(resp, Ok(r) if r.status() == StatusCode::OK)
+        })
+        .await;
+        server
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.client.get(format!("{}{path}", self.url))
+    }
+
+    fn v1(&self, method: Method, path: &str) -> RequestBuilder {
+        self.client
+            .request(method, format!("{}/api/{path}", self.url))
+    }
+
+    fn v2(&self, method: Method, path: &str, user: &str) -> RequestBuilder {
+        self.client
+            .request(method, format!("{}/api/v2/{path}", self.url))
+            .header("x-tinytodo-user", user)
+    }
+
+    async fn stop(mut self) {
+        let _ = self.shutdown.take().unwrap().send(());
+        self.server.take().unwrap().await.unwrap();
+        AppContext::shutdown(&self.app).await.unwrap();
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(server) = &self.server {
+            server.abort();
+        }
+    }
+}
+
+fn free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// Wait up to ten seconds for `check` to succeed
+async fn eventually<F, Fut>(what: &str, mut check: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    for _ in 0..200 {
+        if check().await {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Timed out waiting for {what}");
+}
+
+/// A successful v1 response's body
+async fn ok(resp: reqwest::Result<Response>) -> Value {
+    let resp = resp.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert!(body.get("error").is_none(), "unexpected error: {body}");
+    body
+}
+
+/// A failed v1 response's error message; v1 reports errors with status 200
+async fn error(resp: reqwest::Result<Response>) -> String {
+    let resp = resp.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    body["error"]
+        .as_str()
+        .expect("expected an error")
+        .to_string()
+}
+
+/// A v2 response's status and body, which is `null` when empty
+async fn status(resp: reqwest::Result<Response>) -> (StatusCode, Value) {
+    let resp = resp.unwrap();
+    let status = resp.status();
+    let bytes = resp.bytes().await.unwrap();
+    let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    (status, body)
+}
+
+// The id of a list, as used in v2 paths, e.g. `0` for `List::"0"`
+fn list_id(list: &str) -> &str {
+    list.trim_start_matches("List::\"").trim_end_matches('"')
+}
+
+#[tokio::test]
+async fn v1_routes() {
+    let s = TestServer::start().await;
+
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let list = list.as_str().unwrap().to_string();
+
+    let lists = ok(s
+        .v1(Method::GET, "lists/get")
+        .query(&[("uid", KESHA)])
+        .send()
+        .await)
+    .await;
+    assert_eq!(lists.as_array().unwrap().len(), 1);
+
+    let task = ok(s
+        .v1(Method::POST, "task/create")
+        .json(&json!({ "uid": KESHA, "list": list, "name": "Draft" }))
+        .send()
+        .await)
+    .await;
+    let task = task.as_i64().unwrap();
+    ok(s.v1(Method::POST, "task/update")
+        .json(&json!({ "uid": KESHA, "list": list, "task": task, "state": "Checked" }))
+        .send()
+        .await)
+    .await;
+    ok(s.v1(Method::POST, "list/update")
+        .json(&json!({ "uid": KESHA, "list": list, "name": "Cedar blog posts" }))
+        .send()
+        .await)
+    .await;
+
+    let got = ok(s
+        .v1(Method::GET, "list/get")
+        .query(&[("uid", KESHA), ("list", &list)])
+        .send()
+        .await)
+    .await;
+    assert_eq!(got["name"], "Cedar blog posts");
+    assert_eq!(got["tasks"][0]["state"], "Checked");
+
+    // Aaron may not read the list until it is shared with him
+    let get_as_aaron = || {
+        s.v1(Method::GET, "list/get")
+            .query(&[("uid", AARON), ("list", &list)])
+            .send()
+    };
+    assert_eq!(error(get_as_aaron().await).await, "Authorization Denied");
+    let share = json!({ "uid": KESHA, "list": list, "share_with": AARON, "role": "Reader" });
+    ok(s.v1(Method::POST, "share").json(&share).send().await).await;
+    ok(get_as_aaron().await).await;
+
+    let access = ok(s
+        .v1(Method::GET, "list/access")
+        .query(&[
+            ("uid", KESHA),
+            ("list", &list),
+            ("action", r#"Action::"GetList""#),
+        ])
+        .send()
+        .await)
+    .await;
+    let users = access["users"].as_array().unwrap();
+    assert!(users.contains(&json!(KESHA)));
+    assert!(users.contains(&json!(AARON)));
+
+    // A reader may not edit
+    let err = error(
+        s.v1(Method::POST, "task/create")
+            .json(&json!({ "uid": AARON, "list": list, "name": "Sneaky" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(err, "Authorization Denied");
+
+    let unshare = json!({ "uid": KESHA, "list": list, "unshare_with": AARON, "role": "Reader" });
+    ok(s.v1(Method::DELETE, "share").json(&unshare).send().await).await;
+    assert_eq!(error(get_as_aaron().await).await, "Authorization Denied");
+
+    ok(s.v1(Method::DELETE, "task/delete")
+        .json(&json!({ "uid": KESHA, "list": list, "task": task }))
+        .send()
+        .await)
+    .await;
+    ok(s.v1(Method::DELETE, "list/delete")
+        .json(&json!({ "uid": KESHA, "list": list }))
+        .send()
+        .await)
+    .await;
+    let lists = ok(s
+        .v1(Method::GET, "lists/get")
+        .query(&[("uid", KESHA)])
+        .send()
+        .await)
+    .await;
+    assert_eq!(lists, json!([]));
+
+    let doc = ok(s.v1(Method::GET, "openapi.json").send().await).await;
+    assert!(doc["paths"]["/api/v2/lists"].is_object());
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn openapi_paths_are_served() {
+    let s = TestServer::start().await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let id = list_id(list.as_str().unwrap()).to_string();
+    let doc = ok(s.v1(Method::GET, "openapi.json").send().await).await;
+    let paths = doc["paths"].as_object().unwrap();
+    assert!(paths.contains_key("/api/list/get"));
+
+    // Every operation in the document is routed. The requests are malformed or made by
+    // Aaron, who may not change Kesha's list, so none of them changes anything.
+    for (path, operations) in paths {
+        let url = path
+            .replace("{id}", &id)
+            .replace("{task}", "0")
+            .replace("{comment}", "0")
+            .replace("{attachment}", "0");
+        for method in operations.as_object().unwrap().keys() {
+            let method: Method = method.to_uppercase().parse().unwrap();
+            let resp = s
+                .client
+                .request(method.clone(), format!("{}{url}", s.url))
+                .header("x-tinytodo-user", AARON)
+                .json(&json!({}))
+                .send()
+                .await
+                .unwrap();
+            assert!(
+                ![StatusCode::NOT_FOUND, StatusCode::METHOD_NOT_ALLOWED].contains(&resp.status()),
+                "{method} {path}: {}",
+                resp.status()
+            );
+        }
+    }
+
+    s.stop().await;
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn typed_client() {
+    use tiny_todo_server::client::{Client, ClientError};
+
+    let s = TestServer::start().await;
+    let client = Client::new(&s.url);
+    client.healthz().await.unwrap();
+    assert!(client.openapi().await.unwrap()["paths"]["/api/v2/lists"].is_object());
+
+    let request = |body: Value| serde_json::from_value(body).unwrap();
+    let list = client
+        .create_list(&request(json!({ "uid": KESHA, "name": "Cedar blog" })))
+        .await
+        .unwrap()
+        .to_string();
+    let task = json!({ "uid": KESHA, "list": list, "name": "Draft" });
+    assert_eq!(client.create_task(&request(task)).await.unwrap(), 0);
+    let got = client
+        .get_list(&request(json!({ "uid": KESHA, "list": list })))
+        .await
+        .unwrap();
+    let got = serde_json::to_value(got).unwrap();
+    assert_eq!(got["name"], "Cedar blog");
+    assert_eq!(got["tasks"][0]["name"], "Draft");
+
+    let denied = client
+        .get_list(&request(json!({ "uid": AARON, "list": list })))
+        .await;
+    assert!(matches!(denied, Err(ClientError::AuthDenied)), "{denied:?}");
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn list_access() {
+    let s = TestServer::start().await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let list = list.as_str().unwrap();
+    let temp = r#"Team::"temp""#;
+    let share = json!({ "uid": KESHA, "list": list, "share_with": temp, "role": "Reader" });
+    ok(s.v1(Method::POST, "share").json(&share).send().await).await;
+    let server = &s;
+    let access = |uid: &'static str, action: &'static str| async move {
+        server
+            .v1(Method::GET, "list/access")
+            .query(&[("uid", uid), ("list", list), ("action", action)])
+            .send()
+            .await
+    };
+
+    // Aaron reads the list through the interns team, which is in the temp team
+    let readers = ok(access(KESHA, r#"Action::"GetList""#).await).await;
+    assert_eq!(readers["users"], json!([AARON, ANDREW, KESHA]));
+    let teams = readers["teams"].as_array().unwrap();
+    assert!(teams.contains(&json!(temp)));
+    assert!(teams.contains(&json!(r#"Team::"interns""#)));
+    assert!(!teams.contains(&json!(r#"Team::"admin""#)));
+
+    let editors = ok(access(KESHA, r#"Action::"UpdateList""#).await).await;
+    assert_eq!(editors["users"], json!([KESHA]));
+    assert!(!editors["teams"].as_array().unwrap().contains(&json!(temp)));
+
+    // Admins may transfer any list, and only the owner may transfer it otherwise
+    let transfer = ok(access(KESHA, r#"Action::"TransferOwnership""#).await).await;
+    assert_eq!(transfer["users"], json!([ANDREW, EMINA, KESHA]));
+
+    // Only those who may change the list's shares may see who it is shared with
+    assert_eq!(
+        error(access(AARON, r#"Action::"GetList""#).await).await,
+        "Authorization Denied"
+    );
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn v2_routes() {
+    let s = TestServer::start().await;
+
+    let resp = s
+        .v2(Method::POST, "lists", KESHA)
+        .json(&json!({ "name": "Cedar blog" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let location = resp.headers()["location"].to_str().unwrap().to_string();
+    let list: String = resp.json().await.unwrap();
+    let id = list_id(&list).to_string();
+    assert_eq!(location, format!("/api/v2/lists/{id}"));
+
+    let (code, lists) = status(s.v2(Method::GET, "lists", KESHA).send().await).await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(lists.as_array().unwrap().len(), 1);
+
+    let (code, _) = status(
+        s.v2(Method::PATCH, &format!("lists/{id}"), KESHA)
+            .json(&json!({ "name": "Cedar blog posts" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::OK);
+
+    let resp = s
+        .v2(Method::POST, &format!("lists/{id}/tasks"), KESHA)
+        .json(&json!({ "name": "Draft" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let task: i64 = resp.json().await.unwrap();
+
+    let (code, _) = status(
+        s.v2(Method::PATCH, &format!("lists/{id}/tasks/{task}"), KESHA)
+            .json(&json!({ "state": "Checked" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::OK);
+
+    let (code, got) = status(
+        s.v2(Method::GET, &format!("lists/{id}"), KESHA)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(got["name"], "Cedar blog posts");
+    assert_eq!(got["tasks"][0]["state"], "Checked");
+
+    // Errors are reported with status codes
+    let (code, _) = status(
+        s.v2(Method::GET, &format!("lists/{id}"), AARON)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::FORBIDDEN);
+    let (code, _) = status(s.v2(Method::GET, "lists/nonexistent", KESHA).send().await).await;
+    assert_eq!(code, StatusCode::NOT_FOUND);
+    let (code, _) = status(s.get("/api/v2/lists").send().await).await;
+    assert_eq!(code, StatusCode::UNAUTHORIZED);
+
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+            .json(&json!({ "share_with": AARON, "role": "Editor" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    let (code, _) = status(
+        s.v2(Method::GET, &format!("lists/{id}"), AARON)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::OK);
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/tasks"), AARON)
+            .json(&json!({ "name": "Review" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+
+    let (code, access) = status(
+        s.v2(Method::GET, &format!("lists/{id}/access"), KESHA)
+            .query(&[("action", r#"Action::"UpdateTask""#)])
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::OK);
+    assert!(access["users"].as_array().unwrap().contains(&json!(AARON)));
+
+    let unshare = || {
+        s.v2(Method::DELETE, &format!("lists/{id}/shares"), KESHA)
+            .query(&[("target", AARON), ("role", "Editor")])
+            .send()
+    };
+    let (code, _) = status(unshare().await).await;
+    assert_eq!(code, StatusCode::NO_CONTENT);
+    let (code, _) = status(
+        s.v2(Method::GET, &format!("lists/{id}"), AARON)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::FORBIDDEN);
+    // Removing a share that no longer exists changes nothing, in either sharing mode
+    let (code, _) = status(unshare().await).await;
+    assert_eq!(code, StatusCode::NO_CONTENT);
+
+    let (code, _) = status(
+        s.v2(Method::DELETE, &format!("lists/{id}/tasks/{task}"), KESHA)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::NO_CONTENT);
+    let (code, _) = status(
+        s.v2(Method::DELETE, &format!("lists/{id}"), KESHA)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::NO_CONTENT);
+    let (code, _) = status(
+        s.v2(Method::GET, &format!("lists/{id}"), KESHA)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::NOT_FOUND);
+
+    s.stop().await;
+}
+
+// With templates, each share is a linked policy, which must go when its list does
+#[tokio::test]
+async fn deleting_a_list_removes_its_shares() {
+    let s = TestServer::start().await;
+    let server = &s;
+    let policies = || async move {
+        let (_, ready) = status(server.get("/readyz").send().await).await;
+        ready["policies"].as_u64().unwrap()
+    };
+    let before = policies().await;
+    let (code, list) = status(
+        s.v2(Method::POST, "lists", KESHA)
+            .json(&json!({ "name": "Cedar blog" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    let id = list_id(list.as_str().unwrap()).to_string();
+    for user in [AARON, EMINA] {
+        let (code, _) = status(
+            s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+                .json(&json!({ "share_with": user, "role": "Reader" }))
+                .send()
+                .await,
+        )
+        .await;
+        assert_eq!(code, StatusCode::CREATED);
+    }
+    let (code, _) = status(
+        s.v2(Method::DELETE, &format!("lists/{id}"), KESHA)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::NO_CONTENT);
+    assert_eq!(policies().await, before);
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn comments_and_attachments() {
+    let s = TestServer::start().await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let list = list.as_str().unwrap().to_string();
+    let id = list_id(&list).to_string();
+    let task = ok(s
+        .v1(Method::POST, "task/create")
+        .json(&json!({ "uid": KESHA, "list": list, "name": "Draft" }))
+        .send()
+        .await)
+    .await;
+    let task = task.as_i64().unwrap();
+    for user in [AARON, EMINA] {
+        let share = json!({ "uid": KESHA, "list": list, "share_with": user, "role": "Reader" });
+        ok(s.v1(Method::POST, "share").json(&share).send().await).await;
+    }
+
+    // Readers may comment, and authors may edit their own comments
+    let comment = ok(s
+        .v1(Method::POST, "comment/create")
+        .json(&json!({ "uid": AARON, "list": list, "task": task, "body": "LGTM" }))
+        .send()
+        .await)
+    .await;
+    let comment = comment.as_i64().unwrap();
+    ok(s.v1(Method::POST, "comment/update")
+        .json(&json!({ "uid": AARON, "list": list, "task": task, "comment": comment, "body": "LGTM!" }))
+        .send()
+        .await)
+    .await;
+    let err = error(
+        s.v1(Method::POST, "comment/update")
+            .json(&json!({ "uid": EMINA, "list": list, "task": task, "comment": comment, "body": "Nope" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(err, "Authorization Denied");
+
+    let resp = s
+        .v2(
+            Method::POST,
+            &format!("lists/{id}/tasks/{task}/comments"),
+            EMINA,
+        )
+        .json(&json!({ "body": "Ship it" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let theirs: i64 = resp.json().await.unwrap();
+    let (code, _) = status(
+        s.v2(
+            Method::PATCH,
+            &format!("lists/{id}/tasks/{task}/comments/{comment}"),
+            EMINA,
+        )
+        .json(&json!({ "body": "Nope" }))
+        .send()
+        .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::FORBIDDEN);
+    let (code, _) = status(
+        s.v2(
+            Method::DELETE,
+            &format!("lists/{id}/tasks/{task}/comments/{theirs}"),
+            EMINA,
+        )
+        .send()
+        .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::NO_CONTENT);
+    let (code, _) = status(
+        s.v2(
+            Method::DELETE,
+            &format!("lists/{id}/tasks/{task}/comments/{theirs}"),
+            EMINA,
+        )
+        .send()
+        .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::NOT_FOUND);
+    // The ids of deleted comments are not reused
+    let resp = s
+        .v2(
+            Method::POST,
+            &format!("lists/{id}/tasks/{task}/comments"),
+            EMINA,
+        )
+        .json(&json!({ "body": "Ship it!" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let next: i64 = resp.json().await.unwrap();
+    assert!(next > theirs, "{next} after {theirs}");
+
+    // Attachments are decoded and checked against `max_attachment_bytes`
+    let attachments = format!("lists/{id}/tasks/{task}/attachments");
+    let resp = s
+        .v2(Method::POST, &attachments, AARON)
+        .json(&json!({ "name": "notes.txt", "content_type": "text/plain", "data": "aGVsbG8=" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let attachment: i64 = resp.json().await.unwrap();
+    let (code, _) = status(
+        s.v2(Method::POST, &attachments, AARON)
+            .json(&json!({ "name": "bad", "content_type": "text/plain", "data": "not base64!" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::BAD_REQUEST);
+    let big = BASE64_STANDARD.encode(vec![
+        0u8;
+        LimitsConfig::default().max_attachment_bytes.unwrap()
+            + 1
+    ]);
+    let (code, _) = status(
+        s.v2(Method::POST, &attachments, AARON)
+            .json(
+                &json!({ "name": "big", "content_type": "application/octet-stream", "data": big }),
+            )
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::PAYLOAD_TOO_LARGE);
+    // A task may only have so many attachments
+    let max = LimitsConfig::default().max_attachments_per_task.unwrap();
+    for n in 1..=max {
+        let (code, _) = status(
+            s.v2(Method::POST, &attachments, AARON)
+                .json(&json!({ "name": "more.txt", "content_type": "text/plain", "data": "" }))
+                .send()
+                .await,
+        )
+        .await;
+        let expected = if n < max {
+            StatusCode::CREATED
+        } else {
+            StatusCode::FORBIDDEN
+        };
+        assert_eq!(code, expected, "attachment {n}");
+    }
+
+    let got = ok(s
+        .v1(Method::GET, "list/get")
+        .query(&[("uid", KESHA), ("list", &list)])
+        .send()
+        .await)
+    .await;
+    let comments = got["tasks"][0]["comments"].as_array().unwrap();
+    assert_eq!(comments.len(), 2);
+    assert_eq!(comments[0]["author"], AARON);
+    assert_eq!(comments[0]["body"], "LGTM!");
+    assert_eq!(got["tasks"][0]["attachments"][0]["name"], "notes.txt");
+    assert_eq!(got["tasks"][0]["attachments"][0]["data"], "aGVsbG8=");
+
+    // Only the author or the list's owner may delete an attachment
+    let delete = json!({ "uid": EMINA, "list": list, "task": task, "attachment": attachment });
+    let err = error(
+        s.v1(Method::DELETE, "attachment/delete")
+            .json(&delete)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(err, "Authorization Denied");
+    let delete = json!({ "uid": KESHA, "list": list, "task": task, "attachment": attachment });
+    ok(s.v1(Method::DELETE, "attachment/delete")
+        .json(&delete)
+        .send()
+        .await)
+    .await;
+    let delete = json!({ "uid": AARON, "list": list, "task": task, "comment": comment });
+    ok(s.v1(Method::DELETE, "comment/delete")
+        .json(&delete)
+        .send()
+        .await)
+    .await;
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn revoked_author() {
+    let s = TestServer::start().await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let list = list.as_str().unwrap().to_string();
+    let task = ok(s
+        .v1(Method::POST, "task/create")
+        .json(&json!({ "uid": KESHA, "list": list, "name": "Draft" }))
+        .send()
+        .await)
+    .await;
+    let task = task.as_i64().unwrap();
+    let share = json!({ "uid": KESHA, "list": list, "share_with": AARON, "role": "Reader" });
+    ok(s.v1(Method::POST, "share").json(&share).send().await).await;
+    let comment = ok(s
+        .v1(Method::POST, "comment/create")
+        .json(&json!({ "uid": AARON, "list": list, "task": task, "body": "LGTM" }))
+        .send()
+        .await)
+    .await;
+    let attachment = ok(s
+        .v1(Method::POST, "attachment/create")
+        .json(&json!({
+            "uid": AARON, "list": list, "task": task,
+            "name": "notes.txt", "content_type": "text/plain", "data": "aGVsbG8=",
+        }))
+        .send()
+        .await)
+    .await;
+
+    // Once the list is no longer shared with them, authors may not change what they wrote
+    let unshare = json!({ "uid": KESHA, "list": list, "unshare_with": AARON, "role": "Reader" });
+    ok(s.v1(Method::DELETE, "share").json(&unshare).send().await).await;
+    let edit = json!({ "uid": AARON, "list": list, "task": task, "comment": comment, "body": "!" });
+    let delete_comment = json!({ "uid": AARON, "list": list, "task": task, "comment": comment });
+    let delete_attachment =
+        json!({ "uid": AARON, "list": list, "task": task, "attachment": attachment });
+    for (method, path, body) in [
+        (Method::POST, "comment/update", &edit),
+        (Method::DELETE, "comment/delete", &delete_comment),
+        (Method::DELETE, "attachment/delete", &delete_attachment),
+    ] {
+        let err = error(s.v1(method, path).json(body).send().await).await;
+        assert_eq!(err, "Authorization Denied", "{path}");
+    }
+
+    // The list's owner still may
+    let delete = json!({ "uid": KESHA, "list": list, "task": task, "comment": comment });
+    ok(s.v1(Method::DELETE, "comment/delete")
+        .json(&delete)
+        .send()
+        .await)
+    .await;
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn state_survives_a_restart() {
+    let state = tempfile::tempdir().unwrap();
+    let persist = |config: &mut Config| config.persistence_dir = Some(state.path().into());
+    let s = TestServer::start_with(FILES, persist).await;
+    let (code, list) = status(
+        s.v2(Method::POST, "lists", KESHA)
+            .json(&json!({ "name": "Cedar blog" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    let id = list_id(list.as_str().unwrap()).to_string();
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+            .json(&json!({ "share_with": AARON, "role": "Reader" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    s.stop().await;
+
+    // A server started from the same directory has the list, and it is still shared
+    let s = TestServer::start_with(FILES, persist).await;
+    for user in [KESHA, AARON] {
+        let (code, got) =
+            status(s.v2(Method::GET, &format!("lists/{id}"), user).send().await).await;
+        assert_eq!(code, StatusCode::OK, "{user}");
+        assert_eq!(got["name"], "Cedar blog");
+    }
+    let (code, _) = status(
+        s.v2(Method::GET, &format!("lists/{id}"), EMINA)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::FORBIDDEN);
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn events() {
+    let s = TestServer::start().await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Groceries" }))
+        .send()
+        .await)
+    .await;
+    let list = list.as_str().unwrap().to_string();
+    let id = list_id(&list).to_string();
+
+    // Subscribe over v1 and v2, then make a change each should see
+    let mut v1 = s
+        .v1(Method::GET, "list/events")
+        .query(&[("uid", KESHA), ("list", &list)])
+        .send()
+        .await
+        .unwrap();
+    let mut v2 = s
+        .v2(Method::GET, &format!("lists/{id}/events"), KESHA)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(v1.status(), StatusCode::OK);
+    assert_eq!(v2.status(), StatusCode::OK);
+    ok(s.v1(Method::POST, "task/create")
+        .json(&json!({ "uid": KESHA, "list": list, "name": "Milk" }))
+        .send()
+        .await)
+    .await;
+    for stream in [&mut v1, &mut v2] {
+        let mut received = String::new();
+        while !received.contains("event:task_created") {
+            let chunk = tokio::time::timeout(Duration::from_secs(10), stream.chunk())
+                .await
+                .expect("timed out waiting for an event")
+                .unwrap()
+                .expect("the event stream ended");
+            received.push_str(&String::from_utf8_lossy(&chunk));
+        }
+    }
+
+    // A user who may not read the list may not subscribe to it
+    let (code, _) = status(
+        s.v2(Method::GET, &format!("lists/{id}/events"), AARON)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::FORBIDDEN);
+
+    drop((v1, v2));
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn probes_and_metrics() {
+    let s = TestServer::start().await;
+
+    let (code, health) = status(s.get("/healthz").send().await).await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(health, json!({ "status": "ok" }));
+
+    let (code, ready) = status(s.get("/readyz").send().await).await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(ready["policy_reload_error"], Value::Null);
+    let templates = if cfg!(feature = "use-templates") {
+        2
+    } else {
+        0
+    };
+    assert_eq!(ready["templates"], templates);
+
+    ok(s.v1(Method::GET, "lists/get")
+        .query(&[("uid", KESHA)])
+        .send()
+        .await)
+    .await;
+    let metrics = s
+        .get("/metrics")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(metrics.contains(
+        r#"tinytodo_http_requests_total{method="GET",route="/api/lists/get",status="200"}"#
+    ));
+    assert!(metrics.contains("tinytodo_authorization_decisions_total"));
+
+    s.stop().await;
+}
+
+// The value of `series` in the Prometheus text `metrics`, or 0 if it has not been recorded
+fn metric(metrics: &str, series: &str) -> u64 {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[tokio::test]
+async fn decision_metrics() {
+    let s = TestServer::start().await;
+    let server = &s;
+    let metrics = || async move {
+        let resp = server.get("/metrics").send().await.unwrap();
+        resp.text().await.unwrap()
+    };
+    let decisions = |action: &str, decision: &str| {
+        format!(
+            r#"tinytodo_authorization_decisions_total{{action="Action::\"{action}\"",decision="{decision}"}}"#
+        )
+    };
+    let created = decisions("CreateList", "allow");
+    let refused = decisions("GetList", "deny");
+    let before = metrics().await;
+
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let id = list_id(list.as_str().unwrap());
+    let (code, _) = status(
+        s.v2(Method::GET, &format!("lists/{id}"), AARON)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::FORBIDDEN);
+
+    // Other tests share the counters, so they may have grown by more
+    let after = metrics().await;
+    assert!(
+        metric(&after, &created) > metric(&before, &created),
+        "{after}"
+    );
+    assert!(
+        metric(&after, &refused) > metric(&before, &refused),
+        "{after}"
+    );
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn decision_cache() {
+    let s = TestServer::start_with(FILES, |config| {
+        config.decision_cache = Some(CacheConfig::default());
+    })
+    .await;
+    let server = &s;
+    let hits = || async move {
+        let resp = server.get("/metrics").send().await.unwrap();
+        let metrics = resp.text().await.unwrap();
+        metric(&metrics, r#"tinytodo_decision_cache_total{result="hit"}"#)
+    };
+    let (code, list) = status(
+        s.v2(Method::POST, "lists", KESHA)
+            .json(&json!({ "name": "Cedar blog" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    let id = list_id(list.as_str().unwrap()).to_string();
+    let id = id.as_str();
+    let get = || async move {
+        let resp = server
+            .v2(Method::GET, &format!("lists/{id}"), AARON)
+            .send()
+            .await;
+        status(resp).await.0
+    };
+
+    // The second request is answered from the cache
+    assert_eq!(get().await, StatusCode::FORBIDDEN);
+    let before = hits().await;
+    assert_eq!(get().await, StatusCode::FORBIDDEN);
+    assert!(hits().await > before);
+
+    // Sharing the list, and unsharing it, changes the decision rather than leaving the
+    // cached one in place
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+            .json(&json!({ "share_with": AARON, "role": "Reader" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    assert_eq!(get().await, StatusCode::OK);
+    assert_eq!(get().await, StatusCode::OK);
+    let (code, _) = status(
+        s.v2(Method::DELETE, &format!("lists/{id}/shares"), KESHA)
+            .query(&[("target", AARON), ("role", "Reader")])
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::NO_CONTENT);
+    assert_eq!(get().await, StatusCode::FORBIDDEN);
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn spans_continue_the_callers_trace() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("spans.jsonl");
+    let provider =
+        telemetry::tracer_provider(&TelemetryConfig::File { path: path.clone() }).unwrap();
+    // The test runtime runs the server on this thread, so its spans go to this subscriber
+    let subscriber = tracing_subscriber::registry().with(telemetry::layer(&provider));
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let s = TestServer::start().await;
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    let (code, _) = status(
+        s.v2(Method::GET, "lists", KESHA)
+            .header("traceparent", format!("00-{trace_id}-00f067aa0ba902b7-01"))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::OK);
+    s.stop().await;
+    provider.shutdown().unwrap();
+
+    let spans = std::fs::read_to_string(&path).unwrap();
+    let traced = spans
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter(|span| span["trace_id"] == trace_id)
+        .map(|span| span["name"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    for name in [
+        "query",
+        "handle_query",
+        "authorize",
+        "as_entities",
+        "evaluate",
+    ] {
+        assert!(traced.iter().any(|n| n == name), "no {name} in {traced:?}");
+    }
+}
+
+#[tokio::test]
+async fn tracing_without_a_collector() {
+    // Nothing listens at the endpoint, so every export fails, but the server carries on
+    let endpoint = format!("http://127.0.0.1:{}/v1/traces", free_port());
+    let provider = telemetry::tracer_provider(&TelemetryConfig::Otlp { endpoint }).unwrap();
+    let subscriber = tracing_subscriber::registry().with(telemetry::layer(&provider));
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let s = TestServer::start().await;
+    let (code, _) = status(s.v2(Method::GET, "lists", KESHA).send().await).await;
+    assert_eq!(code, StatusCode::OK);
+    s.stop().await;
+    // Flushing returns, whether or not it reports the failed exports
+    let _ = tokio::task::spawn_blocking(move || provider.shutdown())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn per_ip_limit_over_https() {
+    // tests/tls holds a self-signed certificate for `localhost`
+    let tls = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/tls");
+    let s = TestServer::start_with(FILES, |config| {
+        config.tls = Some(TlsConfig {
+            cert: tls.join("cert.pem"),
+            key: tls.join("key.pem"),
+            ..TlsConfig::default()
+        });
+        config.limits.per_ip = Some(Bucket {
+            burst: 3,
+            per_second: 0.01,
+        });
+    })
+    .await;
+
+    let mut codes = Vec::new();
+    for _ in 0..4 {
+        let resp = s.v2(Method::GET, "lists", KESHA).send().await.unwrap();
+        codes.push(resp.status());
+    }
+    assert_eq!(
+        codes,
+        [
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::TOO_MANY_REQUESTS
+        ]
+    );
+    // The probes are not rate limited
+    let (code, _) = status(s.get("/healthz").send().await).await;
+    assert_eq!(code, StatusCode::OK);
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn quotas_and_principal_limits() {
+    let s = TestServer::start_with(FILES, |config| {
+        config.limits.max_lists_per_user = Some(1);
+        config.limits.max_tasks_per_list = Some(1);
+        config.limits.actions = [(
+            "CreateTask".to_string(),
+            Bucket {
+                burst: 2,
+                per_second: 0.01,
+            },
+        )]
+        .into();
+    })
+    .await;
+    let server = &s;
+    let post = |path: String, user: &'static str, body: Value| async move {
+        let resp = server
+            .v2(Method::POST, &path, user)
+            .json(&body)
+            .send()
+            .await;
+        status(resp).await
+    };
+    let create_list = |user| async move {
+        let (code, list) = post("lists".to_string(), user, json!({ "name": "Chores" })).await;
+        (code, list.as_str().map(|list| list_id(list).to_string()))
+    };
+    let create_task = |id: String, user| async move {
+        post(
+            format!("lists/{id}/tasks"),
+            user,
+            json!({ "name": "Sweep" }),
+        )
+        .await
+        .0
+    };
+
+    // Each user may own one list, and each list may hold one task
+    let (code, kesha) = create_list(KESHA).await;
+    assert_eq!(code, StatusCode::CREATED);
+    let kesha = kesha.unwrap();
+    assert_eq!(create_list(KESHA).await.0, StatusCode::FORBIDDEN);
+    let (code, aaron) = create_list(AARON).await;
+    assert_eq!(code, StatusCode::CREATED);
+    let aaron = aaron.unwrap();
+    assert_eq!(create_task(kesha.clone(), KESHA).await, StatusCode::CREATED);
+    assert_eq!(
+        create_task(kesha.clone(), KESHA).await,
+        StatusCode::FORBIDDEN
+    );
+
+    // Each user has their own bucket for each action, which refused requests still drain
+    assert_eq!(
+        create_task(kesha.clone(), KESHA).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(create_task(aaron, AARON).await, StatusCode::CREATED);
+    let (code, _) = status(
+        s.v2(Method::GET, &format!("lists/{kesha}"), KESHA)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::OK);
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn attachment_bodies_are_limited() {
+    let s = TestServer::start_with(FILES, |config| {
+        config.limits.max_attachment_bytes = Some(16);
+    })
+    .await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let id = list_id(list.as_str().unwrap());
+    let (_, task) = status(
+        s.v2(Method::POST, &format!("lists/{id}/tasks"), KESHA)
+            .json(&json!({ "name": "Draft" }))
+            .send()
+            .await,
+    )
+    .await;
+    let attachments = format!("lists/{id}/tasks/{task}/attachments");
+    let attach = |data: String| {
+        s.v2(Method::POST, &attachments, KESHA)
+            .json(&json!({ "name": "notes.txt", "content_type": "text/plain", "data": data }))
+            .send()
+    };
+    let (code, _) = status(attach(BASE64_STANDARD.encode([0u8; 16])).await).await;
+    assert_eq!(code, StatusCode::CREATED);
+    // A body too long to hold such a file is refused before it is read, so even data that
+    // is not base64 is refused for its size
+    let (code, _) = status(attach("!".repeat(8192)).await).await;
+    assert_eq!(code, StatusCode::PAYLOAD_TOO_LARGE);
+
+    s.stop().await;
+}
+
+// A client of the HTTPS server at `bind`, whose certificate is tests/tls/cert.pem, presenting
+// `identity`, a certificate and its key in tests/tls, if given
+fn tls_client(bind: SocketAddr, identity: Option<&str>) -> reqwest::Client {
+    let tls = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/tls");
+    let cert = std::fs::read(tls.join("cert.pem")).unwrap();
+    let mut builder = reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(&cert).unwrap())
+        .resolve("localhost", bind);
+    if let Some(identity) = identity {
+        let pem = std::fs::read(tls.join(identity)).unwrap();
+        builder = builder.identity(reqwest::Identity::from_pem(&pem).unwrap());
+    }
+    builder.build().unwrap()
+}
+
+#[tokio::test]
+async fn client_certificates() {
+    // tests/tls/client-ca.pem signed the client certificates for `CN=billing` and
+    // `CN=stranger`, each in a file with its key
+    let tls = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/tls");
+    for require_client_cert in [false, true] {
+        let mut bind = None;
+        let s = TestServer::start_with(FILES, |config| {
+            let kesha = KESHA.parse::<util::EntityUid>().unwrap();
+            config.tls = Some(TlsConfig {
+                cert: tls.join("cert.pem"),
+                key: tls.join("key.pem"),
+                client_ca: Some(tls.join("client-ca.pem")),
+                require_client_cert,
+                client_principals: [("billing".to_string(), kesha.try_into().unwrap())].into(),
+            });
+            bind = Some(config.bind);
+        })
+        .await;
+        let bind = bind.unwrap();
+        let lists = |client: reqwest::Client, user: Option<&'static str>| {
+            let mut req = client.get(format!("https://localhost:{}/api/v2/lists", bind.port()));
+            if let Some(user) = user {
+                req = req.header("x-tinytodo-user", user);
+            }
+            async move { req.send().await.map(|resp| resp.status()) }
+        };
+
+        // A mapped certificate acts as its user, and only as them
+        let billing = tls_client(bind, Some("billing.pem"));
+        assert_eq!(lists(billing.clone(), None).await.unwrap(), StatusCode::OK);
+        assert_eq!(
+            lists(billing.clone(), Some(KESHA)).await.unwrap(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            lists(billing, Some(AARON)).await.unwrap(),
+            StatusCode::FORBIDDEN
+        );
+
+        // An unmapped certificate names its user in the header, as a client without one does
+        let stranger = tls_client(bind, Some("stranger.pem"));
+        assert_eq!(
+            lists(stranger.clone(), Some(AARON)).await.unwrap(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            lists(stranger, None).await.unwrap(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Without a certificate, the handshake fails if one is required
+        let anonymous = lists(tls_client(bind, None), Some(AARON)).await;
+        if require_client_cert {
+            assert!(anonymous.is_err(), "{anonymous:?}");
+        } else {
+            assert_eq!(anonymous.unwrap(), StatusCode::OK);
+        }
+
+        s.stop().await;
+    }
+}
+
+// The log output of a test, for checking what reaches the audit log
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn audit_redaction() {
+    // The test runtime runs the server on this thread, so it logs to this subscriber
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    // Both policies fail to evaluate, and the error of the one reading `joblevel` may
+    // quote Kesha's job level
+    let failing = r#"
+@id("overflowing")
+permit (principal, action == Action::"GetLists", resource)
+when { principal.joblevel * 4611686018427387904 > 0 };
+@id("unrelated")
+permit (principal, action == Action::"GetLists", resource)
+when { 4611686018427387904 * 4 > 0 };
+"#;
+    let s = TestServer::start_with(FILES, |config| {
+        config.audit.redact = vec!["location".to_string(), "joblevel".to_string()];
+        let original = std::fs::read_to_string(&config.policies).unwrap();
+        std::fs::write(&config.policies, format!("{original}{failing}")).unwrap();
+    })
+    .await;
+    let (code, _) = status(s.v2(Method::GET, "lists", KESHA).send().await).await;
+    assert_eq!(code, StatusCode::OK);
+    s.stop().await;
+
+    let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+    let decisions = output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|event| event["fields"]["message"] == "Authorization decision")
+        .filter(|event| event["fields"]["principal"] == KESHA)
+        .collect::<Vec<_>>();
+    assert!(!decisions.is_empty(), "{output}");
+    for event in &decisions {
+        let attributes = event["fields"]["principal_attributes"].as_str().unwrap();
+        let attributes: Value = serde_json::from_str(attributes).unwrap();
+        assert_eq!(attributes["location"], "[REDACTED]");
+        assert_eq!(attributes["joblevel"], "[REDACTED]");
+        let event = event.to_string();
+        assert!(!event.contains("ABC17"), "{event}");
+    }
+    let errors = decisions
+        .iter()
+        .filter_map(|event| event["fields"]["errors"].as_str())
+        .find(|errors| errors.contains("overflowing"))
+        .expect("the failing policies to be logged");
+    assert!(errors.contains("error evaluating policy overflowing: [REDACTED]"));
+    assert!(errors.contains("unrelated"), "{errors}");
+    assert!(!errors.contains("unrelated: [REDACTED]"), "{errors}");
+    assert!(!errors.contains("`5`"), "{errors}");
+}
+
+#[tokio::test]
+async fn policy_hot_reload() {
+    let s = TestServer::start().await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Secrets" }))
+        .send()
+        .await)
+    .await;
+    let id = list_id(list.as_str().unwrap());
+    let server = &s;
+    let get_as_emina = || async move {
+        let resp = server
+            .v2(Method::GET, &format!("lists/{id}"), EMINA)
+            .send()
+            .await;
+        status(resp).await.0
+    };
+    let readiness = || async move { status(server.get("/readyz").send().await).await };
+    assert_eq!(get_as_emina().await, StatusCode::FORBIDDEN);
+    let (_, ready) = readiness().await;
+    let policies = ready["policies"].as_u64().unwrap();
+
+    // Emina is an admin, so the admin policy lets her read the list
+    let original = std::fs::read_to_string(&s.policies).unwrap();
+    std::fs::write(&s.policies, format!("{original}\n{ADMIN_POLICY}")).unwrap();
+    eventually("the admin policy to be loaded", || async move {
+        get_as_emina().await == StatusCode::OK
+    })
+    .await;
+    let (code, ready) = readiness().await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(ready["policies"].as_u64().unwrap(), policies + 1);
+
+    // A broken policies file is reported by `/readyz`, and the loaded policies stay in effect
+    std::fs::write(&s.policies, "permit (").unwrap();
+    eventually("the reload failure to be reported", || async move {
+        readiness().await.0 == StatusCode::SERVICE_UNAVAILABLE
+    })
+    .await;
+    assert_eq!(get_as_emina().await, StatusCode::OK);
+
+    // Restoring the original policies revokes Emina's access again
+    std::fs::write(&s.policies, original).unwrap();
+    eventually("the original policies to be loaded", || async move {
+        readiness().await.0 == StatusCode::OK
+    })
+    .await;
+    assert_eq!(get_as_emina().await, StatusCode::FORBIDDEN);
+
+    s.stop().await;
+}
+
+// With templates, a policies file must define the built-in share roles' templates
+#[cfg(not(feature = "use-templates"))]
+const ROLE_TEMPLATES: &str = "";
+#[cfg(feature = "use-templates")]
+const ROLE_TEMPLATES: &str = r#"
+@id("reader-template")
+permit (principal in ?principal, action == Action::"GetList", resource == ?resource);
+@id("editor-template")
+permit (principal in ?principal, action == Action::"UpdateList", resource == ?resource);
+"#;
+
+// Lint `policies` with the role templates as the policies file, refusing any warnings
+fn lint_policies(
+    dir: &Path,
+    policies: &str,
+    configure: impl FnOnce(&mut Config),
+) -> Result<(), ContextError> {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let path = dir.join("policies.cedar");
+    std::fs::write(&path, format!("{policies}{ROLE_TEMPLATES}")).unwrap();
+    let mut config = Config {
+        entities: manifest.join("entities.json"),
+        schema: manifest.join(FILES.0),
+        policies: path,
+        lint: LintConfig {
+            deny_warnings: true,
+        },
+        ..Config::default()
+    };
+    configure(&mut config);
+    AppContext::spawn(&config).map(|_| ())
+}
+
+fn warnings(result: Result<(), ContextError>) -> Vec<(String, usize, WarningKind)> {
+    match result {
+        Err(ContextError::Lint(Warnings(warnings))) => warnings
+            .into_iter()
+            .map(|w| (w.policy.to_string(), w.location.unwrap().start.line, w.kind))
+            .collect(),
+        r => panic!("expected lint warnings, got {r:?}"),
+    }
+}
+
+#[tokio::test]
+async fn policy_lint() {
+    // The shipped policies, with every profile, have nothing to warn about
+    let profiles = [
+        PolicyProfile::Admin,
+        PolicyProfile::Interns,
+        PolicyProfile::Location,
+    ];
+    for (files, task_entities) in [(FILES, false), (TASK_FILES, true)] {
+        let s = TestServer::start_with(files, |config| {
+            config.task_entities = task_entities;
+            config.profiles = profiles.into_iter().collect();
+            config.lint.deny_warnings = true;
+        })
+        .await;
+        s.stop().await;
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let policies = r#"@id("admins-read")
+permit (
+    principal in Team::"admin",
+    action in [Action::"GetList", Action::"UpdateList"],
+    resource is List
+);
+@id("admins-get")
+permit (principal in Team::"admin", action == Action::"GetList", resource is List);
+@id("never")
+permit (principal, action == Action::"GetList", resource is List) when { false };
+@id("no-new-lists")
+forbid (principal, action == Action::"CreateList", resource == Application::"TinyTodo");
+@id("unused-template")
+permit (principal == ?principal, action == Action::"GetList", resource == ?resource);
+"#;
+    let result = lint_policies(dir.path(), policies, |_| ());
+    let message = result.as_ref().unwrap_err().to_string();
+    let path = dir.path().join("policies.cedar");
+    let redundant = "policy admins-get: it is redundant: admins-read permits every request it does";
+    assert!(message.contains(&format!("{}:7:1: {redundant}", path.display())));
+    assert_eq!(
+        warnings(result),
+        vec![
+            (
+                "admins-get".to_string(),
+                7,
+                WarningKind::Shadowed(PolicyId::new("admins-read"))
+            ),
+            (
+                "never".to_string(),
+                9,
+                WarningKind::Unreachable("a `when` condition is `false`".to_string())
+            ),
+            (
+                "no-new-lists".to_string(),
+                11,
+                WarningKind::IneffectiveForbid
+            ),
+            (
+                "unused-template".to_string(),
+                13,
+                WarningKind::UnlinkedTemplate
+            ),
+        ]
+    );
+
+    // Colliding ids stop the policies from loading, with or without `deny_warnings`
+    let policies = r#"@id("mine")
+permit (principal, action == Action::"GetList", resource is List)
+when { resource.owner == principal };
+@id("mine")
+permit (principal, action == Action::"UpdateList", resource is List)
+when { resource.owner == principal };
+@id("profile-admin")
+permit (principal, action == Action::"GetLists", resource == Application::"TinyTodo");
+"#;
+    let collisions = warnings(lint_policies(dir.path(), policies, |config| {
+        config.profiles = [PolicyProfile::Admin].into_iter().collect();
+        config.lint.deny_warnings = false;
+    }));
+    let with_mine = format!("the policy at {}:1:1", path.display());
+    let with_profile = "a policy the server adds for a share role or profile".to_string();
+    assert_eq!(
+        collisions,
+        vec![
+            ("mine".to_string(), 4, WarningKind::IdCollision(with_mine)),
+            (
+                "profile-admin".to_string(),
+                7,
+                WarningKind::IdCollision(with_profile)
+            ),
+        ]
+    );
+
+    // So do ids the server keeps for its own policies
+    let policies = r#"@id("expiring-share:mine")
+permit (principal, action == Action::"GetList", resource is List)
+when { resource.owner == principal };
+"#;
+    let reserved = warnings(lint_policies(dir.path(), policies, |config| {
+        config.lint.deny_warnings = false;
+    }));
+    assert_eq!(
+        reserved,
+        vec![(
+            "expiring-share:mine".to_string(),
+            1,
+            WarningKind::ReservedId(EXPIRING_SHARE_PREFIX)
+        )]
+    );
+}
+
+#[tokio::test]
+async fn policy_lint_on_reload() {
+    let s = TestServer::start_with(FILES, |config| config.lint.deny_warnings = true).await;
+    let server = &s;
+    let readiness = || async move { status(server.get("/readyz").send().await).await };
+
+    // A second copy of Policy 8 is redundant, so the reload is refused
+    let original = std::fs::read_to_string(&s.policies).unwrap();
+    let transfer = r#"
+@id("admins-transfer")
+permit (
+    principal in Team::"admin",
+    action == Action::"TransferOwnership",
+    resource is List
+);
+"#;
+    std::fs::write(&s.policies, format!("{original}\n{transfer}")).unwrap();
+    eventually("the lint failure to be reported", || async move {
+        readiness().await.0 == StatusCode::SERVICE_UNAVAILABLE
+    })
+    .await;
+    let (_, ready) = readiness().await;
+    let error = ready["policy_reload_error"].as_str().unwrap();
+    assert!(
+        error.contains("policy admins-transfer: it is redundant"),
+        "{error}"
+    );
+
+    std::fs::write(&s.policies, original).unwrap();
+    eventually("the original policies to be loaded", || async move {
+        readiness().await.0 == StatusCode::OK
+    })
+    .await;
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn task_entities() {
+    let s = TestServer::start_with(TASK_FILES, |config| config.task_entities = true).await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let id = list_id(list.as_str().unwrap()).to_string();
+    for name in ["Draft", "Publish"] {
+        let (code, _) = status(
+            s.v2(Method::POST, &format!("lists/{id}/tasks"), KESHA)
+                .json(&json!({ "name": name }))
+                .send()
+                .await,
+        )
+        .await;
+        assert_eq!(code, StatusCode::CREATED);
+    }
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+            .json(&json!({ "share_with": AARON, "role": "Editor" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+
+    let server = &s;
+    let id = id.as_str();
+    let update = |user: &'static str, task: i64| async move {
+        let resp = server
+            .v2(Method::PATCH, &format!("lists/{id}/tasks/{task}"), user)
+            .json(&json!({ "state": "Checked" }))
+            .send()
+            .await;
+        status(resp).await.0
+    };
+    // Sharing a list shares its tasks, which are authorized as entities of their own
+    assert_eq!(update(AARON, 0).await, StatusCode::OK);
+    assert_eq!(update(EMINA, 0).await, StatusCode::FORBIDDEN);
+    assert_eq!(update(AARON, 7).await, StatusCode::NOT_FOUND);
+    // Only those who may read the list learn which tasks it has
+    assert_eq!(update(EMINA, 7).await, StatusCode::FORBIDDEN);
+    let (code, _) = status(
+        s.v2(
+            Method::DELETE,
+            &format!("lists/{id}/tasks/7/comments/0"),
+            EMINA,
+        )
+        .send()
+        .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::FORBIDDEN);
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/tasks/1/comments"), AARON)
+            .json(&json!({ "body": "Ready when you are" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+
+    // Policy 10: once a task is assigned, other editors may not change it
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+            .json(&json!({ "share_with": EMINA, "role": "Editor" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    let (code, task) = status(
+        s.v2(Method::POST, &format!("lists/{id}/tasks"), KESHA)
+            .json(&json!({ "name": "Review", "assignee": AARON }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    let task = task.as_i64().unwrap();
+    assert_eq!(update(EMINA, task).await, StatusCode::FORBIDDEN);
+    assert_eq!(update(AARON, task).await, StatusCode::OK);
+    let (_, got) = status(
+        s.v2(Method::GET, &format!("lists/{id}"), EMINA)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(got["tasks"][task as usize]["assignee"], AARON);
+    let (code, _) = status(
+        s.v2(Method::PATCH, &format!("lists/{id}/tasks/{task}"), KESHA)
+            .json(&json!({ "assignee": null }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(update(EMINA, task).await, StatusCode::OK);
+
+    // A policy on single tasks: only the owner may change a task once it is checked off
+    let forbid = r#"
+forbid (
+    principal,
+    action in [Action::"UpdateTask", Action::"DeleteTask"],
+    resource is Task
+)
+when { resource.state == "checked" }
+unless { resource.list.owner == principal };
+"#;
+    let original = std::fs::read_to_string(&s.policies).unwrap();
+    std::fs::write(&s.policies, format!("{original}\n{forbid}")).unwrap();
+    eventually("the task policy to be loaded", || async move {
+        update(AARON, 0).await == StatusCode::FORBIDDEN
+    })
+    .await;
+    assert_eq!(update(AARON, 1).await, StatusCode::OK);
+    assert_eq!(update(KESHA, 0).await, StatusCode::OK);
+
+    let (code, got) = status(
+        s.v2(Method::GET, &format!("lists/{id}"), AARON)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(got["tasks"][1]["comments"][0]["author"], AARON);
+
+    s.stop().await;
+}
+
+// Were a deleted task's id reused, two tasks would be the same entity
+#[tokio::test]
+async fn task_ids_are_not_reused() {
+    let s = TestServer::start_with(TASK_FILES, |config| config.task_entities = true).await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let id = list_id(list.as_str().unwrap()).to_string();
+    let server = &s;
+    let id = id.as_str();
+    let create = |name: &'static str| async move {
+        let resp = server
+            .v2(Method::POST, &format!("lists/{id}/tasks"), KESHA)
+            .json(&json!({ "name": name }))
+            .send()
+            .await;
+        let (code, task) = status(resp).await;
+        assert_eq!(code, StatusCode::CREATED, "{task}");
+        task.as_i64().unwrap()
+    };
+    assert_eq!(create("Draft").await, 0);
+    assert_eq!(create("Review").await, 1);
+    let (code, _) = status(
+        s.v2(Method::DELETE, &format!("lists/{id}/tasks/0"), KESHA)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::NO_CONTENT);
+    assert_eq!(create("Publish").await, 2);
+
+    // The server still answers, with both tasks
+    let (code, _) = status(
+        s.v2(Method::PATCH, &format!("lists/{id}/tasks/2"), KESHA)
+            .json(&json!({ "state": "Checked" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::OK);
+    let (code, got) = status(
+        s.v2(Method::GET, &format!("lists/{id}"), KESHA)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::OK);
+    let ids: Vec<_> = got["tasks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|task| task["id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(ids, [1, 2]);
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn expiring_shares() {
+    let s = TestServer::start().await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let id = list_id(list.as_str().unwrap()).to_string();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    // A share cannot end before it starts
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+            .json(&json!({ "share_with": AARON, "role": "Reader", "expires": now - 1 }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::BAD_REQUEST);
+
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+            .json(&json!({ "share_with": AARON, "role": "Reader", "expires": now + 3 }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    let server = &s;
+    let id = id.as_str();
+    let get = |user: &'static str| async move {
+        let resp = server
+            .v2(Method::GET, &format!("lists/{id}"), user)
+            .send()
+            .await;
+        status(resp).await.0
+    };
+    assert_eq!(get(AARON).await, StatusCode::OK);
+
+    // The sweeper revokes the share once it expires
+    eventually("the share to expire", || async move {
+        get(AARON).await == StatusCode::FORBIDDEN
+    })
+    .await;
+    assert_eq!(get(KESHA).await, StatusCode::OK);
+
+    s.stop().await;
+}
+
+// Only the server's own policies are expiring shares, whatever a policy is annotated with
+#[tokio::test]
+async fn annotated_policies_are_not_shares() {
+    let annotated = r#"
+@id("aaron-reads")
+@share_list("List::\"0\"")
+@share_target("User::\"aaron\"")
+@share_role("Reader")
+@share_expires("1")
+permit (principal == User::"aaron", action == Action::"GetList", resource is List);
+"#;
+    let s = TestServer::start_with(FILES, |config| {
+        let original = std::fs::read_to_string(&config.policies).unwrap();
+        std::fs::write(&config.policies, format!("{original}{annotated}")).unwrap();
+    })
+    .await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let id = list_id(list.as_str().unwrap());
+    let (code, _) = status(
+        s.v2(Method::GET, &format!("lists/{id}"), AARON)
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::OK);
+    s.stop().await;
+}
+
+// A snapshot holds everything a server needs to carry on where the exported one left off
+#[tokio::test]
+async fn snapshot_round_trip() {
+    let state = tempfile::tempdir().unwrap();
+    let archive = state.path().join("snapshot.tar");
+    let mut exported = None;
+    let s = TestServer::start_with(FILES, |config| {
+        config.persistence_dir = Some(state.path().join("exported"));
+        exported = Some(config.clone());
+    })
+    .await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let id = list_id(list.as_str().unwrap()).to_string();
+    let server = &s;
+    let id = id.as_str();
+    let create = |path: &'static str, body: Value| async move {
+        let resp = server
+            .v2(Method::POST, &format!("lists/{id}/{path}"), KESHA)
+            .json(&body)
+            .send()
+            .await;
+        let (code, created) = status(resp).await;
+        assert_eq!(code, StatusCode::CREATED, "{path}: {created}");
+        created.as_i64().unwrap()
+    };
+    let delete = |path: &'static str| async move {
+        let resp = server
+            .v2(Method::DELETE, &format!("lists/{id}/{path}"), KESHA)
+            .send()
+            .await;
+        assert_eq!(status(resp).await.0, StatusCode::NO_CONTENT, "{path}");
+    };
+    let attachment =
+        json!({ "name": "notes.txt", "content_type": "text/plain", "data": "aGVsbG8=" });
+    assert_eq!(create("tasks", json!({ "name": "Draft" })).await, 0);
+    assert_eq!(create("tasks", json!({ "name": "Review" })).await, 1);
+    delete("tasks/1").await;
+    assert_eq!(
+        create("tasks/0/comments", json!({ "body": "LGTM" })).await,
+        0
+    );
+    delete("tasks/0/comments/0").await;
+    assert_eq!(create("tasks/0/attachments", attachment.clone()).await, 0);
+    assert_eq!(create("tasks/0/attachments", attachment.clone()).await, 1);
+    delete("tasks/0/attachments/1").await;
+    let expires = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 6;
+    let share = json!({ "share_with": AARON, "role": "Reader", "expires": expires });
+    assert_eq!(
+        status(
+            s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+                .json(&share)
+                .send()
+                .await
+        )
+        .await
+        .0,
+        StatusCode::CREATED
+    );
+    s.stop().await;
+    snapshot::export(&exported.unwrap(), &archive).unwrap();
+
+    let s = TestServer::start_with(FILES, |config| {
+        config.persistence_dir = Some(state.path().join("imported"));
+        snapshot::import(config, &archive).unwrap();
+    })
+    .await;
+    let server = &s;
+    let create = |path: &'static str, body: Value| async move {
+        let resp = server
+            .v2(Method::POST, &format!("lists/{id}/{path}"), KESHA)
+            .json(&body)
+            .send()
+            .await;
+        let (code, created) = status(resp).await;
+        assert_eq!(code, StatusCode::CREATED, "{path}: {created}");
+        created.as_i64().unwrap()
+    };
+    let get = |user: &'static str| async move {
+        let resp = server
+            .v2(Method::GET, &format!("lists/{id}"), user)
+            .send()
+            .await;
+        status(resp).await
+    };
+
+    // The attachments keep their contents
+    let (code, got) = get(KESHA).await;
+    assert_eq!(code, StatusCode::OK);
+    let attachments = got["tasks"][0]["attachments"].as_array().unwrap();
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(attachments[0]["content_type"], "text/plain");
+    assert_eq!(attachments[0]["data"], "aGVsbG8=");
+
+    // The ids of deleted tasks, comments and attachments are still not reused
+    assert_eq!(create("tasks", json!({ "name": "Publish" })).await, 2);
+    assert_eq!(
+        create("tasks/0/comments", json!({ "body": "LGTM" })).await,
+        1
+    );
+    assert_eq!(create("tasks/0/attachments", attachment).await, 2);
+
+    // The share still expires
+    assert_eq!(get(AARON).await.0, StatusCode::OK);
+    eventually("the share to expire", || async move {
+        get(AARON).await.0 == StatusCode::FORBIDDEN
+    })
+    .await;
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn configured_roles() {
+    let s = TestServer::start_with(FILES, |config| {
+        let commenter = if cfg!(feature = "use-templates") {
+            let template = r#"
+@id("commenter-template")
+permit (
+    principal in ?principal,
+    action in
+        [Action::"GetList",
+         Action::"CommentOnTask",
+         Action::"EditComment",
+         Action::"DeleteComment"],
+    resource == ?resource
+)
+unless {
+    action in [Action::"EditComment", Action::"DeleteComment"] &&
+    context has author &&
+    context.author != principal
+};
+"#;
+            let original = std::fs::read_to_string(&config.policies).unwrap();
+            std::fs::write(&config.policies, format!("{original}\n{template}")).unwrap();
+            RoleConfig {
+                template: Some("commenter-template".to_string()),
+                ..RoleConfig::default()
+            }
+        } else {
+            RoleConfig {
+                actions: vec!["GetList".to_string(), "CommentOnTask".to_string()],
+                ..RoleConfig::default()
+            }
+        };
+        config.roles = [(ShareRole::new("Commenter"), commenter)]
+            .into_iter()
+            .collect();
+    })
+    .await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let id = list_id(list.as_str().unwrap()).to_string();
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/tasks"), KESHA)
+            .json(&json!({ "name": "Draft" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+            .json(&json!({ "share_with": AARON, "role": "Janitor" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::BAD_REQUEST);
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+            .json(&json!({ "share_with": AARON, "role": "Commenter" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+
+    // A commenter may read the list and comment on its tasks, but not change them
+    let server = &s;
+    let id = id.as_str();
+    let get = || async move {
+        let resp = server
+            .v2(Method::GET, &format!("lists/{id}"), AARON)
+            .send()
+            .await;
+        status(resp).await.0
+    };
+    assert_eq!(get().await, StatusCode::OK);
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/tasks/0/comments"), AARON)
+            .json(&json!({ "body": "Looks good" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    let (code, _) = status(
+        s.v2(Method::PATCH, &format!("lists/{id}/tasks/0"), AARON)
+            .json(&json!({ "state": "Checked" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::FORBIDDEN);
+
+    // As readers and editors may, a commenter may edit their own comments, and only theirs
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/tasks/0/comments"), KESHA)
+            .json(&json!({ "body": "Thanks" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    let edit = |comment: i64| async move {
+        let resp = server
+            .v2(
+                Method::PATCH,
+                &format!("lists/{id}/tasks/0/comments/{comment}"),
+                AARON,
+            )
+            .json(&json!({ "body": "Looks great" }))
+            .send()
+            .await;
+        status(resp).await.0
+    };
+    assert_eq!(edit(0).await, StatusCode::OK);
+    assert_eq!(edit(1).await, StatusCode::FORBIDDEN);
+
+    let (code, _) = status(
+        s.v2(Method::DELETE, &format!("lists/{id}/shares"), KESHA)
+            .query(&[("target", AARON), ("role", "Commenter")])
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::NO_CONTENT);
+    assert_eq!(get().await, StatusCode::FORBIDDEN);
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn transfer_and_duplicate() {
+    let s = TestServer::start().await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let id = list_id(list.as_str().unwrap()).to_string();
+    for name in ["Draft", "Review"] {
+        let (code, _) = status(
+            s.v2(Method::POST, &format!("lists/{id}/tasks"), KESHA)
+                .json(&json!({ "name": name }))
+                .send()
+                .await,
+        )
+        .await;
+        assert_eq!(code, StatusCode::CREATED);
+    }
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+            .json(&json!({ "share_with": AARON, "role": "Reader" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    let server = &s;
+    let get = |id: String, user: &'static str| async move {
+        let resp = server
+            .v2(Method::GET, &format!("lists/{id}"), user)
+            .send()
+            .await;
+        status(resp).await
+    };
+
+    // A reader may duplicate the list; the copy has its tasks, but none of its shares
+    let (code, copy) = status(
+        s.v2(Method::POST, &format!("lists/{id}/duplicate"), AARON)
+            .json(&json!({ "name": "My copy" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+    let copy = list_id(copy.as_str().unwrap()).to_string();
+    let (code, body) = get(copy.clone(), AARON).await;
+    assert_eq!(code, StatusCode::OK);
+    assert_eq!(body["owner"], json!(AARON));
+    assert_eq!(body["name"], json!("My copy"));
+    let names: Vec<_> = body["tasks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|task| task["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["Draft", "Review"]);
+    assert_eq!(get(copy.clone(), KESHA).await.0, StatusCode::FORBIDDEN);
+
+    // Only the owner or an admin may transfer a list
+    let transfer = |id: String, user: &'static str, owner: &'static str| async move {
+        let resp = server
+            .v2(Method::PUT, &format!("lists/{id}/owner"), user)
+            .json(&json!({ "owner": owner }))
+            .send()
+            .await;
+        status(resp).await.0
+    };
+    assert_eq!(
+        transfer(id.clone(), AARON, AARON).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(transfer(id.clone(), KESHA, EMINA).await, StatusCode::OK);
+    assert_eq!(get(id.clone(), KESHA).await.0, StatusCode::FORBIDDEN);
+    assert_eq!(get(id.clone(), EMINA).await.0, StatusCode::OK);
+    assert_eq!(get(id.clone(), AARON).await.0, StatusCode::OK);
+    assert_eq!(transfer(copy.clone(), ANDREW, KESHA).await, StatusCode::OK);
+    assert_eq!(get(copy, KESHA).await.0, StatusCode::OK);
+
+    s.stop().await;
+}
+
+/// A route as (method, path, query, body)
+type Route = (
+    Method,
+    &'static str,
+    Vec<(&'static str, &'static str)>,
+    Value,
+);
+
+/// The v2 routes, other than the events stream (which is authorized as `GetList`)
+fn routes(user: &'static str) -> Vec<Route> {
+    vec![
+        (Method::GET, "lists", vec![], Value::Null),
+        (Method::POST, "lists", vec![], json!({ "name": "Mine" })),
+        (Method::GET, "lists/{id}", vec![], Value::Null),
+        (
+            Method::PATCH,
+            "lists/{id}",
+            vec![],
+            json!({ "name": "Renamed" }),
+        ),
+        (Method::DELETE, "lists/{id}", vec![], Value::Null),
+        (
+            Method::PUT,
+            "lists/{id}/owner",
+            vec![],
+            json!({ "owner": EMINA }),
+        ),
+        (Method::POST, "lists/{id}/duplicate", vec![], json!({})),
+        (
+            Method::GET,
+            "lists/{id}/access",
+            vec![("action", r#"Action::"GetList""#)],
+            Value::Null,
+        ),
+        (
+            Method::POST,
+            "lists/{id}/tasks",
+            vec![],
+            json!({ "name": "Review" }),
+        ),
+        (
+            Method::PATCH,
+            "lists/{id}/tasks/0",
+            vec![],
+            json!({ "state": "Checked" }),
+        ),
+        (Method::DELETE, "lists/{id}/tasks/0", vec![], Value::Null),
+        (
+            Method::POST,
+            "lists/{id}/shares",
+            vec![],
+            json!({ "share_with": KESHA, "role": "Reader" }),
+        ),
+        (
+            Method::DELETE,
+            "lists/{id}/shares",
+            vec![("target", user), ("role", "Editor")],
+            Value::Null,
+        ),
+        (
+            Method::POST,
+            "lists/{id}/tasks/0/comments",
+            vec![],
+            json!({ "body": "LGTM" }),
+        ),
+        (
+            Method::PATCH,
+            "lists/{id}/tasks/0/comments/{comment}",
+            vec![],
+            json!({ "body": "LGTM!" }),
+        ),
+        (
+            Method::DELETE,
+            "lists/{id}/tasks/0/comments/{comment}",
+            vec![],
+            Value::Null,
+        ),
+        (
+            Method::POST,
+            "lists/{id}/tasks/0/attachments",
+            vec![],
+            json!({ "name": "notes.txt", "content_type": "text/plain", "data": "aGVsbG8=" }),
+        ),
+        (
+            Method::DELETE,
+            "lists/{id}/tasks/0/attachments/{attachment}",
+            vec![],
+            Value::Null,
+        ),
+    ]
+}
+
+/// The status of each of `routes` when `user` calls it on a fresh list of kesha's, which
+/// is shared with them as an editor and has a task with a comment and an attachment by kesha
+async fn route_statuses(s: &TestServer, user: &'static str) -> Vec<(String, StatusCode)> {
+    let mut statuses = Vec::new();
+    for (method, path, query, body) in routes(user) {
+        let (code, list) = status(
+            s.v2(Method::POST, "lists", KESHA)
+                .json(&json!({ "name": "Cedar blog" }))
+                .send()
+                .await,
+        )
+        .await;
+        assert_eq!(code, StatusCode::CREATED);
+        let id = list_id(list.as_str().unwrap()).to_string();
+        let fixture = |path: String, body: Value| {
+            let resp = s.v2(Method::POST, &path, KESHA).json(&body).send();
+            async move {
+                let (code, body) = status(resp.await).await;
+                assert_eq!(code, StatusCode::CREATED, "{path}: {body}");
+                body
+            }
+        };
+        fixture(format!("lists/{id}/tasks"), json!({ "name": "Draft" })).await;
+        let comment = fixture(
+            format!("lists/{id}/tasks/0/comments"),
+            json!({ "body": "First draft" }),
+        )
+        .await;
+        let attachment = fixture(
+            format!("lists/{id}/tasks/0/attachments"),
+            json!({ "name": "draft.txt", "content_type": "text/plain", "data": "aGVsbG8=" }),
+        )
+        .await;
+        fixture(
+            format!("lists/{id}/shares"),
+            json!({ "share_with": user, "role": "Editor" }),
+        )
+        .await;
+
+        let filled = path
+            .replace("{id}", &id)
+            .replace("{comment}", &comment.to_string())
+            .replace("{attachment}", &attachment.to_string());
+        let mut request = s.v2(method.clone(), &filled, user).query(&query);
+        if !body.is_null() {
+            request = request.json(&body);
+        }
+        let (code, _) = status(request.send().await).await;
+        statuses.push((format!("{method} {path}"), code));
+    }
+    statuses
+}
+
+/// Pair each of `routes` with its expected status
+fn expect(codes: [u16; 18]) -> Vec<(String, StatusCode)> {
+    routes(AARON)
+        .into_iter()
+        .zip(codes)
+        .map(|((method, path, _, _), code)| {
+            (
+                format!("{method} {path}"),
+                StatusCode::from_u16(code).unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn policy_profiles() {
+    // What an editor may do: everything but delete or transfer the list, see who may act
+    // on it, change its shares, or change what someone else wrote
+    let editor = expect([
+        200, 201, 200, 200, 403, 403, 201, 403, 201, 200, 204, 403, 403, 201, 403, 403, 201, 403,
+    ]);
+    // Policy 8 lets admins transfer any list, without a profile
+    let admin_editor = expect([
+        200, 201, 200, 200, 403, 200, 201, 403, 201, 200, 204, 403, 403, 201, 403, 403, 201, 403,
+    ]);
+    let everything = expect([
+        200, 201, 200, 200, 204, 200, 201, 200, 201, 200, 204, 201, 204, 201, 200, 204, 201, 204,
+    ]);
+    let nothing_on_lists = expect([
+        200, 201, 403, 403, 403, 403, 403, 403, 403, 403, 403, 403, 403, 403, 403, 403, 403, 403,
+    ]);
+    let no_new_lists = expect([
+        200, 403, 200, 200, 403, 403, 403, 403, 201, 200, 204, 403, 403, 201, 403, 403, 201, 403,
+    ]);
+    let start = |files, profiles: &[PolicyProfile], task_entities| {
+        let profiles = profiles.iter().copied().collect();
+        TestServer::start_with(files, move |config| {
+            config.profiles = profiles;
+            config.task_entities = task_entities;
+        })
+    };
+
+    // Without profiles, job level, location and the interns team make no difference
+    let s = start(FILES, &[], false).await;
+    assert_eq!(route_statuses(&s, AARON).await, editor);
+    assert_eq!(route_statuses(&s, EMINA).await, admin_editor);
+    assert_eq!(route_statuses(&s, ANDREW).await, admin_editor);
+    s.stop().await;
+
+    // Policy 4: admins (emina and andrew) may do anything
+    let s = start(FILES, &[PolicyProfile::Admin], false).await;
+    assert_eq!(route_statuses(&s, EMINA).await, everything);
+    assert_eq!(route_statuses(&s, ANDREW).await, everything);
+    assert_eq!(route_statuses(&s, AARON).await, editor);
+    s.stop().await;
+
+    // Policy 5: interns (aaron) may not create lists, which duplicating a list does
+    let s = start(FILES, &[PolicyProfile::Interns], false).await;
+    assert_eq!(route_statuses(&s, AARON).await, no_new_lists);
+    assert_eq!(route_statuses(&s, EMINA).await, admin_editor);
+    s.stop().await;
+
+    // Policy 6: andrew, a junior at XYZ77, may not act on lists owned at ABC17, while aaron
+    // (at ABC17) and emina (senior, at DEF33) may. The forbid overrides Policy 4.
+    let s = start(FILES, &[PolicyProfile::Location], false).await;
+    assert_eq!(route_statuses(&s, ANDREW).await, nothing_on_lists);
+    assert_eq!(route_statuses(&s, AARON).await, editor);
+    assert_eq!(route_statuses(&s, EMINA).await, admin_editor);
+    s.stop().await;
+    let s = start(
+        FILES,
+        &[PolicyProfile::Admin, PolicyProfile::Location],
+        false,
+    )
+    .await;
+    assert_eq!(route_statuses(&s, ANDREW).await, nothing_on_lists);
+    assert_eq!(route_statuses(&s, EMINA).await, everything);
+    s.stop().await;
+
+    // With task entities, Policy 6 also covers tasks, by their list's owner
+    let s = start(TASK_FILES, &[PolicyProfile::Location], true).await;
+    assert_eq!(route_statuses(&s, ANDREW).await, nothing_on_lists);
+    assert_eq!(route_statuses(&s, AARON).await, editor);
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn capabilities() {
+    let s = TestServer::start().await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let id = list_id(list.as_str().unwrap()).to_string();
+    let (code, _) = status(
+        s.v2(Method::POST, &format!("lists/{id}/shares"), KESHA)
+            .json(&json!({ "share_with": AARON, "role": "Reader" }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::CREATED);
+
+    let (code, got) = status(s.v2(Method::GET, "capabilities", AARON).send().await).await;
+    assert_eq!(code, StatusCode::OK);
+    let teams = got["teams"].as_array().unwrap();
+    assert!(teams.contains(&json!(r#"Team::"interns""#)), "{got}");
+    assert!(teams.contains(&json!(r#"Team::"temp""#)), "{got}");
+
+    // Whether aaron may read a list depends on the list, so the decision is left to the
+    // residual policies, which constrain the list with aaron substituted in
+    let get_list = &got["actions"]["GetList"];
+    assert_eq!(get_list["resource_types"], json!(["List"]));
+    assert_eq!(get_list["allowed"], Value::Null);
+    let residuals = get_list["residuals"].as_array().unwrap();
+    assert!(!residuals.is_empty());
+    assert!(residuals
+        .iter()
+        .all(|policy| policy["effect"] == "permit" || policy["effect"] == "forbid"));
+    let src = serde_json::to_string(residuals).unwrap();
+    assert!(src.contains("aaron"), "{src}");
+    // Authors may edit their own comments, which leaves a condition on `context.author`
+    let edit_comment = serde_json::to_string(&got["actions"]["EditComment"]).unwrap();
+    assert!(edit_comment.contains("author"), "{edit_comment}");
+
+    let got = ok(s
+        .v1(Method::GET, "capabilities")
+        .query(&[("uid", AARON)])
+        .send()
+        .await)
+    .await;
+    assert!(got["actions"]["DuplicateList"]["residuals"].is_array());
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn batch_authorization() {
+    let s = TestServer::start().await;
+    let list = ok(s
+        .v1(Method::POST, "list/create")
+        .json(&json!({ "uid": KESHA, "name": "Cedar blog" }))
+        .send()
+        .await)
+    .await;
+    let list = list.as_str().unwrap().to_string();
+    let task = ok(s
+        .v1(Method::POST, "task/create")
+        .json(&json!({ "uid": KESHA, "list": list, "name": "Draft" }))
+        .send()
+        .await)
+    .await;
+    let task = task.as_i64().unwrap();
+    let share = json!({ "uid": KESHA, "list": list, "share_with": AARON, "role": "Reader" });
+    ok(s.v1(Method::POST, "share").json(&share).send().await).await;
+
+    let requests = json!([
+        { "action": r#"Action::"CreateList""# },
+        { "action": r#"Action::"GetList""#, "list": list },
+        { "action": r#"Action::"UpdateList""#, "list": list },
+        { "action": r#"Action::"UpdateTask""#, "list": list, "task": task },
+        { "action": r#"Action::"EditComment""#, "list": list, "task": task, "author": AARON },
+        { "action": r#"Action::"EditComment""#, "list": list, "task": task, "author": KESHA },
+    ]);
+    for (user, expected) in [
+        (KESHA, [true, true, true, true, true, true]),
+        (AARON, [true, true, false, false, true, false]),
+        (ANDREW, [true, false, false, false, false, false]),
+    ] {
+        let (code, got) = status(
+            s.v2(Method::POST, "authorize", user)
+                .json(&json!({ "requests": requests }))
+                .send()
+                .await,
+        )
+        .await;
+        assert_eq!(code, StatusCode::OK, "{got}");
+        assert_eq!(got["allowed"], json!(expected), "{user}");
+    }
+
+    let got = ok(s
+        .v1(Method::POST, "authorize")
+        .json(&json!({ "uid": AARON, "requests": requests }))
+        .send()
+        .await)
+    .await;
+    assert_eq!(
+        got["allowed"],
+        json!([true, true, false, false, true, false])
+    );
+
+    let requests = vec![json!({ "action": r#"Action::"GetLists""# }); 101];
+    let (code, _) = status(
+        s.v2(Method::POST, "authorize", KESHA)
+            .json(&json!({ "requests": requests }))
+            .send()
+            .await,
+    )
+    .await;
+    assert_eq!(code, StatusCode::BAD_REQUEST);
+
+    s.stop().await;
+}
+
+#[tokio::test]
+async fn web_ui() {
+    let s = TestServer::start().await;
+    for (path, content_type, text) in [
+        ("/", "text/html", "<title>TinyTodo</title>"),
+        ("/app.js", "text/javascript", "/authorize"),
+        ("/style.css", "text/css", "fieldset"),
+    ] {
+        let resp = s.get(path).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK, "{path}");
+        let header = resp.headers()["content-type"].to_str().unwrap().to_string();
+        assert!(header.starts_with(content_type), "{path}: {header}");
+        assert!(resp.text().await.unwrap().contains(text), "{path}");
+    }
+    s.stop().await;
+}
+
+// A script for the command-line client: kesha creates a list, shares it and unshares it
+#[cfg(feature = "client")]
+const CLIENT_SCRIPT: &str = r#"
+# kesha owns the list, and aaron may read it
+set_user kesha
+allow create_list "Cedar blog"
+allow create_task 0 "Draft the post"
+allow share_list 0 aaron
+set_user aaron
+allow get_list 0
+deny toggle_task 0 1
+deny delete_list 0
+deny list_access 0
+
+# As an intern, aaron may edit it once interns are editors
+set_user kesha
+allow share_list 0 Team::"interns" Editor
+set_user aaron
+allow toggle_task 0 1
+allow change_task_description 0 1 "Draft the blog post"
+
+# Once the shares are removed, aaron may do nothing with it
+set_user kesha
+allow unshare_list 0 aaron
+allow unshare_list 0 Team::"interns" Editor
+set_user aaron
+deny get_list 0
+deny create_task 0 "Publish"
+deny delete_task 0 1
+deny share_list 0 aaron Editor
+deny delete_list 0
+
+set_user kesha
+allow create_list Drafts
+"#;
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn command_line_client() {
+    use tiny_todo_server::{
+        cli::{self, Line, Outcome, Session},
+        client::Client,
+    };
+
+    let s = TestServer::start().await;
+    let mut session = Session::new(Client::new(&s.url), None);
+    let script = cli::parse_script(CLIENT_SCRIPT).unwrap();
+    let mismatches = session.run_script(&script).await;
+    assert!(mismatches.is_empty(), "{mismatches:?}");
+
+    let line: Line = "get_list 0".parse().unwrap();
+    let (shown, outcome) = session.run(&line).await;
+    assert_eq!(outcome, Outcome::Allowed);
+    assert!(shown.contains("1: [X] Draft the blog post"), "{shown}");
+    let (shown, _) = session.run(&"get_lists".parse().unwrap()).await;
+    assert_eq!(shown, "Lists: Cedar blog,Drafts");
+
+    let script = cli::parse_script("set_user andrew\nallow get_list 0").unwrap();
+    let mismatches = session.run_script(&script).await;
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!(mismatches[0].line, 2);
+    assert_eq!(mismatches[0].expected, Outcome::Allowed);
+    assert_eq!(mismatches[0].got, Outcome::Denied);
+
+    assert!(cli::parse_script("create_list").is_err());
+    assert!(cli::parse_script("toggle_task 0 0").is_err());
+    assert!(cli::parse_script("create_list \"Cedar blog").is_err());
+    s.stop().await;
+}
+
+// Run a script with the `tinytodo` binary
+#[cfg(feature = "client")]
+async fn run_client(url: &str, dir: &Path, script: &str) -> std::process::Output {
+    let path = dir.join("script.txt");
+    std::fs::write(&path, script).unwrap();
+    tokio::process::Command::new(env!("CARGO_BIN_EXE_tinytodo"))
+        .arg("--url")
+        .arg(url)
+        .arg("--script")
+        .arg(path)
+        .output()
+        .await
+        .unwrap()
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn command_line_client_exit_status() {
+    let s = TestServer::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let output = run_client(&s.url, dir.path(), CLIENT_SCRIPT).await;
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Created list ID 0"), "{stdout}");
+    assert!(stdout.contains("Access denied. User aaron"), "{stdout}");
+
+    let output = run_client(&s.url, dir.path(), "set_user emina\nallow delete_list 0").await;
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Line 2: expected `delete_list 0`"),
+        "{stderr}"
+    );
+
+    let output = run_client(&s.url, dir.path(), "set_user").await;
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    s.stop().await;
+}
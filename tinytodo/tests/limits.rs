@@ -0,0 +1,109 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Tests of the token buckets behind the rate limits. `tests/e2e.rs` tests the limits and
+//! quotas on running servers.
+
+use std::{collections::HashMap, thread::sleep, time::Duration};
+
+use tiny_todo_server::{
+    limits::{Bucket, LimitsConfig, PrincipalLimits, RateLimiter, PRUNE_THRESHOLD},
+    util::EntityUid,
+};
+
+// A bucket that, for the length of a test, never refills
+const SLOW: Bucket = Bucket {
+    burst: 1,
+    per_second: 1e-6,
+};
+
+// A bucket that refills within a microsecond
+const FAST: Bucket = Bucket {
+    burst: 1,
+    per_second: 1e6,
+};
+
+fn uid(s: &str) -> EntityUid {
+    s.parse().unwrap()
+}
+
+#[test]
+fn token_buckets() {
+    let mut limiter = RateLimiter::default();
+    let burst = Bucket {
+        burst: 2,
+        per_second: 1e-6,
+    };
+    assert!(limiter.check("a", &burst));
+    assert!(limiter.check("a", &burst));
+    assert!(!limiter.check("a", &burst));
+    // Each key has its own bucket
+    assert!(limiter.check("b", &burst));
+
+    let refilling = Bucket {
+        burst: 1,
+        per_second: 20.0,
+    };
+    assert!(limiter.check("c", &refilling));
+    assert!(!limiter.check("c", &refilling));
+    sleep(Duration::from_millis(100));
+    assert!(limiter.check("c", &refilling));
+}
+
+#[test]
+fn full_buckets_are_pruned() {
+    let mut limiter = RateLimiter::default();
+    assert!(limiter.check(u32::MAX, &SLOW));
+    for key in 1..PRUNE_THRESHOLD as u32 {
+        assert!(limiter.check(key, &FAST));
+    }
+    assert_eq!(limiter.len(), PRUNE_THRESHOLD);
+    sleep(Duration::from_millis(10));
+
+    // The next check drops the buckets that have refilled, but not the empty one, so the
+    // limit is not lifted by making requests under many other keys
+    assert!(limiter.check(0, &FAST));
+    assert_eq!(limiter.len(), 2);
+    assert!(!limiter.check(u32::MAX, &SLOW));
+}
+
+#[test]
+fn principal_limits() {
+    let config = LimitsConfig {
+        actions: HashMap::from([("CreateTask".to_string(), SLOW)]),
+        ..LimitsConfig::default()
+    };
+    let mut limits = PrincipalLimits::new(&config).unwrap();
+    let (kesha, aaron) = (uid(r#"User::"kesha""#), uid(r#"User::"aaron""#));
+    let (create, get) = (uid(r#"Action::"CreateTask""#), uid(r#"Action::"GetList""#));
+    assert!(limits.check(&kesha, &create));
+    assert!(!limits.check(&kesha, &create));
+    // Each principal has its own bucket, and actions without a limit are not limited
+    assert!(limits.check(&aaron, &create));
+    for _ in 0..10 {
+        assert!(limits.check(&kesha, &get));
+    }
+
+    // `default_action` applies to every action not in `actions`, each with its own bucket
+    let config = LimitsConfig {
+        default_action: Some(SLOW),
+        ..config
+    };
+    let mut limits = PrincipalLimits::new(&config).unwrap();
+    assert!(limits.check(&kesha, &get));
+    assert!(!limits.check(&kesha, &get));
+    assert!(limits.check(&kesha, &uid(r#"Action::"GetLists""#)));
+}
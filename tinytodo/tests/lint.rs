@@ -0,0 +1,95 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Tests of where the linter finds each policy in a policies file. `tests/e2e.rs` tests
+//! the warnings themselves, on running servers.
+
+use std::path::Path;
+
+use cedar_policy::{PolicyId, PolicySet};
+use tiny_todo_server::lint::{Location, Locations, Position};
+
+/// The locations of the policies in `src`, which must parse
+fn locations(src: &str) -> Locations {
+    let policies: PolicySet = src.parse().unwrap();
+    Locations::new(Path::new("policies.cedar"), src, &policies)
+}
+
+/// The start and end of the policy `id`, as (line, column) pairs
+fn span(locations: &Locations, id: &str) -> ((usize, usize), (usize, usize)) {
+    let Location { start, end, .. } = locations
+        .get(&PolicyId::new(id))
+        .unwrap_or_else(|| panic!("no location for {id}"))
+        .clone();
+    let pair = |Position { line, column }| (line, column);
+    (pair(start), pair(end))
+}
+
+#[test]
+fn comments_and_strings() {
+    // Neither `;` nor `//` ends a policy or starts a comment inside a string, and neither
+    // does anything in a comment
+    let src = r#"// A comment; with "a string" and a ; in it
+@id("first")
+permit (principal, action == Action::"GetList", resource)
+when { resource.name like "a;b//c*" };   // trailing ; comment
+permit (principal == ?principal, action == Action::"GetList", resource == ?resource);
+@id("third") forbid (principal, action, resource) when { resource.name == "\"quoted;\"" };
+"#;
+    let locations = locations(src);
+    assert_eq!(span(&locations, "first"), ((2, 1), (4, 38)));
+    // The template has no `@id`, so keeps the id Cedar gives the second statement
+    assert_eq!(span(&locations, "policy1"), ((5, 1), (5, 85)));
+    assert_eq!(span(&locations, "third"), ((6, 1), (6, 90)));
+}
+
+#[test]
+fn shipped_policies() {
+    // Every policy and template in the shipped files, which mix them, is found where it is
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    for file in [
+        "policies.cedar",
+        "policies-tasks.cedar",
+        "policies-templates.cedar",
+        "policies-templates-tasks.cedar",
+    ] {
+        let src = std::fs::read_to_string(manifest.join(file)).unwrap();
+        let policies: PolicySet = src.parse().unwrap();
+        let locations = Locations::new(Path::new(file), &src, &policies);
+        let lines: Vec<&str> = src.lines().collect();
+        let at = |Position { line, column }| -> String {
+            lines[line - 1].chars().skip(column - 1).collect()
+        };
+        let ids = policies
+            .policies()
+            .map(|p| (p.id(), p.annotation("id")))
+            .chain(policies.templates().map(|t| (t.id(), t.annotation("id"))));
+        for (raw, annotation) in ids {
+            let id = annotation.map_or(raw.clone(), PolicyId::new);
+            let location = locations
+                .get(&id)
+                .unwrap_or_else(|| panic!("{file}: no location for {id}"));
+            let start = at(location.start);
+            assert!(
+                ["@", "permit", "forbid"]
+                    .iter()
+                    .any(|s| start.starts_with(s)),
+                "{file}: {id} starts at {start}"
+            );
+            assert!(at(location.end).starts_with(';'), "{file}: {id}");
+        }
+    }
+}
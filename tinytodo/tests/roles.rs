@@ -0,0 +1,176 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Tests of configured share roles, which need no running server. `tests/e2e.rs` tests
+//! sharing lists in them.
+
+use std::collections::BTreeMap;
+
+use tiny_todo_server::{
+    api::ShareRole,
+    config::SharingMode,
+    roles::{self, RoleConfig, Roles, RolesError},
+};
+
+/// A role permitting `actions`, as configured with teams
+fn actions(actions: &[&str]) -> RoleConfig {
+    RoleConfig {
+        actions: actions.iter().map(|a| a.to_string()).collect(),
+        template: None,
+    }
+}
+
+/// A role linking `template`, as configured with templates
+fn template(template: &str) -> RoleConfig {
+    RoleConfig {
+        actions: Vec::new(),
+        template: Some(template.to_string()),
+    }
+}
+
+fn configured(roles: &[(&str, RoleConfig)]) -> BTreeMap<ShareRole, RoleConfig> {
+    roles
+        .iter()
+        .map(|(name, config)| (ShareRole::new(*name), config.clone()))
+        .collect()
+}
+
+#[test]
+fn validation() {
+    use SharingMode::{Teams, Templates};
+    let viewer = || actions(&["GetList"]);
+    roles::validate(&configured(&[("Viewer", viewer())]), Teams).unwrap();
+    roles::validate(&configured(&[("Viewer", template("viewer"))]), Templates).unwrap();
+
+    let bad: [(&[(&str, RoleConfig)], SharingMode, fn(&RolesError) -> bool); 9] = [
+        (&[("reader", viewer())], Teams, |e| {
+            matches!(e, RolesError::BuiltIn(_))
+        }),
+        (&[("1st", viewer())], Teams, |e| {
+            matches!(e, RolesError::Name(_))
+        }),
+        (&[("Read-only", viewer())], Teams, |e| {
+            matches!(e, RolesError::Name(_))
+        }),
+        (&[("Viewer", viewer()), ("viewer", viewer())], Teams, |e| {
+            matches!(e, RolesError::Duplicate(..))
+        }),
+        (&[("Viewer", actions(&[]))], Teams, |e| {
+            matches!(e, RolesError::Actions(_))
+        }),
+        (
+            &[("Viewer", actions(&["Get\"List"]))],
+            Teams,
+            |e| matches!(e, RolesError::Action(_, a) if a == "Get\"List"),
+        ),
+        (&[("Viewer", template("viewer"))], Teams, |e| {
+            matches!(e, RolesError::Actions(_))
+        }),
+        (&[("Viewer", actions(&[]))], Templates, |e| {
+            matches!(e, RolesError::Template(_))
+        }),
+        (
+            &[(
+                "Viewer",
+                RoleConfig {
+                    template: Some("viewer".to_string()),
+                    ..viewer()
+                },
+            )],
+            Templates,
+            |e| {
+                matches!(
+                    e,
+                    RolesError::Field {
+                        field: "actions",
+                        ..
+                    }
+                )
+            },
+        ),
+    ];
+    for (roles, sharing, expected) in bad {
+        match roles::validate(&configured(roles), sharing) {
+            Err(e) if expected(&e) => (),
+            r => panic!("{roles:?} with {sharing:?}: unexpected {r:?}"),
+        }
+    }
+}
+
+#[cfg(not(feature = "use-templates"))]
+#[test]
+fn team_role_policies() {
+    use std::path::Path;
+
+    use cedar_policy::{PolicySet, Schema, ValidationMode, Validator};
+    use tiny_todo_server::roles::ROLE_POLICY;
+
+    let roles = Roles::new(&configured(&[
+        ("Commenter", actions(&["GetList", "CommentOnTask"])),
+        ("Viewer", actions(&["GetList"])),
+    ]));
+    for (task_entities, schema) in [
+        (false, "tinytodo.cedarschema"),
+        (true, "tinytodo-tasks.cedarschema"),
+    ] {
+        let policies = roles.policies(task_entities).unwrap();
+        // Only the role that can comment gets a policy for authors
+        let ids: Vec<_> = policies.iter().map(|p| p.id().to_string()).collect();
+        assert_eq!(
+            ids,
+            ["role-Commenter", "role-Commenter-authors", "role-Viewer"]
+        );
+        for policy in &policies {
+            let role = policy.annotation(ROLE_POLICY).unwrap();
+            assert!(policy.id().to_string().starts_with(&format!("role-{role}")));
+            assert_eq!(
+                policy.to_string().contains("resource.list"),
+                task_entities,
+                "{policy}"
+            );
+        }
+
+        // The policies are checked against the schema when the server adds them
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(schema);
+        let (schema, _) =
+            Schema::from_cedarschema_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let policies = PolicySet::from_policies(policies).unwrap();
+        let output = Validator::new(schema).validate(&policies, ValidationMode::default());
+        let errors: Vec<_> = output.validation_errors().map(|e| e.to_string()).collect();
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+    assert!(roles.templates().is_empty());
+}
+
+#[cfg(feature = "use-templates")]
+#[test]
+fn template_roles() {
+    use cedar_policy::PolicyId;
+
+    let roles = Roles::new(&configured(&[("Commenter", template("commenter"))]));
+    let commenter = ShareRole::new("Commenter");
+    assert!(roles.contains(&ShareRole::reader()) && roles.contains(&commenter));
+    assert!(!roles.contains(&ShareRole::new("Viewer")));
+    assert_eq!(
+        roles.templates(),
+        ["reader-template", "editor-template", "commenter"].map(PolicyId::new)
+    );
+    assert_eq!(
+        roles.role_of_template(&PolicyId::new("commenter")),
+        Some(commenter)
+    );
+    assert_eq!(roles.role_of_template(&PolicyId::new("viewer")), None);
+}
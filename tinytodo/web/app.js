@@ -0,0 +1,282 @@
+/*
+ * Copyright 2022-2023 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// The TinyTodo web front-end. It acts as the logged-in user through the v2 API, and asks
+// `/api/v2/authorize` which of its controls the policies let that user use. Every control
+// is disabled until the server says otherwise.
+
+"use strict";
+
+// The most requests the server accepts in one batch
+const MAX_BATCH = 100;
+
+const state = { user: null, list: null };
+
+const $ = (id) => document.getElementById(id);
+
+function element(tag, attrs = {}, ...children) {
+  const el = document.createElement(tag);
+  for (const [name, value] of Object.entries(attrs)) {
+    if (name.startsWith("on")) {
+      el.addEventListener(name.slice(2), value);
+    } else if (name.startsWith("data-")) {
+      el.dataset[name.slice(5)] = value;
+    } else {
+      el[name] = value;
+    }
+  }
+  el.append(...children);
+  return el;
+}
+
+// `kesha` or `User::"kesha"`
+function userUid(name) {
+  return name.includes("::") ? name : `User::"${name}"`;
+}
+
+// The id of an entity uid, e.g. `0` for `List::"0"`
+function entityId(uid) {
+  const match = uid.match(/::"(.*)"$/);
+  return match ? match[1] : uid;
+}
+
+async function api(method, path, body) {
+  const response = await fetch(`/api/v2${path}`, {
+    method,
+    headers: { "x-tinytodo-user": state.user, "content-type": "application/json" },
+    body: body === undefined ? undefined : JSON.stringify(body),
+  });
+  if (response.status === 204) {
+    return null;
+  }
+  const json = await response.json();
+  if (!response.ok) {
+    throw new Error(json.error || response.statusText);
+  }
+  return json;
+}
+
+// Report the outcome of `action`, if any, then show the current state
+async function run(action = async () => {}) {
+  $("error").textContent = "";
+  try {
+    await action();
+  } catch (e) {
+    $("error").textContent = e.message;
+  }
+  try {
+    await refresh();
+  } catch (e) {
+    $("error").textContent ||= e.message;
+  }
+}
+
+// Enable the controls the user may use. A control names its action in `data-action`, and
+// the list, task and author it acts on, if any, in `data-list`, `data-task` and
+// `data-author`; with no list, the action is on the application. Hidden controls stay
+// disabled.
+async function applyPermissions() {
+  const controls = [...document.querySelectorAll("[data-action]")].filter(
+    (el) => !el.closest("[hidden]")
+  );
+  const requests = controls.map((el) => {
+    const request = { action: `Action::"${el.dataset.action}"` };
+    if (el.dataset.list) request.list = el.dataset.list;
+    if (el.dataset.task) request.task = Number(el.dataset.task);
+    if (el.dataset.author) request.author = el.dataset.author;
+    return request;
+  });
+  const allowed = [];
+  for (let i = 0; i < requests.length; i += MAX_BATCH) {
+    const batch = { requests: requests.slice(i, i + MAX_BATCH) };
+    allowed.push(...(await api("POST", "/authorize", batch)).allowed);
+  }
+  controls.forEach((el, i) => {
+    el.disabled = !allowed[i];
+    el.title = allowed[i] ? "" : `Denied: ${el.dataset.action}`;
+  });
+}
+
+async function refresh() {
+  const lists = await api("GET", "/lists");
+  $("lists").replaceChildren(
+    ...lists.map((list) =>
+      element(
+        "li",
+        {},
+        element("button", { onclick: () => openList(list.uid) }, list.name),
+        element("span", { className: "muted" }, ` ${entityId(list.owner)}`)
+      )
+    )
+  );
+  if (state.list) {
+    // The list may have been deleted, or the user may no longer read it
+    try {
+      renderList(await api("GET", `/lists/${entityId(state.list)}`));
+    } catch (e) {
+      state.list = null;
+      $("list").hidden = true;
+      throw e;
+    }
+  }
+  await applyPermissions();
+  await showAccess();
+}
+
+function openList(uid) {
+  state.list = uid;
+  run();
+}
+
+function renderList(list) {
+  const uid = list.uid;
+  const path = `/lists/${entityId(uid)}`;
+  $("list").hidden = false;
+  $("list-name").textContent = list.name;
+  $("list-owner").textContent = `Owned by ${entityId(list.owner)}`;
+  for (const id of ["rename-fields", "delete-list", "new-task-fields", "share-fields"]) {
+    $(id).dataset.list = uid;
+    $(id).disabled = true;
+  }
+  $("tasks").replaceChildren(...list.tasks.map((task) => renderTask(uid, path, task)));
+}
+
+function renderTask(uid, path, task) {
+  const taskPath = `${path}/tasks/${task.id}`;
+  const checked = task.state === "Checked";
+  const controls = { "data-list": uid, "data-task": task.id, disabled: true };
+  const comments = task.comments.map((comment) =>
+    element(
+      "li",
+      {},
+      `${entityId(comment.author)}: ${comment.body} `,
+      element(
+        "button",
+        {
+          ...controls,
+          "data-action": "DeleteComment",
+          "data-author": comment.author,
+          onclick: () => run(() => api("DELETE", `${taskPath}/comments/${comment.id}`)),
+        },
+        "Delete"
+      )
+    )
+  );
+  const commentForm = element(
+    "form",
+    {
+      onsubmit: (event) => {
+        event.preventDefault();
+        const body = event.target.elements.body.value;
+        run(() => api("POST", `${taskPath}/comments`, { body }));
+      },
+    },
+    element(
+      "fieldset",
+      { ...controls, "data-action": "CommentOnTask" },
+      element("input", { name: "body", placeholder: "Comment", required: true }),
+      element("button", {}, "Comment")
+    )
+  );
+  return element(
+    "li",
+    {},
+    element("input", {
+      ...controls,
+      "data-action": "UpdateTask",
+      type: "checkbox",
+      checked,
+      onchange: () =>
+        run(() => api("PATCH", taskPath, { state: checked ? "Unchecked" : "Checked" })),
+    }),
+    element("span", { className: checked ? "checked" : "" }, ` ${task.name} `),
+    element(
+      "button",
+      {
+        ...controls,
+        "data-action": "DeleteTask",
+        onclick: () => run(() => api("DELETE", taskPath)),
+      },
+      "Delete"
+    ),
+    element("ul", {}, ...comments),
+    commentForm
+  );
+}
+
+// Who may read the list, which only users who may edit its shares can see
+async function showAccess() {
+  if (!state.list || $("share-fields").disabled) {
+    $("access").replaceChildren();
+    return;
+  }
+  const action = encodeURIComponent('Action::"GetList"');
+  const access = await api("GET", `/lists/${entityId(state.list)}/access?action=${action}`);
+  $("access").replaceChildren(
+    ...[...access.users, ...access.teams].map((uid) => element("li", {}, uid))
+  );
+}
+
+function onSubmit(id, handler) {
+  $(id).addEventListener("submit", (event) => {
+    event.preventDefault();
+    run(() => handler(event.target.elements, event.submitter));
+  });
+}
+
+onSubmit("login", async (fields) => {
+  state.user = userUid(fields.user.value.trim());
+  state.list = null;
+  $("list").hidden = true;
+  sessionStorage.setItem("user", state.user);
+  $("whoami").textContent = `Logged in as ${state.user}`;
+  document.querySelector("main").hidden = false;
+});
+
+onSubmit("new-list", (fields) => api("POST", "/lists", { name: fields.name.value }));
+
+onSubmit("rename-list", (fields) =>
+  api("PATCH", `/lists/${entityId(state.list)}`, { name: fields.name.value })
+);
+
+onSubmit("new-task", (fields) =>
+  api("POST", `/lists/${entityId(state.list)}/tasks`, { name: fields.name.value })
+);
+
+onSubmit("share", (fields, submitter) => {
+  const path = `/lists/${entityId(state.list)}/shares`;
+  const target = fields.target.value.trim();
+  const role = fields.role.value.trim();
+  if (submitter.name === "remove") {
+    const query = new URLSearchParams({ target, role });
+    return api("DELETE", `${path}?${query}`);
+  }
+  return api("POST", path, { share_with: target, role });
+});
+
+$("delete-list").addEventListener("click", () =>
+  run(async () => {
+    await api("DELETE", `/lists/${entityId(state.list)}`);
+    state.list = null;
+    $("list").hidden = true;
+  })
+);
+
+const saved = sessionStorage.getItem("user");
+if (saved) {
+  $("user").value = saved;
+  $("login").requestSubmit();
+}